@@ -0,0 +1,287 @@
+//! Turns readings from the watch into cracks.
+//!
+//! Readings go through a chain of stages set in `accel_stages` in the settings. A reading starts out as the
+//! `x y z` the watch sent, and a crack goes off whenever one makes it through every stage. The crack value is the
+//! size of what comes out the end. Stages that need a single number use the magnitude of the reading if it
+//! hasn't been through `magnitude` yet. The default chain is
+//!
+//! ```json
+//! "accel_stages": [
+//!     { "stage": "magnitude" },
+//!     { "stage": "moving_average", "size": 4 },
+//!     { "stage": "threshold", "threshold": 500 },
+//!     { "stage": "refractory", "secs": 2 }
+//! ]
+//! ```
+use std::collections::VecDeque;
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::replay::{Input, LoggedInput};
+
+/// One step of the chain. Stages after a trigger (`threshold`, `peak` or `hysteresis`) only see the readings
+/// that set it off
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum Stage {
+    /// length of the reading
+    Magnitude,
+    /// average of the last `size` readings. Starts out full of zeros
+    MovingAverage { size: usize },
+    /// take out anything changing slower than `cutoff` Hz, like gravity. Works on each axis separately
+    HighPass { cutoff: f32 },
+    Gain { gain: f32 },
+    /// let through readings over `threshold`
+    Threshold { threshold: f32 },
+    /// let through the top of each peak over `threshold`, one reading after it
+    Peak { threshold: f32 },
+    /// let through the first reading over `on`, then nothing until the readings have dropped below `off`
+    Hysteresis { on: f32, off: f32 },
+    /// let through nothing for `secs` seconds after the last reading that got through, or after the show starts
+    Refractory { secs: f32 },
+}
+
+impl Stage {
+    /// true for the stages that can hold readings back, rather than just changing them
+    pub fn is_trigger(&self) -> bool {
+        matches!(self, Self::Threshold { .. } | Self::Peak { .. } | Self::Hysteresis { .. } | Self::Refractory { .. })
+    }
+}
+
+pub fn default_stages() -> Vec<Stage> {
+    vec![
+        Stage::Magnitude,
+        Stage::MovingAverage { size: 4 },
+        Stage::Threshold { threshold: 500.0 },
+        Stage::Refractory { secs: 2.0 },
+    ]
+}
+
+fn magnitude(v: &[f32]) -> f32 {
+    v.iter().map(|a| a * a).sum::<f32>().sqrt()
+}
+
+/// A stage along with what it remembers about earlier readings
+enum StageState {
+    Magnitude,
+    MovingAverage { size: usize, buf: VecDeque<Vec<f32>> },
+    HighPass { rc: f64, last: Option<(f64, Vec<f32>, Vec<f32>)> },
+    Gain(f32),
+    Threshold(f32),
+    Peak { threshold: f32, prev: f32, rising: bool },
+    Hysteresis { on: f32, off: f32, armed: bool },
+    Refractory { secs: f64, last: f64 },
+}
+
+impl StageState {
+    fn new(stage: &Stage) -> Result<Self, Box<dyn Error>> {
+        Ok(match *stage {
+            Stage::Magnitude => Self::Magnitude,
+            Stage::MovingAverage { size } => {
+                if size == 0 {
+                    return Err("moving_average needs a size of at least 1".into());
+                }
+                Self::MovingAverage { size, buf: VecDeque::new() }
+            }
+            Stage::HighPass { cutoff } => {
+                if cutoff <= 0_f32 {
+                    return Err(format!("high_pass needs a cutoff over 0, got {}", cutoff).into());
+                }
+                Self::HighPass { rc: 1_f64 / (2_f64 * std::f64::consts::PI * cutoff as f64), last: None }
+            }
+            Stage::Gain { gain } => Self::Gain(gain),
+            Stage::Threshold { threshold } => Self::Threshold(threshold),
+            Stage::Peak { threshold } => Self::Peak { threshold, prev: 0_f32, rising: false },
+            Stage::Hysteresis { on, off } => {
+                if off > on {
+                    return Err(format!("hysteresis needs off <= on, got on {} and off {}", on, off).into());
+                }
+                Self::Hysteresis { on, off, armed: true }
+            }
+            Stage::Refractory { secs } => Self::Refractory { secs: secs as f64, last: 0_f64 },
+        })
+    }
+
+    /// Returns what gets through to the next stage
+    fn push(&mut self, t: f64, v: Vec<f32>) -> Option<Vec<f32>> {
+        match self {
+            Self::Magnitude => Some(vec![magnitude(&v)]),
+            Self::MovingAverage { size, buf } => {
+                if buf.is_empty() {
+                    buf.extend(std::iter::repeat(vec![0_f32; v.len()]).take(*size));
+                }
+                buf.pop_front();
+                buf.push_back(v);
+                let mut avg = vec![0_f32; buf[0].len()];
+                for r in buf.iter() {
+                    for (a, x) in avg.iter_mut().zip(r) {
+                        *a += x / *size as f32;
+                    }
+                }
+                Some(avg)
+            }
+            Self::HighPass { rc, last } => {
+                let out = match last.as_ref() {
+                    Some((last_t, last_in, last_out)) => {
+                        let dt = (t - last_t).max(0_f64);
+                        let alpha = (*rc / (*rc + dt)) as f32;
+                        v.iter().zip(last_in).zip(last_out)
+                            .map(|((x, lx), ly)| alpha * (ly + x - lx))
+                            .collect()
+                    }
+                    None => vec![0_f32; v.len()],
+                };
+                *last = Some((t, v, out.clone()));
+                Some(out)
+            }
+            Self::Gain(g) => Some(v.iter().map(|x| x * *g).collect()),
+            Self::Threshold(threshold) => (magnitude(&v) > *threshold).then_some(v),
+            Self::Peak { threshold, prev, rising } => {
+                let s = magnitude(&v);
+                let peak = (s < *prev && *rising && *prev > *threshold).then(|| vec![*prev]);
+                if s != *prev {
+                    *rising = s > *prev;
+                }
+                *prev = s;
+                peak
+            }
+            Self::Hysteresis { on, off, armed } => {
+                let s = magnitude(&v);
+                if *armed && s > *on {
+                    *armed = false;
+                    Some(v)
+                } else {
+                    if s < *off {
+                        *armed = true;
+                    }
+                    None
+                }
+            }
+            Self::Refractory { secs, last } => {
+                if t - *last > *secs {
+                    *last = t;
+                    Some(v)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// The chain of stages readings go through
+pub struct AccelFilter {
+    config: Vec<Stage>,
+    stages: Vec<StageState>,
+}
+
+impl AccelFilter {
+    pub fn new(stages: &[Stage]) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            config: stages.to_vec(),
+            stages: stages.iter().map(StageState::new).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The stages the filter was built from
+    pub fn stages(&self) -> &[Stage] {
+        &self.config
+    }
+
+    /// Returns the crack value if a reading `t` seconds into the show sets off a crack
+    pub fn push(&mut self, t: f64, accel: [f32; 3]) -> Option<f32> {
+        let mut v = accel.to_vec();
+        for s in self.stages.iter_mut() {
+            v = s.push(t, v)?;
+        }
+        Some(magnitude(&v))
+    }
+
+    /// Run the readings in a logged show through the chain. Returns when each crack would have gone off and its value
+    pub fn run_log(&mut self, inputs: &[LoggedInput]) -> Vec<(f64, f32)> {
+        inputs.iter()
+            .filter_map(|i| match i.input {
+                Input::Accel(a) => self.push(i.t, a).map(|v| (i.t, v)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Default for AccelFilter {
+    fn default() -> Self {
+        Self::new(&default_stages()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_chain() {
+        let mut f = AccelFilter::default();
+        let hard = [600.0, 0.0, 800.0];
+        // one hard reading isn't enough to get the moving average over 500
+        assert_eq!(f.push(3.0, hard), None);
+        assert_eq!(f.push(3.1, hard), None);
+        assert_eq!(f.push(3.2, hard), Some(750.0));
+        assert_eq!(f.push(4.0, hard), None);
+        assert_eq!(f.push(5.3, hard), Some(1000.0));
+        // nothing in the first 2 seconds of the show
+        let mut f = AccelFilter::default();
+        for i in 0..10 {
+            assert_eq!(f.push(i as f64 * 0.1, hard), None);
+        }
+    }
+
+    #[test]
+    fn test_stages() {
+        let one = |stage: Stage, readings: &[f32]| {
+            let mut f = AccelFilter::new(&[Stage::Magnitude, stage]).unwrap();
+            readings.iter().enumerate()
+                .map(|(i, r)| f.push(i as f64, [*r, 0.0, 0.0]))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(one(Stage::Gain { gain: 2.0 }, &[1.0, -3.0]), vec![Some(2.0), Some(6.0)]);
+        assert_eq!(one(Stage::Peak { threshold: 5.0 }, &[1.0, 6.0, 8.0, 8.0, 7.0, 4.0, 3.0, 4.0, 2.0]),
+            vec![None, None, None, None, Some(8.0), None, None, None, None]);
+        assert_eq!(one(Stage::Hysteresis { on: 5.0, off: 2.0 }, &[6.0, 7.0, 3.0, 6.0, 1.0, 6.0]),
+            vec![Some(6.0), None, None, None, None, Some(6.0)]);
+        assert_eq!(one(Stage::Refractory { secs: 1.5 }, &[1.0, 1.0, 1.0, 1.0, 1.0]),
+            vec![None, None, Some(1.0), None, Some(1.0)]);
+
+        // gravity on z goes away, a shake on x doesn't
+        let mut f = AccelFilter::new(&[Stage::HighPass { cutoff: 0.5 }]).unwrap();
+        let mut last = None;
+        for i in 0..200 {
+            last = f.push(i as f64 * 0.02, [0.0, 0.0, 9.8]);
+        }
+        assert!(last.unwrap() < 0.01);
+        assert!(f.push(4.0, [5.0, 0.0, 9.8]).unwrap() > 4.5);
+
+        assert!(AccelFilter::new(&[Stage::MovingAverage { size: 0 }]).is_err());
+        assert!(AccelFilter::new(&[Stage::Hysteresis { on: 1.0, off: 2.0 }]).is_err());
+    }
+
+    #[test]
+    fn test_run_log() {
+        let stages: Vec<Stage> = serde_json::from_str(r#"[
+            { "stage": "high_pass", "cutoff": 0.5 },
+            { "stage": "magnitude" },
+            { "stage": "gain", "gain": 100 },
+            { "stage": "hysteresis", "on": 500, "off": 100 }
+        ]"#).unwrap();
+        // the watch lying still, then shaken twice
+        let inputs: Vec<LoggedInput> = (0..300)
+            .map(|i| {
+                let shake = if (100..105).contains(&i) || (200..205).contains(&i) { 10.0 } else { 0.0 };
+                LoggedInput { t: i as f64 * 0.02, frame: i, input: Input::Accel([shake, 0.0, 9.8]) }
+            })
+            .collect();
+        let cracks = AccelFilter::new(&stages).unwrap().run_log(&inputs);
+        assert_eq!(cracks.iter().map(|c| c.0).collect::<Vec<_>>(), vec![2.0, 4.0]);
+        assert!(cracks.iter().all(|c| c.1 > 900.0));
+    }
+}
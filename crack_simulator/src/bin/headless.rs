@@ -0,0 +1,235 @@
+//! Runs the simulation without a window, for exploring parameters offline.
+//!
+//! Usage: `headless <script.json> [<script.json> ...]`
+//!
+//! Each script builds a graph, applies its impacts, steps the simulation and writes the outputs it asks for.
+//! With `output.frames` every step is rendered to a numbered png, one step per frame at 60 frames a second,
+//! so a script with a `seed` can be turned into the same video every time.
+//! Positions are in pixels of a `width` x `height` screen with the origin in the top left, the same as masks
+//! and scratch files.
+//!
+//! ```json
+//! {
+//!     "width": 640, "height": 360, "steps": 600,
+//!     "params": { "crack_threshold": 2.0 },
+//!     "impacts": [{ "step": 0, "position": [320, 180], "amount": 80 }, { "step": 120, "amount": 40 }],
+//!     "seed": 7, "stop_step": 300,
+//!     "output": { "stats": "stats.json", "png": "cracks.png", "render": "render.png", "svg": "cracks.svg", "snapshot": "graph.snap",
+//!                 "frames": "frames/" }
+//! }
+//! ```
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crack_simulator::graphics::{bloom_mix, crack_color_at, FADE_FRAMES};
+use crack_simulator::graphics::recorder::FrameRecorder;
+use crack_simulator::graphics::export::{write_png, write_svg};
+use crack_simulator::graphics::software::{composite, write_rgba_png, CompositeLayer, SoftwareLayer};
+use crack_simulator::graphics::vertex::Vertex;
+use crack_simulator::simulation::graph::{BoundaryMode, Graph, GraphParams};
+use crack_simulator::simulation::mask::Mask;
+use crack_simulator::simulation::scratches::load_scratches;
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Script {
+    width: u32,
+    height: u32,
+    boundary: BoundaryMode,
+    /// a `snapshot` keeps the params it was saved with unless these are given
+    params: Option<GraphParams>,
+    mask: Option<String>,
+    scratches: Option<String>,
+    /// start from a saved graph instead of building a new one. `width`, `height` and `boundary` must match the
+    /// saved graph. `mask` and `scratches` are applied on top of it
+    snapshot: Option<String>,
+    /// number of times to step the simulation
+    steps: usize,
+    impacts: Vec<Impact>,
+    /// makes runs of the same script come out the same, including ones starting from a `snapshot`
+    seed: Option<u64>,
+    /// color of the cracks in `output.render` and `output.frames`. Changes over time like the show if left out
+    crack_color: Option<[f32; 4]>,
+    /// step to start fading out at, like the show does when it's stopped
+    stop_step: Option<usize>,
+    output: Output,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            boundary: BoundaryMode::default(),
+            params: None,
+            mask: None,
+            scratches: None,
+            snapshot: None,
+            steps: 1000,
+            impacts: Vec::new(),
+            seed: None,
+            crack_color: None,
+            stop_step: None,
+            output: Output::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Impact {
+    /// step to apply the impact before
+    step: usize,
+    /// where to hit the ice. Random if left out
+    #[serde(default)]
+    position: Option<[f32; 2]>,
+    amount: f32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Output {
+    /// json crack statistics
+    stats: Option<String>,
+    /// plain white cracks on black
+    png: Option<String>,
+    /// the cracks the way the show draws them, with bloom and color
+    render: Option<String>,
+    svg: Option<String>,
+    snapshot: Option<String>,
+    /// directory to write every step to as `frame_000000.png`, `frame_000001.png`, ...
+    frames: Option<String>,
+}
+
+fn run(script_path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut contents = String::new();
+    File::open(script_path)?.read_to_string(&mut contents)?;
+    let mut script: Script = serde_json::from_str(&contents)?;
+    let (width, height) = (script.width, script.height);
+
+    let t = std::time::Instant::now();
+    // only draw triangles if something is going to look at them
+    let draw = script.output.render.is_some() || script.output.frames.is_some();
+    let mut renderer = draw.then(|| SoftwareLayer::new(width, height));
+    let mut recorder = match &script.output.frames {
+        Some(dir) => Some(FrameRecorder::new(Path::new(dir))?),
+        None => None,
+    };
+    let crack_color = |step: usize| script.crack_color.unwrap_or_else(|| crack_color_at(step as f32 / 60_f32));
+    let mut fade_amt = 1_f32;
+    let mut vertices = Vec::new();
+    let mut graph = match &script.snapshot {
+        Some(path) => {
+            let mut graph = Graph::load_snapshot(Path::new(path), Graph::screen_dimensions(width, height, script.boundary))
+                .map_err(|e| format!("{} can't be used for a {}x{} screen: {}", path, width, height, e))?;
+            // snapshots don't keep the rng
+            if let Some(seed) = script.seed {
+                graph.reseed(seed);
+            }
+            if let Some(params) = script.params {
+                graph.set_params(params);
+            }
+            graph
+        }
+        None => {
+            let mut graph = Graph::for_screen(width, height, script.boundary, script.seed);
+            graph.set_params(script.params.unwrap_or_default());
+            graph.update_graph_edge_stresses(None);
+            graph
+        }
+    };
+    if let Some(path) = &script.mask {
+        let mask = Mask::load(Path::new(path))?;
+        graph.apply_mask(|v| {
+            let [x, y] = v.to_pixel(width, height);
+            mask.contains(x, y, width, height)
+        });
+    }
+    if let Some(path) = &script.scratches {
+        for line in load_scratches(Path::new(path))? {
+            let ndcs: Vec<Vertex> = line.iter().map(|p| Vertex::from_pixel(*p, width, height)).collect();
+            graph.crack_path(&ndcs, renderer.as_ref().map(|_| &mut vertices));
+        }
+    }
+    // the cracks the snapshot started with are drawn in the first step too
+    if script.snapshot.is_some() && renderer.is_some() {
+        vertices = graph.crack_triangles();
+    }
+
+    script.impacts.sort_by_key(|i| i.step);
+    let mut impacts = script.impacts.iter().peekable();
+    for step in 0..script.steps {
+        while let Some(impact) = impacts.next_if(|i| i.step <= step) {
+            let index = match impact.position {
+                Some(p) => graph.edge_near(Vertex::from_pixel(p, width, height))
+                    .ok_or_else(|| format!("no ice near {:?}", p))?,
+                None => graph.get_random_edge_index().ok_or("there is no uncracked ice left to hit")?,
+            };
+            graph.add_stress(index, impact.amount)
+                .map_err(|_| format!("couldn't add stress at {:?}", index))?;
+        }
+        graph.update_graph_edge_stresses(renderer.as_ref().map(|_| &mut vertices));
+        graph.update_graph_stress_propagation();
+        if let Some(r) = renderer.as_mut() {
+            r.draw_cracks(&vertices);
+            vertices.clear();
+        }
+
+        if let (Some(rec), Some(r)) = (recorder.as_mut(), renderer.as_mut()) {
+            if script.stop_step.is_some_and(|s| step >= s) {
+                fade_amt = (fade_amt - 1_f32 / FADE_FRAMES).max(0_f32);
+            }
+            r.update_bloom();
+            let layers = [CompositeLayer { layer: r, crack_color: crack_color(step), bloom_mix: bloom_mix(graph.get_update_amt()) }];
+            rec.record(composite(&layers, fade_amt * fade_amt, width, height), width, height);
+        }
+    }
+    if let Some(rec) = recorder {
+        rec.finish();
+    }
+
+    let stats = graph.stats(width, height);
+    println!("{}: {} steps in {} seconds, {:?}", script_path.display(), script.steps, t.elapsed().as_secs_f32(), stats);
+
+    let output = &script.output;
+    if let Some(path) = &output.stats {
+        serde_json::to_writer_pretty(File::create(path)?, &stats)?;
+    }
+    if let Some(path) = &output.png {
+        write_png(&graph, width, height, Path::new(path))?;
+    }
+    if let (Some(path), Some(r)) = (&output.render, renderer.as_mut()) {
+        r.update_bloom();
+        let layers = [CompositeLayer { layer: r, crack_color: crack_color(script.steps), bloom_mix: bloom_mix(graph.get_update_amt()) }];
+        write_rgba_png(&composite(&layers, 1.0, width, height), width, height, Path::new(path))?;
+    }
+    if let Some(path) = &output.svg {
+        write_svg(&graph, width, height, Path::new(path))?;
+    }
+    if let Some(path) = &output.snapshot {
+        graph.save_snapshot(Path::new(path))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let scripts: Vec<String> = std::env::args().skip(1).collect();
+    if scripts.is_empty() {
+        eprintln!("usage: headless <script.json> [<script.json> ...]");
+        std::process::exit(2);
+    }
+
+    let mut failed = false;
+    for script in scripts {
+        if let Err(e) = run(Path::new(&script)) {
+            eprintln!("{}: {}", script, e);
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,245 @@
+//! Tunes the accel stages and the stress mapping to a watch and a performer.
+//!
+//! `calibrate [rest_secs hit_secs [apply]]` on the console, or `/calibrate [rest_secs hit_secs [apply]]` over osc,
+//! records the watch lying still for `rest_secs`, then being hit a few times for `hit_secs`. Watch readings don't
+//! crack the ice while it's going. The thresholds of the accel stages are then put halfway between the noise and the
+//! softest hit, and the stress mapping is stretched so the hardest hit gives `MAX_STRESS`. The results are printed
+//! as settings, and used straight away with `apply`.
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::accel::{AccelFilter, Stage};
+use crate::simulation::StressMapping;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CalibrateRequest {
+    pub rest_secs: f32,
+    pub hit_secs: f32,
+    /// use the results straight away instead of only printing them
+    pub apply: bool,
+}
+
+impl Default for CalibrateRequest {
+    fn default() -> Self {
+        Self {
+            rest_secs: 5.0,
+            hit_secs: 10.0,
+            apply: false,
+        }
+    }
+}
+
+impl CalibrateRequest {
+    /// Read the arguments of `calibrate` typed into the console: `[rest_secs hit_secs [apply]]`
+    pub fn parse(args: &str) -> Result<Self, Box<dyn Error>> {
+        let words: Vec<&str> = args.split_whitespace().collect();
+        let mut request = Self::default();
+        match words.as_slice() {
+            [] => (),
+            [rest, hit] | [rest, hit, "apply"] => {
+                request.rest_secs = rest.parse()?;
+                request.hit_secs = hit.parse()?;
+                request.apply = words.len() == 3;
+            }
+            _ => return Err("usage: calibrate [rest_secs hit_secs [apply]]".into()),
+        }
+        Ok(request)
+    }
+}
+
+/// What a calibration found
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalibrationResult {
+    pub noise_mean: f32,
+    pub noise_max: f32,
+    /// the top of each hit
+    pub hits: Vec<f32>,
+    pub threshold: f32,
+    pub stages: Vec<Stage>,
+    pub stress_mapping: StressMapping,
+}
+
+/// Hits closer together than this are counted as one
+const HIT_GAP: f64 = 0.25;
+
+/// A calibration that's going
+pub struct Calibration {
+    request: CalibrateRequest,
+    /// stages of the chain being calibrated
+    stages: Vec<Stage>,
+    /// the stages before the first one that holds readings back, which is what the thresholds are compared against
+    signal: Option<AccelFilter>,
+    /// show time of the first reading
+    start: f64,
+    rest: Vec<f32>,
+    hits: Vec<(f64, f32)>,
+}
+
+impl Calibration {
+    pub fn new(request: CalibrateRequest) -> Self {
+        Self {
+            request,
+            stages: Vec::new(),
+            signal: None,
+            start: 0_f64,
+            rest: Vec::new(),
+            hits: Vec::new(),
+        }
+    }
+
+    pub fn request(&self) -> CalibrateRequest {
+        self.request
+    }
+
+    /// Take in a reading `t` seconds into the show, for calibrating `filter`. Returns the result once both halves
+    /// are over
+    pub fn push(&mut self, filter: &AccelFilter, t: f64, accel: [f32; 3]) -> Option<Result<CalibrationResult, Box<dyn Error>>> {
+        let signal = match self.signal.as_mut() {
+            Some(s) => s,
+            None => {
+                self.stages = filter.stages().to_vec();
+                let prefix: Vec<Stage> = self.stages.iter().take_while(|s| !s.is_trigger()).cloned().collect();
+                self.start = t;
+                println!("calibrating: keep the watch still for {} seconds", self.request.rest_secs);
+                self.signal.insert(AccelFilter::new(&prefix).ok()?)
+            }
+        };
+        let v = signal.push(t, accel)?;
+        let elapsed = t - self.start;
+        if elapsed < self.request.rest_secs as f64 {
+            self.rest.push(v);
+        } else if elapsed < (self.request.rest_secs + self.request.hit_secs) as f64 {
+            if self.hits.is_empty() {
+                println!("calibrating: now hit it a few times for {} seconds", self.request.hit_secs);
+            }
+            self.hits.push((t, v));
+        } else {
+            return Some(analyze(&self.rest, &self.hits, &self.stages));
+        }
+        None
+    }
+}
+
+/// Work out the thresholds and stress mapping from readings at rest and readings while being hit
+pub fn analyze(rest: &[f32], hits: &[(f64, f32)], stages: &[Stage]) -> Result<CalibrationResult, Box<dyn Error>> {
+    if rest.is_empty() || hits.is_empty() {
+        return Err("no readings from the watch".into());
+    }
+    let noise_mean = rest.iter().sum::<f32>() / rest.len() as f32;
+    let noise_max = rest.iter().cloned().fold(0_f32, f32::max);
+
+    // the top of everything that stands well clear of the noise
+    let floor = noise_max * 1.5 + (noise_max - noise_mean);
+    let mut peaks: Vec<(f64, f32)> = Vec::new();
+    for w in hits.windows(3) {
+        let (t, v) = w[1];
+        if v > floor && v >= w[0].1 && v > w[2].1 {
+            match peaks.last_mut() {
+                Some(p) if t - p.0 < HIT_GAP => {
+                    if v > p.1 {
+                        *p = (t, v);
+                    }
+                }
+                _ => peaks.push((t, v)),
+            }
+        }
+    }
+    if peaks.is_empty() {
+        return Err("no hits stood out from the noise".into());
+    }
+    let hits: Vec<f32> = peaks.iter().map(|p| p.1).collect();
+    let softest = hits.iter().cloned().fold(f32::MAX, f32::min);
+    let hardest = hits.iter().cloned().fold(0_f32, f32::max);
+    let threshold = (noise_max + softest) / 2_f32;
+
+    let mut stages = stages.to_vec();
+    let mut has_trigger = false;
+    for s in stages.iter_mut() {
+        match s {
+            Stage::Threshold { threshold: t } | Stage::Peak { threshold: t } => *t = threshold,
+            Stage::Hysteresis { on, off } => {
+                *on = threshold;
+                *off = (noise_max + threshold) / 2_f32;
+            }
+            _ => continue,
+        }
+        has_trigger = true;
+    }
+    if !has_trigger {
+        let at = stages.iter().position(|s| s.is_trigger()).unwrap_or(stages.len());
+        stages.insert(at, Stage::Threshold { threshold });
+    }
+
+    // the same kick as the default mapping at the threshold, and everything at the hardest hit
+    let default = StressMapping::default();
+    let floor = threshold - (500_f32 - default.floor);
+    let stress_mapping = StressMapping {
+        floor,
+        range: (hardest - floor).max(1_f32),
+        ..default
+    };
+    Ok(CalibrationResult { noise_mean, noise_max, hits, threshold, stages, stress_mapping })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accel::default_stages;
+
+    #[test]
+    fn test_default_stress_mapping() {
+        // the mapping the show has always used
+        for v in [500_f32, 640.0, 750.0, 1000.0] {
+            let old = ((v - 500.0 + 31.0) / 500.0).powf(2.5_f32) * StressMapping::MAX_STRESS;
+            assert_eq!(StressMapping::default().stress(v), old);
+        }
+        assert_eq!(StressMapping::default().stress(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(CalibrateRequest::parse("").unwrap(), CalibrateRequest::default());
+        assert_eq!(CalibrateRequest::parse(" 3 8 apply").unwrap(), CalibrateRequest { rest_secs: 3.0, hit_secs: 8.0, apply: true });
+        assert_eq!(CalibrateRequest::parse("3 8").unwrap(), CalibrateRequest { rest_secs: 3.0, hit_secs: 8.0, apply: false });
+        assert!(CalibrateRequest::parse("3").is_err());
+        assert!(CalibrateRequest::parse("3 x").is_err());
+        assert!(CalibrateRequest::parse("3 8 now").is_err());
+    }
+
+    #[test]
+    fn test_calibration() {
+        let mut c = Calibration::new(CalibrateRequest { rest_secs: 2.0, hit_secs: 3.0, apply: false });
+        let filter = AccelFilter::default();
+        let mut result = None;
+        for i in 0..300 {
+            let t = 10.0 + i as f64 * 0.02;
+            // a little noise, then hits at 12.5, 13.5 and 14.5 seconds
+            let mut a = [(i % 7) as f32 * 3.0, 0.0, 20.0];
+            if [125, 175, 225].contains(&i) {
+                a[0] = [300.0, 600.0, 900.0][(i - 125) / 50] * 4.0;
+            }
+            if let Some(r) = c.push(&filter, t, a) {
+                result = Some(r.unwrap());
+                break;
+            }
+        }
+        let r = result.unwrap();
+        assert_eq!(r.hits.len(), 3);
+        assert!(r.noise_max < 50.0);
+        // the softest hit gets through the moving average at about a quarter of its size
+        assert!(r.threshold > r.noise_max && r.threshold < r.hits[0]);
+        assert_eq!(r.stages[2], Stage::Threshold { threshold: r.threshold });
+        assert_eq!(r.stages.len(), default_stages().len());
+        assert!((r.stress_mapping.stress(r.hits[2]) - StressMapping::MAX_STRESS).abs() < 1.0);
+        assert!(r.stress_mapping.stress(r.threshold) < 100.0);
+
+        // nothing but noise
+        let rest = vec![10.0; 100];
+        let hits: Vec<(f64, f32)> = (0..100).map(|i| (i as f64 * 0.02, 10.0)).collect();
+        assert!(analyze(&rest, &hits, &default_stages()).is_err());
+        // a chain without a trigger gets one
+        let r = analyze(&rest, &[(0.0, 10.0), (0.02, 200.0), (0.04, 10.0)], &[Stage::Magnitude]).unwrap();
+        assert_eq!(r.stages, vec![Stage::Magnitude, Stage::Threshold { threshold: 105.0 }]);
+    }
+}
@@ -0,0 +1,204 @@
+//! Command line flags for the simulator. Each one has an environment variable that does the same, for launch
+//! scripts. Flags win over environment variables, which win over the settings file.
+use std::path::PathBuf;
+
+use crate::graphics::layer::LayerSettings;
+use crate::settings::Settings;
+
+pub const USAGE: &str = "\
+usage: crack_simulator [options]
+
+  --settings <path>        settings file, instead of settings.json      CRACK_SETTINGS
+  --resolution <WxH>       window size, 1920x1080 if not given          CRACK_RESOLUTION
+  --fullscreen             start fullscreen                             CRACK_FULLSCREEN=1
+  --seed <n>               seed for the graphs                          CRACK_SEED
+  --headless               run the show without a window. Frames are    CRACK_HEADLESS=1
+                           only drawn when record_frames is set
+  --replay <path>          play back an input log                       CRACK_REPLAY
+  --param <name>=<value>   set one of the graph params on every layer.  CRACK_PARAMS=a=1,b=2
+                           Can be given more than once
+  --help                   print this";
+
+pub const DEFAULT_RESOLUTION: (u32, u32) = (1920, 1080);
+
+/// What the simulator was started with
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    pub settings: Option<PathBuf>,
+    pub resolution: Option<(u32, u32)>,
+    pub fullscreen: bool,
+    pub seed: Option<u64>,
+    pub headless: bool,
+    pub replay: Option<String>,
+    pub params: Vec<(String, f32)>,
+    pub help: bool,
+}
+
+impl Options {
+    /// The flags the process was started with, and its environment
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(std::env::args().skip(1), |name| std::env::var(name).ok())
+    }
+
+    /// Read `args`, falling back on the environment variables `var` looks up
+    pub fn parse(args: impl IntoIterator<Item = String>, var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        if let Some(v) = var("CRACK_SETTINGS") {
+            options.settings = Some(PathBuf::from(v));
+        }
+        if let Some(v) = var("CRACK_RESOLUTION") {
+            options.resolution = Some(parse_resolution(&v)?);
+        }
+        if let Some(v) = var("CRACK_FULLSCREEN") {
+            options.fullscreen = parse_bool("CRACK_FULLSCREEN", &v)?;
+        }
+        if let Some(v) = var("CRACK_SEED") {
+            options.seed = Some(parse_seed(&v)?);
+        }
+        if let Some(v) = var("CRACK_HEADLESS") {
+            options.headless = parse_bool("CRACK_HEADLESS", &v)?;
+        }
+        if let Some(v) = var("CRACK_REPLAY") {
+            options.replay = Some(v);
+        }
+        if let Some(v) = var("CRACK_PARAMS") {
+            for p in v.split(',').filter(|p| !p.trim().is_empty()) {
+                options.params.push(parse_param(p)?);
+            }
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // `--flag value` or `--flag=value`
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || inline.clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag));
+            match flag.as_str() {
+                "--settings" => options.settings = Some(PathBuf::from(value()?)),
+                "--resolution" => options.resolution = Some(parse_resolution(&value()?)?),
+                "--seed" => options.seed = Some(parse_seed(&value()?)?),
+                "--replay" => options.replay = Some(value()?),
+                "--param" => options.params.push(parse_param(&value()?)?),
+                "--fullscreen" | "--headless" | "--help" | "-h" if inline.is_some() => return Err(format!("{} doesn't take a value", flag)),
+                "--fullscreen" => options.fullscreen = true,
+                "--headless" => options.headless = true,
+                "--help" | "-h" => options.help = true,
+                _ => return Err(format!("unknown argument {:?}", arg)),
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution.unwrap_or(DEFAULT_RESOLUTION)
+    }
+
+    /// Put the options that override the settings file into `settings`
+    pub fn apply(&self, settings: &mut Settings) -> Result<(), String> {
+        if let Some(seed) = self.seed {
+            settings.seed = Some(seed);
+        }
+        if let Some(replay) = &self.replay {
+            settings.replay = Some(replay.clone());
+        }
+        if !self.params.is_empty() && settings.layers.is_empty() {
+            settings.layers.push(LayerSettings::default());
+        }
+        for (name, value) in &self.params {
+            for layer in settings.layers.iter_mut() {
+                layer.params.set(name, *value).map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let bad = || format!("expected a resolution like 1920x1080, got {:?}", s);
+    let (w, h) = s.trim().split_once(['x', 'X']).ok_or_else(bad)?;
+    match (w.parse(), h.parse()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(bad()),
+    }
+}
+
+fn parse_seed(s: &str) -> Result<u64, String> {
+    s.trim().parse().map_err(|_| format!("expected a whole number seed, got {:?}", s))
+}
+
+fn parse_bool(name: &str, s: &str) -> Result<bool, String> {
+    match s.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "" | "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("expected {} to be 1 or 0, got {:?}", name, s)),
+    }
+}
+
+/// `name=value`
+fn parse_param(s: &str) -> Result<(String, f32), String> {
+    let (name, value) = s.split_once('=')
+        .ok_or_else(|| format!("expected a param like crack_threshold=2.5, got {:?}", s))?;
+    let value = value.trim().parse()
+        .map_err(|_| format!("expected a number for {}, got {:?}", name.trim(), value))?;
+    Ok((name.trim().to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(args: &[&str], vars: &[(&str, &str)]) -> Result<Options, String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Options::parse(args.iter().map(|a| a.to_string()), |k| vars.get(k).cloned())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&[], &[]).unwrap(), Options::default());
+        assert_eq!(parse(&[], &[]).unwrap().resolution(), (1920, 1080));
+        let o = parse(&["--settings", "show/a.json", "--resolution=1280x720", "--fullscreen", "--seed", "7", "--headless",
+            "--replay", "logs/in.jsonl", "--param", "crack_threshold=2.5", "--param=propagation_const=0.5"], &[]).unwrap();
+        assert_eq!(o, Options {
+            settings: Some(PathBuf::from("show/a.json")),
+            resolution: Some((1280, 720)),
+            fullscreen: true,
+            seed: Some(7),
+            headless: true,
+            replay: Some("logs/in.jsonl".into()),
+            params: vec![("crack_threshold".into(), 2.5), ("propagation_const".into(), 0.5)],
+            help: false,
+        });
+
+        // flags win over the environment
+        let vars = [("CRACK_SETTINGS", "b.json"), ("CRACK_SEED", "3"), ("CRACK_FULLSCREEN", "1"), ("CRACK_PARAMS", "crack_threshold=1,dir_propagation=2")];
+        let o = parse(&["--seed", "9"], &vars).unwrap();
+        assert_eq!((o.settings, o.seed, o.fullscreen, o.params.len()), (Some(PathBuf::from("b.json")), Some(9), true, 2));
+
+        assert!(parse(&["--seed"], &[]).is_err());
+        assert!(parse(&["--seed", "x"], &[]).is_err());
+        assert!(parse(&["--resolution", "1280"], &[]).is_err());
+        assert!(parse(&["--param", "crack_threshold"], &[]).is_err());
+        assert!(parse(&["--fullscreen=1"], &[]).is_err());
+        assert!(parse(&["--nope"], &[]).is_err());
+        assert!(parse(&[], &[("CRACK_HEADLESS", "maybe")]).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut settings: Settings = serde_json::from_str(r#"{ "src_ip": "", "watch_port": 1, "src_port": 2, "seed": 1 }"#).unwrap();
+        let o = parse(&["--seed", "5", "--replay", "in.jsonl", "--param", "crack_threshold=9"], &[]).unwrap();
+        o.apply(&mut settings).unwrap();
+        assert_eq!(settings.seed, Some(5));
+        assert_eq!(settings.replay.as_deref(), Some("in.jsonl"));
+        assert_eq!(settings.layers.len(), 1);
+        assert_eq!(settings.layers[0].params.crack_threshold, 9.0);
+
+        let o = parse(&["--param", "nope=1"], &[]).unwrap();
+        assert!(o.apply(&mut settings).is_err());
+    }
+}
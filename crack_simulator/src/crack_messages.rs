@@ -0,0 +1,159 @@
+//! Messages about the cracks the simulation draws, so sound can follow the visible cracks instead of the sensor.
+//!
+//! * `/crack/grow id x y length branches dx dy` - a crack grew. `x y` is where its newest edge is, as fractions of
+//!   the screen from the top left. `length` and `branches` count edges since the crack started growing this time,
+//!   and `dx dy` is the direction it's growing in, with y pointing down. Sent at most `GROW_RATE` times a second
+//!   per crack
+//! * `/crack/end id` - the crack stopped growing
+use std::collections::BTreeMap;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rosc::{OscMessage, OscType};
+
+use crate::osc::Show;
+use crate::simulation::graph::events::CrackEvent;
+
+/// times a second to send `/crack/grow` for each growing crack
+pub const GROW_RATE: f32 = 30_f32;
+
+/// A crack that's growing
+struct Growth {
+    position: [f32; 2],
+    length: usize,
+    branches: usize,
+    direction: [f32; 2],
+    /// grew since its last `/crack/grow`
+    changed: bool,
+}
+
+/// Turns crack events into `/crack/grow` and `/crack/end` messages
+#[derive(Default)]
+pub struct CrackReporter {
+    growing: BTreeMap<usize, Growth>,
+}
+
+impl CrackReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in an event. Returns the messages it sets off right away, which is only when cracks stop growing
+    pub fn event(&mut self, event: CrackEvent) -> Vec<OscMessage> {
+        match event {
+            CrackEvent::EdgeCracked { crack, position, direction, branched, .. } => {
+                let g = self.growing.entry(crack).or_insert(Growth {
+                    position: [0_f32; 2],
+                    length: 0,
+                    branches: 0,
+                    direction: [0_f32; 2],
+                    changed: true,
+                });
+                g.position = [(position[0] + 1_f32) / 2_f32, (1_f32 - position[1]) / 2_f32];
+                g.length += 1;
+                g.branches += branched as usize;
+                // stress directions are (down, right)
+                let len = (direction[0] * direction[0] + direction[1] * direction[1]).sqrt();
+                if len > 0_f32 {
+                    g.direction = [direction[1] / len, direction[0] / len];
+                }
+                g.changed = true;
+                Vec::new()
+            }
+            CrackEvent::PropagationSettled { .. } => {
+                let mut msgs = self.flush();
+                for id in std::mem::take(&mut self.growing).into_keys() {
+                    msgs.push(OscMessage {
+                        addr: "/crack/end".to_string(),
+                        args: vec![OscType::Int(id as i32)],
+                    });
+                }
+                msgs
+            }
+            CrackEvent::CrackStarted { .. } | CrackEvent::FragmentFormed { .. } => Vec::new(),
+        }
+    }
+
+    /// `/crack/grow` for every crack that grew since the last flush
+    pub fn flush(&mut self) -> Vec<OscMessage> {
+        self.growing.iter_mut()
+            .filter(|(_, g)| g.changed)
+            .map(|(id, g)| {
+                g.changed = false;
+                OscMessage {
+                    addr: "/crack/grow".to_string(),
+                    args: vec![
+                        OscType::Int(*id as i32),
+                        OscType::Float(g.position[0]),
+                        OscType::Float(g.position[1]),
+                        OscType::Int(g.length as i32),
+                        OscType::Int(g.branches as i32),
+                        OscType::Float(g.direction[0]),
+                        OscType::Float(g.direction[1]),
+                    ],
+                }
+            })
+            .collect()
+    }
+}
+
+/// Send messages about the cracks in `events` to every target once the show has started
+pub fn report_cracks(events: Receiver<CrackEvent>, show: Arc<Show>) {
+    std::thread::spawn(move || {
+        let interval = Duration::from_secs_f32(1_f32 / GROW_RATE);
+        let mut reporter = CrackReporter::new();
+        let mut next_flush = Instant::now() + interval;
+        loop {
+            let mut msgs = match events.recv_timeout(next_flush.saturating_duration_since(Instant::now())) {
+                Ok(e) => reporter.event(e),
+                Err(RecvTimeoutError::Timeout) => Vec::new(),
+                // the simulation has gone
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            if Instant::now() >= next_flush {
+                msgs.extend(reporter.flush());
+                next_flush = Instant::now() + interval;
+            }
+            if let Some(n) = show.notifier().filter(|_| !msgs.is_empty()) {
+                let n = n.lock().unwrap();
+                for msg in msgs {
+                    n.notify(Some(msg), None);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::graph::edge::EdgeIndex;
+
+    fn cracked(crack: usize, position: [f32; 2], direction: [f32; 2], branched: bool) -> CrackEvent {
+        CrackEvent::EdgeCracked { crack, edge: EdgeIndex { row: 0, col: 0, ty: 0 }, position, energy: 1.0, direction, branched }
+    }
+
+    #[test]
+    fn test_crack_reporter() {
+        let mut r = CrackReporter::new();
+        assert!(r.event(CrackEvent::CrackStarted { crack: 3, position: [0.0, 0.0] }).is_empty());
+        r.event(cracked(3, [0.0, 0.0], [0.0, 0.0], false));
+        r.event(cracked(3, [0.5, -0.5], [0.0, 2.0], true));
+        r.event(cracked(5, [-1.0, 1.0], [1.0, 0.0], false));
+
+        let grow = |id: i32, x: f32, y: f32, length: i32, branches: i32, dx: f32, dy: f32| OscMessage {
+            addr: "/crack/grow".to_string(),
+            args: vec![OscType::Int(id), OscType::Float(x), OscType::Float(y), OscType::Int(length), OscType::Int(branches),
+                OscType::Float(dx), OscType::Float(dy)],
+        };
+        assert_eq!(r.flush(), vec![grow(3, 0.75, 0.75, 2, 1, 1.0, 0.0), grow(5, 0.0, 0.0, 1, 0, 0.0, 1.0)]);
+        assert!(r.flush().is_empty());
+
+        r.event(cracked(5, [-1.0, 1.0], [1.0, 0.0], false));
+        let end = |id: i32| OscMessage { addr: "/crack/end".to_string(), args: vec![OscType::Int(id)] };
+        assert_eq!(r.event(CrackEvent::PropagationSettled { cracks: vec![3, 5] }),
+            vec![grow(5, 0.0, 0.0, 2, 0, 0.0, 1.0), end(3), end(5)]);
+        assert!(r.flush().is_empty());
+    }
+}
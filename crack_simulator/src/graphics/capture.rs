@@ -0,0 +1,71 @@
+//! Gets frames from the GPU to a `FrameRecorder` without holding up the show.
+//! Each frame is drawn into a texture and copied into a pixel buffer in video memory, which only gets read a few
+//! frames later, once the copy has had time to finish.
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::Path;
+
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::pixel_buffer::PixelBuffer;
+use glium::texture::{RawImage2d, SrgbTexture2d};
+use glium::Display;
+
+use super::recorder::FrameRecorder;
+
+/// frames between starting to copy a frame back from the GPU and reading it
+const READBACK_DELAY: usize = 3;
+
+pub struct Capture {
+    recorder: FrameRecorder,
+    texture: SrgbTexture2d,
+    /// oldest first
+    pending: VecDeque<PixelBuffer<(u8, u8, u8, u8)>>,
+}
+
+impl Capture {
+    /// Record `width` x `height` frames into `dir`
+    pub fn new(display: &Display, width: u32, height: u32, dir: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            recorder: FrameRecorder::new(dir)?,
+            texture: SrgbTexture2d::empty(display, width, height)?,
+            pending: VecDeque::with_capacity(READBACK_DELAY + 1),
+        })
+    }
+
+    /// Where to draw the next frame to record
+    pub fn surface<'a>(&'a self, display: &Display) -> SimpleFrameBuffer<'a> {
+        SimpleFrameBuffer::new(display, &self.texture).expect("failed to create frame buffer")
+    }
+
+    /// Start copying the frame drawn on `surface` back from the GPU, and record the one from `READBACK_DELAY`
+    /// frames ago
+    pub fn capture(&mut self) {
+        self.pending.push_back(self.texture.read_to_pixel_buffer());
+        if self.pending.len() > READBACK_DELAY {
+            let buffer = self.pending.pop_front().unwrap();
+            self.record(buffer);
+        }
+    }
+
+    fn record(&mut self, buffer: PixelBuffer<(u8, u8, u8, u8)>) {
+        let image: RawImage2d<u8> = match buffer.read_as_texture_2d() {
+            Ok(i) => i,
+            Err(e) => {
+                println!("failed to read frame: {:?}", e);
+                return;
+            }
+        };
+        // gl images start at the bottom
+        let row = image.width as usize * 4;
+        let pixels = image.data.chunks(row).rev().flatten().copied().collect();
+        self.recorder.record(pixels, image.width, image.height);
+    }
+
+    /// Record the frames still on their way back and wait for every frame to be saved
+    pub fn finish(mut self) {
+        while let Some(buffer) = self.pending.pop_front() {
+            self.record(buffer);
+        }
+        self.recorder.finish();
+    }
+}
@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::simulation::graph::Graph;
+
+/// Draw the cracks of a graph as white lines on black, one byte per pixel
+pub fn rasterize_cracks(graph: &Graph, width: u32, height: u32) -> Vec<u8> {
+    let mut out = vec![0_u8; width as usize * height as usize];
+    for [a, b] in graph.cracked_segments() {
+        let (a, b) = (a.to_pixel(width, height), b.to_pixel(width, height));
+        let n = (b[0] - a[0]).abs().max((b[1] - a[1]).abs()).ceil().max(1_f32) as usize;
+        for i in 0..=n {
+            let t = i as f32 / n as f32;
+            let (x, y) = (a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t);
+            if x >= 0_f32 && y >= 0_f32 && (x as u32) < width && (y as u32) < height {
+                out[y as usize * width as usize + x as usize] = 255;
+            }
+        }
+    }
+    out
+}
+
+/// Save the cracks of a graph as a grayscale png
+pub fn write_png(graph: &Graph, width: u32, height: u32, path: &Path) -> Result<(), Box<dyn Error>> {
+    let pixels = rasterize_cracks(graph, width, height);
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    Ok(())
+}
+
+/// Save the cracks of a graph as a single svg path, in pixel coordinates
+pub fn write_svg(graph: &Graph, width: u32, height: u32, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut f = BufWriter::new(File::create(path)?);
+    writeln!(f, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#, width, height)?;
+    writeln!(f, r#"<rect width="100%" height="100%" fill="black"/>"#)?;
+    write!(f, r#"<path stroke="white" stroke-width="1" fill="none" d=""#)?;
+    for [a, b] in graph.cracked_segments() {
+        let (a, b) = (a.to_pixel(width, height), b.to_pixel(width, height));
+        write!(f, "M{:.2} {:.2}L{:.2} {:.2}", a[0], a[1], b[0], b[1])?;
+    }
+    writeln!(f, r#""/>"#)?;
+    writeln!(f, "</svg>")?;
+    f.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::vertex::Vertex;
+    use crate::simulation::graph::BoundaryMode;
+
+    #[test]
+    fn test_rasterize_cracks() {
+        let mut g = Graph::for_screen(100, 50, BoundaryMode::Free, None);
+        assert!(rasterize_cracks(&g, 100, 50).iter().all(|p| *p == 0));
+
+        g.crack_path(&[Vertex::from_pixel([10.0, 25.0], 100, 50), Vertex::from_pixel([90.0, 25.0], 100, 50)], None);
+        let pixels = rasterize_cracks(&g, 100, 50);
+        let row = &pixels[25 * 100..26 * 100];
+        assert!(row[20..80].iter().all(|p| *p == 255));
+        assert_eq!(row[5], 0);
+        assert!(pixels[..10 * 100].iter().all(|p| *p == 0));
+    }
+}
@@ -0,0 +1,83 @@
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::SrgbTexture2d;
+use glium::{Display, Surface};
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::graph::GraphParams;
+
+/// How a single sheet of ice looks and behaves
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LayerSettings {
+    /// multiplied with the crack color
+    pub color: [f32; 4],
+    /// 0 => surface, 1 => as deep as it gets. Deeper layers are drawn dimmer and blurrier
+    pub depth: f32,
+    /// fraction of the stress hitting the layer above, or moving along its cracks, that reaches this layer
+    pub coupling: f32,
+    pub params: GraphParams,
+    /// keep an energy ledger for tuning `params`. Slows the simulation down a little
+    pub track_energy: bool,
+    /// check the graph for corruption about once a minute and print anything that's wrong
+    pub validate: bool,
+}
+
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+            depth: 0.0,
+            coupling: 1.0,
+            params: GraphParams::default(),
+            track_energy: false,
+            validate: false,
+        }
+    }
+}
+
+impl LayerSettings {
+    /// Returns the brightness and extra bloom used to push this layer back into the ice
+    pub fn depth_cue(&self) -> (f32, f32) {
+        let depth = self.depth.clamp(0_f32, 1_f32);
+        (1_f32 - 0.5 * depth, 0.3 * depth)
+    }
+
+    /// Color of this layer's cracks given the color of the whole sheet
+    pub fn tint(&self, crack_color: [f32; 4]) -> [f32; 4] {
+        let (brightness, _) = self.depth_cue();
+        let c = self.color;
+        [
+            crack_color[0] * c[0] * brightness,
+            crack_color[1] * c[1] * brightness,
+            crack_color[2] * c[2] * brightness,
+            crack_color[3] * c[3],
+        ]
+    }
+}
+
+/// The textures the cracks of a layer of ice are drawn into
+pub struct Layer {
+    pub(super) crack_texture: SrgbTexture2d,
+    pub(super) bloom_texture: SrgbTexture2d,
+}
+
+impl Layer {
+    pub fn new(display: &Display, width: u32, height: u32) -> Self {
+        let crack_texture = SrgbTexture2d::empty(display, width * 4, height * 4)
+            .expect("failed to create texture");
+        let bloom_texture = SrgbTexture2d::empty(display, width, height)
+            .expect("failed to create texture");
+        let layer = Self { crack_texture, bloom_texture };
+        layer.clear(display);
+        layer
+    }
+
+    /// Wipe every crack off the textures
+    pub fn clear(&self, display: &Display) {
+        for texture in [&self.crack_texture, &self.bloom_texture] {
+            SimpleFrameBuffer::new(display, texture)
+                .expect("failed to create frame buffer")
+                .clear_color(0.0, 0.0, 0.0, 0.0);
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use std::collections::VecDeque;
+use std::error::Error;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 use glium::framebuffer::SimpleFrameBuffer;
@@ -6,106 +8,163 @@ use glium::glutin::dpi::LogicalSize;
 use glium::glutin::event::ElementState;
 use glium::glutin::window::Fullscreen;
 use glium::glutin::{self, event_loop::EventLoop};
-use glium::texture::SrgbTexture2d;
-use glium::{Display, Surface, Program, VertexBuffer};
+use glium::{Display, Surface, Program, VertexBuffer, Blend, BlendingFunction, LinearBlendingFactor, DrawParameters};
 use glium::uniform;
 use vertex::Vertex;
-use crate::{TIMER, TOTAL_TIME};
-use crate::simulation::graph::Graph;
+use layer::Layer;
+use crate::TOTAL_TIME;
+use crate::simulation::ice::Ice;
+use crate::simulation::SimCommand;
+use crate::simulation::timeline::Rewind;
+use crate::osc;
+use capture::Capture;
 
 pub mod vertex;
+pub mod layer;
+pub mod export;
+pub mod software;
+pub mod recorder;
+pub mod capture;
+pub mod windowless;
 
+/// number of frames the show takes to fade out after it's stopped
+pub const FADE_FRAMES: f32 = 60_f32 * 5_f32;
+/// the bloom kernel steps 1 / `BLOOM_SCALE` of the screen across and up, whatever the size of the screen
+pub const BLOOM_SCALE: f32 = 1920_f32;
+
+/// Color of the cracks `secs` seconds into the show. They cool from cyan to blue over `TOTAL_TIME`
+pub fn crack_color_at(secs: f32) -> [f32; 4] {
+    [0.5 - 0.5 * secs / *TOTAL_TIME as f32, 1.0 - secs / *TOTAL_TIME as f32, 1.0, 1.0]
+}
+
+/// How much bloom to mix into a layer given how many edges it still has to update.
+/// Busy layers glow more
+pub fn bloom_mix(queue_depth: usize) -> f32 {
+    (queue_depth as f32 / 100_f32).clamp(0.1, 0.5)
+}
+
+/// Draws the ice in a window with OpenGL
 pub struct SimulationScreen {
-    pub event_loop: EventLoop<()>,
+    /// taken when the simulation starts running
+    pub event_loop: Option<EventLoop<()>>,
     pub display: Display,
     pub width: u32,
     pub height: u32,
 
-    vertice_update_list: Vec<Vertex>,
-    
     crack_shader_program: Program,
     screen_shader_program: Program,
     bloom_shader_program: Program,
-    crack_texture: SrgbTexture2d,
-    bloom_texture: SrgbTexture2d,
-
-    bloom_mix: f32,
 
-    graph: Graph,
-    crack_color: [f32; 4],
-    crack_update_list: Arc<Mutex<VecDeque<f32>>>,
+    ice: Ice,
+    /// the textures of each layer of `ice`
+    layers: Vec<Layer>,
+    crack_update_list: Arc<Mutex<VecDeque<SimCommand>>>,
 
-    ending: bool,
-    fade_amt: f32,
+    capture: Option<Capture>,
 }
 
 impl SimulationScreen {
-    pub fn new(width: u32, height: u32, crack_update_list: Arc<Mutex<VecDeque<f32>>>) -> Self {
-        let t = std::time::Instant::now();
-        println!("Building graph...");
-        let mut graph = Graph::new((height as f32 / 3_f32.sqrt() * 2_f32).ceil() as usize, width as usize + 1);
-        graph.set_node_ndcs(0.5, 0.0, 1.0 / width as f32, (height as f32 / 3_f32.sqrt() * 2_f32).ceil() / (height as f32 / 3_f32.sqrt() * 2_f32) / height as f32);
-        graph.update_graph_edge_stresses(None);
-        println!("Finished building graph in {} seconds", t.elapsed().as_secs_f32());
-
+    /// Open a window the size of `ice`, which takes its commands from `crack_update_list`
+    pub fn new(ice: Ice, crack_update_list: Arc<Mutex<VecDeque<SimCommand>>>) -> Self {
+        let (width, height) = (ice.width, ice.height);
         let event_loop = glutin::event_loop::EventLoop::new();
         let wb = glutin::window::WindowBuilder::new()
             .with_inner_size(LogicalSize::new(width, height));
         let cb = glutin::ContextBuilder::new()
             .with_multisampling(8);
         let display = glium::Display::new(wb, cb, &event_loop).unwrap();
-
-        let crack_texture = SrgbTexture2d::empty(&display, width * 4, height * 4)
-            .expect("failed to create texture");
-        let mut frame_buf = SimpleFrameBuffer::new(&display, &crack_texture)
-            .expect("failed to create frame buffer");
-        frame_buf.clear_color(0.0, 0.0, 0.0, 0.0);
-
-        let bloom_texture = SrgbTexture2d::empty(&display, width, height)
-            .expect("failed to create texture");
-        let mut frame_buf = SimpleFrameBuffer::new(&display, &bloom_texture)
-            .expect("failed to create frame buffer");
-        frame_buf.clear_color(0.0, 0.0, 0.0, 0.0);
+        let layers = ice.layers.iter().map(|_| Layer::new(&display, width, height)).collect();
 
         // initialize screen with black
         let mut target = display.draw();
         target.clear_color(0.0, 0.0, 0.0, 1.0);
-
-        //target.draw(&vertex_buffer, &indices, &crack_shader_program, &uniform! {}, &Default::default()).unwrap();
         target.finish().unwrap();
-        
-        
-        // graph.add_stress(EdgeIndex { row: 500, col: 500, ty: 2 }, 50.0).unwrap();
-        // graph.add_stress(EdgeIndex { row: 500, col: 1000, ty: 0 }, 100.0).unwrap();
-        // graph.add_stress(EdgeIndex { row: 500, col: 1500, ty: 1 }, 50.0).unwrap();
 
         let crack_shader_program = Self::init_crack_program(&display);
         let screen_shader_program = Self::init_screen_program(&display);
         let bloom_shader_program = Self::init_bloom_program(&display);
         Self {
-            event_loop,
+            event_loop: Some(event_loop),
             display,
             width,
             height,
 
-            vertice_update_list: Vec::with_capacity(256),
-
             crack_shader_program,
             screen_shader_program,
             bloom_shader_program,
 
-            graph,
-            crack_texture,
-            bloom_texture,
-            bloom_mix: 0_f32,
-            crack_color: [0.5, 1.0, 1.0, 1.0],
+            ice,
+            layers,
             crack_update_list,
+            capture: None,
+        }
+    }
+
+    /// Borderless fullscreen on the current monitor, or back to a window. F11 toggles it too
+    pub fn set_fullscreen(&self, fullscreen: bool) {
+        let fullscreen = fullscreen.then_some(Fullscreen::Borderless(None));
+        self.display.gl_window().window().set_fullscreen(fullscreen);
+    }
+
+    /// Save every frame from now on as a numbered png in `dir`, at the size of the ice rather than the window
+    pub fn record_frames(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        self.capture = Some(Capture::new(&self.display, self.width, self.height, dir)?);
+        Ok(())
+    }
+
+    /// Redraw every crack from scratch, for when the graphs jump to a different state
+    fn redraw_cracks(&self) {
+        for (layer, ice_layer) in self.layers.iter().zip(&self.ice.layers) {
+            layer.clear(&self.display);
+            let triangles = ice_layer.graph.crack_triangles();
+            if !triangles.is_empty() {
+                self.draw_cracks(layer, &triangles);
+            }
+        }
+    }
 
-            ending: false,
-            fade_amt: 1.0,
+    /// a pair of triangles covering the whole screen
+    fn screen_quad(&self) -> VertexBuffer<Vertex> {
+        glium::VertexBuffer::new(&self.display, &[[-1_f32, -1_f32].into(), [1_f32, 1_f32].into(), [-1_f32, 1_f32].into(), [-1_f32, -1_f32].into(), [1_f32, 1_f32].into(), [1_f32, -1_f32].into()]).unwrap()
+    }
+
+    /// Add every layer onto `target`, deepest first
+    fn draw_layers<S: Surface>(&self, target: &mut S, bloom_mixes: &[f32], crack_color: [f32; 4]) {
+        let default_vbo = self.screen_quad();
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        let draw_params = DrawParameters {
+            blend: Blend {
+                color: BlendingFunction::Addition { source: LinearBlendingFactor::One, destination: LinearBlendingFactor::One },
+                alpha: BlendingFunction::Addition { source: LinearBlendingFactor::One, destination: LinearBlendingFactor::One },
+                constant_value: (0.0, 0.0, 0.0, 0.0),
+            },
+            ..Default::default()
+        };
+        let fade_amt = self.ice.fade_amt * self.ice.fade_amt;
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+        for ((layer, ice_layer), bloom_mix) in self.layers.iter().zip(&self.ice.layers).zip(bloom_mixes).rev() {
+            target.draw(&default_vbo, indices, &self.screen_shader_program, &uniform! {crack_texture: &layer.crack_texture, bloom_texture: &layer.bloom_texture, crack_color: ice_layer.settings.tint(crack_color), bloom_mix: *bloom_mix, fade_amt: fade_amt}, &draw_params)
+                .expect("failed to draw frame");
         }
     }
 
+    /// Draw crack triangles into a layer's crack texture and redo its bloom
+    fn draw_cracks(&self, layer: &Layer, vertices: &[Vertex]) {
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        let vertex_buffer = glium::VertexBuffer::new(&self.display, vertices).unwrap();
+        let mut frame_buf = SimpleFrameBuffer::new(&self.display, &layer.crack_texture)
+            .expect("failed to create frame buffer");
+
+        frame_buf.draw(&vertex_buffer, indices, &self.crack_shader_program, &uniform! {}, &Default::default())
+            .expect("failed to draw frame");
+
+        let mut frame_buf = SimpleFrameBuffer::new(&self.display, &layer.bloom_texture)
+            .expect("failed to create frame buffer");
+
+        frame_buf.draw(&self.screen_quad(), indices, &self.bloom_shader_program, &uniform! {crack_texture: &layer.crack_texture, scale: BLOOM_SCALE}, &Default::default())
+            .expect("failed to draw frame");
+    }
+
     /// compile the shaders for ice cracks
     fn init_crack_program(display: &Display) -> Program {
         let vertex_shader_src: &str = include_str!("./shaders/crack_vs.glsl");
@@ -125,78 +184,60 @@ impl SimulationScreen {
         glium::Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap()
     }
 
-    const MAX_STRESS: f32 = 10000.0;
     pub fn run(mut self) {
         let mut time = std::time::Instant::now();
-        self.event_loop.run(move |ev, _, control_flow| {
-            let mut update_list = self.crack_update_list.lock().unwrap();
-            while let Some(v) = update_list.pop_front() {
-                if v < 0_f32 {
-                    self.ending = true;
-                    break;
-                }
-
-                let pre = (v - 500.0 + 31.0).min(Self::MAX_STRESS);
-                let post = (pre / 500.0).powf(2.5_f32) * Self::MAX_STRESS;
-                println!("stress amt: {}", post);
-                self.graph.add_stress(self.graph.get_random_edge_index(), post).unwrap();
-                // if self.count < *NUM_CRACKS {
-                //     self.crack_color[0] -= 0.5 / *NUM_CRACKS as f32;
-                //     self.crack_color[1] -= 1.0 / *NUM_CRACKS as f32;
-                // }
-                // self.count += 1;
-                // println!("color: {:?}", self.crack_color);
-            }
-            drop(update_list);
+        let event_loop = self.event_loop.take().expect("simulation is already running");
+        // scratches and snapshots loaded before the show are drawn in the first frame
+        self.ice.redraw = true;
+        self.ice.keyframe();
+        event_loop.run(move |ev, _, control_flow| {
+            self.ice.handle_commands(&self.crack_update_list);
             if time.elapsed().as_nanos() > 16_666_667 {
-                if self.ending {
-                    self.fade_amt -= 1_f32 / (60_f32 * 5_f32);
-                    if self.fade_amt <= 0_f32 {
-                        self.fade_amt = 0_f32;
-                        self.ending = false;
+                time = std::time::Instant::now();
+                let crack_color = self.ice.crack_color();
+                let bloom_mixes = self.ice.bloom_mixes();
+                self.ice.update_stresses();
+                if self.ice.redraw {
+                    self.redraw_cracks();
+                    self.ice.redraw = false;
+                } else {
+                    for (layer, ice_layer) in self.layers.iter().zip(&self.ice.layers) {
+                        if !ice_layer.vertices.is_empty() {
+                            self.draw_cracks(layer, &ice_layer.vertices);
+                        }
                     }
                 }
-                self.crack_color[0] = 0.5 - 0.5 * (*TIMER).read().unwrap().elapsed().as_secs_f32() / *TOTAL_TIME as f32;
-                self.crack_color[1] = 1.0 - (*TIMER).read().unwrap().elapsed().as_secs_f32() / *TOTAL_TIME as f32;
-                time =std::time::Instant::now();
-                let default_vbo: VertexBuffer<Vertex> = glium::VertexBuffer::new(&self.display, &vec![[-1_f32, -1_f32].into(), [1_f32, 1_f32].into(), [-1_f32, 1_f32].into(), [-1_f32, -1_f32].into(), [1_f32, 1_f32].into(), [1_f32, -1_f32].into()]).unwrap();
-                let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
-                
-                self.bloom_mix = (self.graph.get_update_amt() as f32 / 100_f32).min(0.5).max(0.1);
-                if self.graph.get_update_amt() != 0 {
-                    self.graph.update_graph_edge_stresses(Some(&mut self.vertice_update_list));
-                    let vertex_buffer = glium::VertexBuffer::new(&self.display, &self.vertice_update_list).unwrap();
-                    let mut frame_buf = SimpleFrameBuffer::new(&self.display, &self.crack_texture)
-                        .expect("failed to create frame buffer");
-
-                    frame_buf.draw(&vertex_buffer, &indices, &self.crack_shader_program, &uniform! {}, &Default::default())
-                        .expect("failed to draw frame");
-
-                    let mut frame_buf = SimpleFrameBuffer::new(&self.display, &self.bloom_texture)
-                        .expect("failed to create frame buffer");
-
-                    frame_buf.draw(&default_vbo, &indices, &self.bloom_shader_program, &uniform! {crack_texture: &self.crack_texture, scale: 1920_f32}, &Default::default())
-                        .expect("failed to draw frame");
-                }
 
                 let mut target = self.display.draw();
-                //target.draw(&vertex_buffer, &indices, &self.screen_shader_program, &uniform! {crack_texture: &self.texture, crack_color: self.crack_color}, &Default::default()).unwrap();
-                target.draw(&default_vbo, &indices, &self.screen_shader_program, &uniform! {crack_texture: &self.crack_texture, bloom_texture: &self.bloom_texture, crack_color: self.crack_color, bloom_mix: self.bloom_mix, fade_amt: self.fade_amt * self.fade_amt}, &Default::default())
-                    .expect("failed to draw frame");
+                self.draw_layers(&mut target, &bloom_mixes, crack_color);
                 target.finish().unwrap();
-                self.vertice_update_list = Vec::with_capacity(256);
-                self.graph.update_graph_stress_propagation();
+                if let Some(capture) = &self.capture {
+                    self.draw_layers(&mut capture.surface(&self.display), &bloom_mixes, crack_color);
+                }
+                if let Some(capture) = self.capture.as_mut() {
+                    capture.capture();
+                }
+                for ice_layer in self.ice.layers.iter_mut() {
+                    ice_layer.vertices.clear();
+                }
+                self.ice.propagate();
             }
             let next_frame_time = std::time::Instant::now() +
                 std::time::Duration::from_nanos(16_666_667);
             *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
             
 
-            match ev {
-                glutin::event::Event::WindowEvent { event, .. } => match event {
+            // the event loop ends the process without dropping anything, so save the rest of the recording now
+            if let glutin::event::Event::LoopDestroyed = ev {
+                if let Some(capture) = self.capture.take() {
+                    capture.finish();
+                }
+                return;
+            }
+            if let glutin::event::Event::WindowEvent { event, .. } = ev {
+                match event {
                     glutin::event::WindowEvent::CloseRequested => {
                         *control_flow = glutin::event_loop::ControlFlow::Exit;
-                        return;
                     },
                     glutin::event::WindowEvent::KeyboardInput { input, .. } => {
                         if let Some(kc) = input.virtual_keycode {
@@ -205,27 +246,30 @@ impl SimulationScreen {
                                 // handle esc
                                 VirtualKeyCode::Escape => {
                                     *control_flow = glutin::event_loop::ControlFlow::Exit;
-                                    return;
                                 },
                                 // handle fullscreen
-                                VirtualKeyCode::F11 => {
-                                    if input.state == ElementState::Pressed {
-                                        let window = self.display.gl_window();
-                                        let window = window.window();
-                                        if window.fullscreen().is_some() {
-                                            self.display.gl_window().window().set_fullscreen(None)
-                                        } else {
-                                            self.display.gl_window().window().set_fullscreen(Some(Fullscreen::Borderless(None)))
-                                        }
+                                VirtualKeyCode::F11 if input.state == ElementState::Pressed => {
+                                    let window = self.display.gl_window();
+                                    let window = window.window();
+                                    if window.fullscreen().is_some() {
+                                        self.display.gl_window().window().set_fullscreen(None)
+                                    } else {
+                                        self.display.gl_window().window().set_fullscreen(Some(Fullscreen::Borderless(None)))
                                     }
                                 }
+                                // undo the last hit, or go back 5 seconds
+                                VirtualKeyCode::Back if input.state == ElementState::Pressed => {
+                                    osc::command(&self.crack_update_list, SimCommand::Rewind(Rewind::Impacts(1)));
+                                }
+                                VirtualKeyCode::Left if input.state == ElementState::Pressed => {
+                                    osc::command(&self.crack_update_list, SimCommand::Rewind(Rewind::Seconds(5.0)));
+                                }
                                 _ => (),
                             }   
                         }
                     }
-                    _ => return,
-                },
-                _ => (),
+                    _ => (),
+                }
             }
         });
     }
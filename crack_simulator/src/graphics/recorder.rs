@@ -0,0 +1,120 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use super::software::write_rgba_png;
+
+/// number of threads encoding pngs
+const WORKERS: usize = 4;
+/// frames waiting to be encoded before `record` blocks
+const MAX_QUEUED: usize = 32;
+
+struct Frame {
+    number: usize,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Saves frames as numbered pngs (`frame_000000.png`, `frame_000001.png` ...) in the background
+pub struct FrameRecorder {
+    next: usize,
+    sender: Option<SyncSender<Frame>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl FrameRecorder {
+    /// Start recording into `dir`, creating it if it doesn't exist
+    pub fn new(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(dir)?;
+        let (sender, receiver) = sync_channel::<Frame>(MAX_QUEUED);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..WORKERS)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let dir = dir.to_path_buf();
+                std::thread::spawn(move || Self::work(&dir, &receiver))
+            })
+            .collect();
+        Ok(Self {
+            next: 0,
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    fn work(dir: &Path, receiver: &Mutex<Receiver<Frame>>) {
+        loop {
+            let frame = match receiver.lock().unwrap().recv() {
+                Ok(f) => f,
+                Err(_) => return,
+            };
+            let path = Self::frame_path(dir, frame.number);
+            if let Err(e) = write_rgba_png(&frame.pixels, frame.width, frame.height, &path) {
+                println!("failed to save {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    pub fn frame_path(dir: &Path, number: usize) -> PathBuf {
+        dir.join(format!("frame_{:06}.png", number))
+    }
+
+    /// Queue rgba pixels, top row first, to be saved as the next frame
+    pub fn record(&mut self, pixels: Vec<u8>, width: u32, height: u32) {
+        let frame = Frame {
+            number: self.next,
+            width,
+            height,
+            pixels,
+        };
+        self.next += 1;
+        if let Some(s) = &self.sender {
+            s.send(frame).expect("frame recorder stopped");
+        }
+    }
+
+    /// Wait for every queued frame to be saved
+    pub fn finish(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.sender = None;
+        for w in self.workers.drain(..) {
+            w.join().expect("frame recorder thread panicked");
+        }
+    }
+}
+
+impl Drop for FrameRecorder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_recorder() {
+        let dir = std::env::temp_dir().join(format!("frame_recorder_test_{}", std::process::id()));
+        let mut recorder = FrameRecorder::new(&dir).unwrap();
+        for i in 0..5 {
+            recorder.record(vec![i * 50; 2 * 2 * 4], 2, 2);
+        }
+        recorder.finish();
+
+        for i in 0..5 {
+            let decoder = png::Decoder::new(std::fs::File::open(FrameRecorder::frame_path(&dir, i)).unwrap());
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            assert!(buf.iter().all(|p| *p == i as u8 * 50));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! CPU version of the crack, bloom and screen shaders.
+//! Images are kept the same way OpenGL keeps textures, with row 0 at the bottom of the screen.
+//! sRGB conversion and 8 bit rounding of the intermediate textures are left out.
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+
+use super::BLOOM_SCALE;
+use super::vertex::Vertex;
+
+/// the crack texture is drawn at this many times the resolution of the screen
+const SUPERSAMPLING: u32 = 4;
+
+lazy_static!{
+    /// the bloom kernel, read out of the shader so the two can't disagree
+    static ref BLOOM_KERNEL: Vec<f32> = parse_kernel(include_str!("./shaders/bloom_fs.glsl"));
+}
+
+fn parse_kernel(shader: &str) -> Vec<f32> {
+    let start = shader.find("float kernel[").expect("bloom shader has no kernel");
+    let body = &shader[start..];
+    let body = &body[body.find("](").unwrap() + 2..body.find(");").unwrap()];
+    body.split(',')
+        .map(|n| n.trim().parse::<f32>().expect("bad number in bloom kernel"))
+        .collect()
+}
+
+/// A single channel image, sampled the way the shaders sample textures
+struct Image {
+    width: u32,
+    height: u32,
+    data: Vec<f32>,
+}
+
+impl Image {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0_f32; width as usize * height as usize],
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> f32 {
+        self.data[y as usize * self.width as usize + x as usize]
+    }
+
+    /// linear filtering with mirrored repeat wrapping, the default glium sampler for textures without mipmaps
+    fn sample(&self, u: f32, v: f32) -> f32 {
+        let mirror = |i: i64, size: u32| -> u32 {
+            let size = size as i64;
+            let i = i.rem_euclid(2 * size);
+            (if i < size { i } else { 2 * size - 1 - i }) as u32
+        };
+        let s = u * self.width as f32 - 0.5;
+        let t = v * self.height as f32 - 0.5;
+        let (x0, y0) = (s.floor(), t.floor());
+        let (fx, fy) = (s - x0, t - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let (xa, xb) = (mirror(x0, self.width), mirror(x0 + 1, self.width));
+        let (ya, yb) = (mirror(y0, self.height), mirror(y0 + 1, self.height));
+        let top = self.get(xa, ya) * (1_f32 - fx) + self.get(xb, ya) * fx;
+        let bottom = self.get(xa, yb) * (1_f32 - fx) + self.get(xb, yb) * fx;
+        top * (1_f32 - fy) + bottom * fy
+    }
+}
+
+/// The crack and bloom textures of a layer, drawn on the CPU
+pub struct SoftwareLayer {
+    pub width: u32,
+    pub height: u32,
+    crack: Image,
+    bloom: Image,
+    /// pixels `[x0, y0, x1, y1)` whose bloom is out of date
+    dirty: Option<[u32; 4]>,
+}
+
+impl SoftwareLayer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            crack: Image::new(width * SUPERSAMPLING, height * SUPERSAMPLING),
+            bloom: Image::new(width, height),
+            dirty: None,
+        }
+    }
+
+    /// Wipe every crack off
+    pub fn clear(&mut self) {
+        self.crack.data.fill(0_f32);
+        self.bloom.data.fill(0_f32);
+        self.dirty = None;
+    }
+
+    /// Draw a list of crack triangles, the same list `Graph::update_graph_edge_stresses` fills.
+    /// The bloom isn't updated until `update_bloom` is called
+    pub fn draw_cracks(&mut self, vertices: &[Vertex]) {
+        if vertices.len() < 3 {
+            return;
+        }
+        let (w, h) = (self.crack.width as f32, self.crack.height as f32);
+        // bounding box of everything drawn, in screen pixels
+        let mut bounds = [f32::INFINITY, f32::INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY];
+        for t in vertices.chunks_exact(3) {
+            let p: Vec<[f32; 2]> = t.iter()
+                .map(|v| [(v.position[0] + 1_f32) / 2_f32 * w, (v.position[1] + 1_f32) / 2_f32 * h])
+                .collect();
+            self.fill_triangle([p[0], p[1], p[2]]);
+            for q in p {
+                bounds[0] = bounds[0].min(q[0] / SUPERSAMPLING as f32);
+                bounds[1] = bounds[1].min(q[1] / SUPERSAMPLING as f32);
+                bounds[2] = bounds[2].max(q[0] / SUPERSAMPLING as f32);
+                bounds[3] = bounds[3].max(q[1] / SUPERSAMPLING as f32);
+            }
+        }
+
+        // the bloom only changes within reach of the kernel
+        // kernel steps are width / BLOOM_SCALE pixels across and height / BLOOM_SCALE pixels up, plus one for the
+        // linear filtering
+        let half_kernel = (BLOOM_KERNEL.len() as f32).sqrt().floor() / 2_f32;
+        let reach_x = (half_kernel * self.width as f32 / BLOOM_SCALE).ceil() + 1_f32;
+        let reach_y = (half_kernel * self.height as f32 / BLOOM_SCALE).ceil() + 1_f32;
+        let x0 = (bounds[0].floor() - reach_x).max(0_f32) as u32;
+        let y0 = (bounds[1].floor() - reach_y).max(0_f32) as u32;
+        let x1 = ((bounds[2].ceil() + reach_x).max(0_f32) as u32).min(self.width);
+        let y1 = ((bounds[3].ceil() + reach_y).max(0_f32) as u32).min(self.height);
+        self.dirty = Some(match self.dirty {
+            Some(d) => [d[0].min(x0), d[1].min(y0), d[2].max(x1), d[3].max(y1)],
+            None => [x0, y0, x1, y1],
+        });
+    }
+
+    /// Recompute the bloom around everything drawn since the last update. Same as bloom_fs.glsl
+    pub fn update_bloom(&mut self) {
+        let Some([x0, y0, x1, y1]) = self.dirty.take() else {
+            return;
+        };
+        let kernel_size = (BLOOM_KERNEL.len() as f32).sqrt() as i32;
+        let scale = BLOOM_SCALE;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let u = (x as f32 + 0.5) / self.width as f32;
+                let v = (y as f32 + 0.5) / self.height as f32;
+                let mut sum = 0_f32;
+                let mut count = 0;
+                for i in -kernel_size / 2..=kernel_size / 2 {
+                    for j in -kernel_size / 2..=kernel_size / 2 {
+                        sum += BLOOM_KERNEL[count] * self.crack.sample(u + j as f32 / scale, v + i as f32 / scale);
+                        count += 1;
+                    }
+                }
+                self.bloom.data[y as usize * self.width as usize + x as usize] = sum;
+            }
+        }
+    }
+
+    /// Fill the texels whose centers are inside a triangle given in texel coordinates.
+    /// Texels on a shared edge are only drawn by one of the triangles, like on the GPU
+    fn fill_triangle(&mut self, mut p: [[f32; 2]; 3]) {
+        let edge = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+        let area = edge(p[0], p[1], p[2]);
+        if area == 0_f32 {
+            return;
+        }
+        if area < 0_f32 {
+            p.swap(1, 2);
+        }
+        // top-left rule
+        let owns = |a: [f32; 2], b: [f32; 2]| (a[1] == b[1] && b[0] < a[0]) || b[1] > a[1];
+        let owned = [owns(p[1], p[2]), owns(p[2], p[0]), owns(p[0], p[1])];
+
+        let min_x = p.iter().map(|q| q[0]).fold(f32::INFINITY, f32::min).floor().max(0_f32) as u32;
+        let min_y = p.iter().map(|q| q[1]).fold(f32::INFINITY, f32::min).floor().max(0_f32) as u32;
+        let max_x = (p.iter().map(|q| q[0]).fold(f32::NEG_INFINITY, f32::max).ceil().max(0_f32) as u32).min(self.crack.width);
+        let max_y = (p.iter().map(|q| q[1]).fold(f32::NEG_INFINITY, f32::max).ceil().max(0_f32) as u32).min(self.crack.height);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let c = [x as f32 + 0.5, y as f32 + 0.5];
+                let w = [edge(p[1], p[2], c), edge(p[2], p[0], c), edge(p[0], p[1], c)];
+                if (0..3).all(|k| w[k] > 0_f32 || (w[k] == 0_f32 && owned[k])) {
+                    self.crack.data[y as usize * self.crack.width as usize + x as usize] = 1_f32;
+                }
+            }
+        }
+    }
+}
+
+/// A layer along with the uniforms the screen shader gets for it
+pub struct CompositeLayer<'a> {
+    pub layer: &'a SoftwareLayer,
+    pub crack_color: [f32; 4],
+    pub bloom_mix: f32,
+}
+
+/// Same as screen_fs.glsl, with the layers added on top of each other.
+/// `fade_amt` is the value of the uniform. Returns rgba pixels with the top row first.
+/// Call `update_bloom` on the layers first
+pub fn composite(layers: &[CompositeLayer], fade_amt: f32, width: u32, height: u32) -> Vec<u8> {
+    debug_assert!(layers.iter().all(|l| l.layer.dirty.is_none()), "bloom is out of date");
+    let mut out = vec![0_u8; width as usize * height as usize * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let u = (x as f32 + 0.5) / width as f32;
+            let v = (y as f32 + 0.5) / height as f32;
+            // the screen is cleared to opaque black
+            let mut color = [0_f32, 0_f32, 0_f32, 1_f32];
+            for l in layers {
+                let base = (l.layer.crack.sample(u, v) + l.bloom_mix * l.layer.bloom.sample(u, v)).min(1_f32);
+                for (c, tint) in color.iter_mut().zip(l.crack_color) {
+                    *c += base * tint * fade_amt;
+                }
+            }
+            let i = ((height - 1 - y) as usize * width as usize + x as usize) * 4;
+            for k in 0..4 {
+                out[i + k] = (color[k].clamp(0_f32, 1_f32) * 255_f32).round() as u8;
+            }
+        }
+    }
+    out
+}
+
+/// Save rgba pixels from `composite` as a png
+pub fn write_rgba_png(pixels: &[u8], width: u32, height: u32, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_kernel() {
+        assert_eq!(BLOOM_KERNEL.len(), 81);
+        let sum: f32 = BLOOM_KERNEL.iter().sum();
+        assert!((sum - 1_f32).abs() < 0.01);
+        assert_eq!(BLOOM_KERNEL[40], 0.1466);
+    }
+
+    #[test]
+    fn test_shared_edges_drawn_once() {
+        let mut layer = SoftwareLayer::new(4, 4);
+        // two triangles making up a square, with the diagonal through texel centers
+        layer.fill_triangle([[0.5, 0.5], [8.5, 0.5], [8.5, 8.5]]);
+        let first: f32 = layer.crack.data.iter().sum();
+        layer.fill_triangle([[0.5, 0.5], [8.5, 8.5], [0.5, 8.5]]);
+        let both: f32 = layer.crack.data.iter().sum();
+        // 8 x 8 texel centers are inside or on the border of the square
+        assert!(first > 0_f32 && both == 64_f32);
+    }
+
+    #[test]
+    fn test_software_render() {
+        // kernel steps are a pixel across at this width
+        let (width, height) = (BLOOM_SCALE as u32, BLOOM_SCALE as u32 / 2);
+        let mut layer = SoftwareLayer::new(width, height);
+        let full_screen: Vec<Vertex> = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]]
+            .iter()
+            .map(|p| (*p).into())
+            .collect();
+        // a small square in the middle of the screen
+        let square: Vec<Vertex> = full_screen.iter().map(|v| [v.position[0] * 0.25, v.position[1] * 0.25].into()).collect();
+        layer.draw_cracks(&square);
+        layer.update_bloom();
+
+        let layers = [CompositeLayer { layer: &layer, crack_color: [0.5, 1.0, 1.0, 1.0], bloom_mix: 0.5 }];
+        let pixels = composite(&layers, 1.0, width, height);
+        let at = |x: usize, y: usize| &pixels[(y * width as usize + x) * 4..(y * width as usize + x) * 4 + 4];
+        // inside the square the crack is solid, tinted by the crack color
+        let (cx, cy) = (width as usize / 2, height as usize / 2);
+        assert_eq!(at(cx, cy), &[128, 255, 255, 255]);
+        // the bloom glows a little past the edge of the square
+        let glow = at(cx - cx / 4 - 2, cy);
+        assert!(glow[1] > 0 && glow[1] < 255, "{:?}", glow);
+        // and not at all far away from it
+        assert_eq!(at(0, 0), &[0, 0, 0, 255]);
+
+        // fully faded out is black
+        let pixels = composite(&layers, 0.0, width, height);
+        assert!(pixels.chunks(4).all(|p| p == [0, 0, 0, 255]));
+    }
+}
@@ -1,6 +1,7 @@
 use glium::implement_vertex;
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Vertex {
     pub position: [f32; 2],
 }
@@ -13,4 +14,16 @@ impl From<[f32; 2]> for Vertex {
     }
 }
 
+impl Vertex {
+    /// Convert a pixel of a `width` x `height` screen, with the origin in the top left, into ndc
+    pub fn from_pixel(p: [f32; 2], width: u32, height: u32) -> Self {
+        [p[0] / width as f32 * 2_f32 - 1_f32, 1_f32 - p[1] / height as f32 * 2_f32].into()
+    }
+
+    /// Convert ndc into a pixel of a `width` x `height` screen, with the origin in the top left
+    pub fn to_pixel(self, width: u32, height: u32) -> [f32; 2] {
+        [(self.position[0] + 1_f32) / 2_f32 * width as f32, (1_f32 - self.position[1]) / 2_f32 * height as f32]
+    }
+}
+
 implement_vertex!(Vertex, position);
\ No newline at end of file
@@ -0,0 +1,119 @@
+//! Runs the show without a window, for machines with no display. It takes the same commands from osc, the console
+//! and replays as the window does and runs the same frames, 60 a second. Frames are only drawn, with the software
+//! renderer, while they're being recorded.
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::simulation::ice::Ice;
+use crate::simulation::SimCommand;
+use super::recorder::FrameRecorder;
+use super::software::{composite, CompositeLayer, SoftwareLayer};
+
+pub struct Windowless {
+    ice: Ice,
+    crack_update_list: Arc<Mutex<VecDeque<SimCommand>>>,
+    /// one for each layer of `ice`, while recording
+    renderers: Vec<SoftwareLayer>,
+    recorder: Option<FrameRecorder>,
+}
+
+impl Windowless {
+    pub fn new(ice: Ice, crack_update_list: Arc<Mutex<VecDeque<SimCommand>>>) -> Self {
+        Self {
+            ice,
+            crack_update_list,
+            renderers: Vec::new(),
+            recorder: None,
+        }
+    }
+
+    /// Draw every frame from now on and save it as a numbered png in `dir`.
+    /// Drawing on the CPU is slow, so at high resolutions the show runs at less than 60 frames a second
+    pub fn record_frames(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        self.recorder = Some(FrameRecorder::new(dir)?);
+        self.renderers = self.ice.layers.iter().map(|_| SoftwareLayer::new(self.ice.width, self.ice.height)).collect();
+        self.ice.redraw = true;
+        Ok(())
+    }
+
+    /// Run a frame every 60th of a second, forever
+    pub fn run(mut self) {
+        println!("running without a window");
+        let frame_time = Duration::from_nanos(16_666_667);
+        let mut next = Instant::now();
+        self.ice.keyframe();
+        loop {
+            self.frame();
+            next += frame_time;
+            match next.checked_duration_since(Instant::now()) {
+                Some(wait) => std::thread::sleep(wait),
+                // fell behind, don't try to catch up
+                None => next = Instant::now(),
+            }
+        }
+    }
+
+    fn frame(&mut self) {
+        self.ice.handle_commands(&self.crack_update_list);
+        let crack_color = self.ice.crack_color();
+        let bloom_mixes = self.ice.bloom_mixes();
+        self.ice.update_stresses();
+        if let Some(recorder) = self.recorder.as_mut() {
+            for (r, layer) in self.renderers.iter_mut().zip(&self.ice.layers) {
+                if self.ice.redraw {
+                    r.clear();
+                    r.draw_cracks(&layer.graph.crack_triangles());
+                } else {
+                    r.draw_cracks(&layer.vertices);
+                }
+                r.update_bloom();
+            }
+            let layers: Vec<CompositeLayer> = self.renderers.iter()
+                .zip(&self.ice.layers)
+                .zip(bloom_mixes)
+                .map(|((r, layer), bloom_mix)| CompositeLayer { layer: r, crack_color: layer.settings.tint(crack_color), bloom_mix })
+                .collect();
+            let (width, height) = (self.ice.width, self.ice.height);
+            recorder.record(composite(&layers, self.ice.fade_amt * self.ice.fade_amt, width, height), width, height);
+        }
+        self.ice.redraw = false;
+        for layer in self.ice.layers.iter_mut() {
+            layer.vertices.clear();
+        }
+        self.ice.propagate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::graph::BoundaryMode;
+    use crate::simulation::{Impact, Region};
+
+    #[test]
+    fn test_windowless() {
+        let dir = std::env::temp_dir().join(format!("windowless_test_{}", std::process::id()));
+        let commands = Arc::new(Mutex::new(VecDeque::new()));
+        let ice = Ice::new(64, 48, BoundaryMode::Free, Vec::new(), Some(3));
+        let mut show = Windowless::new(ice, Arc::clone(&commands));
+        show.record_frames(&dir).unwrap();
+        commands.lock().unwrap().push_back(SimCommand::Impact(Impact { position: Some([0.5, 0.5]), region: Region::Anywhere, stress: 200.0, direction: None }));
+        for _ in 0..30 {
+            show.frame();
+        }
+        assert!(commands.lock().unwrap().is_empty());
+        assert!(show.ice.stats(0).cracked_edges > 0);
+        show.recorder.take().unwrap().finish();
+
+        // the cracks made it into the last frame
+        let decoder = png::Decoder::new(std::fs::File::open(FrameRecorder::frame_path(&dir, 29)).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        assert!(buf.chunks(4).any(|p| p[2] > 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
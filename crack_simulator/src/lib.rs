@@ -0,0 +1,36 @@
+use lazy_static::lazy_static;
+use std::sync::RwLock;
+use std::sync::atomic::AtomicUsize;
+use std::time::Instant;
+
+use crate::simulation::graph::stats::CrackStats;
+use crate::status::Status;
+
+pub mod simulation;
+pub mod graphics;
+pub mod osc;
+pub mod settings;
+pub mod accel;
+pub mod calibration;
+pub mod cli;
+pub mod sensors;
+pub mod crack_messages;
+pub mod replay;
+pub mod schedule;
+pub mod net;
+pub mod status;
+pub mod targets;
+pub mod tcp;
+
+/// number of frames the simulation has drawn
+pub static FRAME: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static!{
+    pub static ref REPEAT_AMT: RwLock<usize> = RwLock::new(0);
+    pub static ref TIMER: RwLock<Instant> = RwLock::new(Instant::now());
+    pub static ref TOTAL_TIME: usize = 60 * 4;
+    /// how broken the surface was the last time the simulation measured it
+    pub static ref CRACK_STATS: RwLock<CrackStats> = RwLock::new(CrackStats::default());
+    /// the parts of the status only the simulation knows: `paused`, `fps` and `queue_depth`. Updated every frame
+    pub static ref SIM_STATUS: RwLock<Status> = RwLock::new(Status::default());
+}
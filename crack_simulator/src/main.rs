@@ -1,69 +1,130 @@
-use graphics::SimulationScreen;
-use lazy_static::lazy_static;
-use osc::read_watch_task;
-use std::{sync::{Arc, Mutex, RwLock}, collections::VecDeque};
+use std::{sync::{Arc, Mutex}, collections::VecDeque};
 use std::io;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Instant;
 use rand::random;
 
-use crate::osc::{CrackNotifier, time_controller};
-
-mod simulation;
-mod graphics;
-mod osc;
-
-lazy_static!{
-    static ref REPEAT_AMT: RwLock<usize> = RwLock::new(0);
-    static ref TIMER: RwLock<Instant> = RwLock::new(Instant::now());
-    static ref TOTAL_TIME: usize = 60 * 4;
-}
+use crack_simulator::calibration::CalibrateRequest;
+use crack_simulator::cli::{Options, USAGE};
+use crack_simulator::crack_messages::report_cracks;
+use crack_simulator::graphics::SimulationScreen;
+use crack_simulator::graphics::windowless::Windowless;
+use crack_simulator::osc::{self, Show};
+use crack_simulator::replay;
+use crack_simulator::settings::{read_settings, set_settings_path};
+use crack_simulator::simulation::ice::Ice;
+use crack_simulator::simulation::mask::Mask;
+use crack_simulator::simulation::scratches::load_scratches;
+use crack_simulator::simulation::SimCommand;
 
 fn main() {
+    let options = Options::from_env().unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Some(path) = &options.settings {
+        set_settings_path(path);
+    }
+    let mut settings = read_settings().expect("failed to read settings file");
+    if let Err(e) = options.apply(&mut settings) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+
     // initialize simulation and message passing
-    let crack_update_buf: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::with_capacity(20)));
-    let simulation = SimulationScreen::new(1920, 1080, Arc::clone(&crack_update_buf));
+    let crack_update_buf: Arc<Mutex<VecDeque<SimCommand>>> = Arc::new(Mutex::new(VecDeque::with_capacity(20)));
+    let (width, height) = options.resolution();
+    let mut ice = Ice::new(width, height, settings.boundary, settings.layers, settings.seed);
+    ice.set_stress_mapping(settings.stress_mapping);
+    if let Some(mask_path) = settings.mask {
+        let mask = Mask::load(Path::new(&mask_path))
+            .expect("failed to load mask");
+        ice.set_mask(&mask);
+    }
+    if let Some(scratch_path) = settings.scratches {
+        let scratches = load_scratches(Path::new(&scratch_path))
+            .expect("failed to load scratches");
+        ice.seed_scratches(&scratches);
+    }
+    let cracks = settings.crack_messages.then(|| ice.subscribe(0));
+    if let Some(dir) = settings.record_input {
+        let path = replay::start_logging(Path::new(&dir))
+            .expect("failed to start logging input");
+        println!("logging input to {}", path.display());
+    }
+    let replay_path = settings.replay;
+    let show = Show::new(Arc::clone(&crack_update_buf));
+    osc::listen(Arc::clone(&show));
+    if let Some(cracks) = cracks {
+        report_cracks(cracks, Arc::clone(&show));
+    }
     
     // spawn io handler
     std::thread::spawn(move || {
-        let mut input = String::new();
-        while input.trim() != "start" {
-            input = String::new();
-            if let Err(e) = io::stdin().read_line(&mut input) {
-                println!("err reading line: {:?}", e);
+        if let Some(path) = replay_path {
+            if let Err(e) = replay::replay(Path::new(&path), &show) {
+                println!("failed to replay {}: {}", path, e);
             }
+            return;
         }
-        let crack_notifier = Arc::new(Mutex::new(CrackNotifier::new(Arc::clone(&crack_update_buf))
-            .expect("failed to create crack_notifier")));
-        let mut t = (*TIMER).write().unwrap();
-        *t = Instant::now();
-        drop(t);
-
-        let crack_notifier_tmp = Arc::clone(&crack_notifier);
-        std::thread::spawn(move || {
-            time_controller(crack_notifier_tmp);
-        });
-
-        let crack_notifier_tmp = Arc::clone(&crack_notifier);
-        std::thread::spawn(move || {
-            read_watch_task(crack_notifier_tmp);
-        });
-        input = String::new();
-        while input.trim() != "stop" {
-            input = String::new();
-            if let Err(e) = io::stdin().read_line(&mut input) {
-                println!("err reading line: {:?}", e);
+        let mut input = String::new();
+        loop {
+            input.clear();
+            match io::stdin().read_line(&mut input) {
+                // no console, the show is run over osc
+                Ok(0) => return,
+                Ok(_) => (),
+                Err(e) => println!("err reading line: {:?}", e),
+            }
+            let line = input.trim();
+            if line == "start" {
+                show.start();
+                continue;
             }
-            if let Ok(f) = f32::from_str(input.trim()) {
-                CrackNotifier::send_cloned_crack(&crack_notifier, f);
-            } else if input.trim() == "" {
-                CrackNotifier::send_cloned_crack(&crack_notifier, random::<f32>() * 500_f32 + 500_f32);
+            if let Some(args) = line.strip_prefix("calibrate") {
+                match CalibrateRequest::parse(args) {
+                    Ok(r) => show.calibrate(r),
+                    Err(e) => println!("{}", e),
+                }
+                continue;
+            }
+            let Some(crack_notifier) = show.notifier() else {
+                continue;
+            };
+            if line == "stop" {
+                println!("stopping simulation...");
+                show.stop();
+                break;
+            } else if let Ok(f) = f32::from_str(line) {
+                osc::manual_crack(&crack_notifier, f);
+            } else if line.is_empty() {
+                osc::manual_crack(&crack_notifier, random::<f32>() * 500_f32 + 500_f32);
             }
         }
-        println!("stopping simulation...");
-        osc::stop(crack_notifier);
     });
 
     // run simulation
-    simulation.run();
-}
\ No newline at end of file
+    let record_frames = settings.record_frames.map(PathBuf::from);
+    if options.headless {
+        let mut simulation = Windowless::new(ice, crack_update_buf);
+        if let Some(dir) = record_frames {
+            simulation.record_frames(&dir)
+                .expect("failed to start recording frames");
+        }
+        simulation.run();
+    } else {
+        let mut simulation = SimulationScreen::new(ice, crack_update_buf);
+        if options.fullscreen {
+            simulation.set_fullscreen(true);
+        }
+        if let Some(dir) = record_frames {
+            simulation.record_frames(&dir)
+                .expect("failed to start recording frames");
+        }
+        simulation.run();
+    }
+}
@@ -0,0 +1,540 @@
+//! Keeping the show going when the network doesn't.
+//!
+//! Nothing that goes wrong on the network stops the show. Sockets that stop working are bound again, targets that
+//! go away are tried again with back-off until they come back, and errors that keep happening are only logged every
+//! `LOG_INTERVAL`.
+//!
+//! Addresses in the settings can be ipv4 or ipv6 addresses, or hostnames, which are looked up again with back-off
+//! until they're found. An empty `src_ip`, `0.0.0.0` or `::` listens on every interface, so the same settings work
+//! whatever address the network hands out. Multicast groups in `multicast` are joined on every listening socket, and
+//! targets can be multicast groups too.
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// how long the same error stays quiet after being logged
+pub const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What went wrong sending or receiving
+#[derive(Debug)]
+pub enum NetError {
+    Bind { addr: SocketAddr, source: io::Error },
+    /// a hostname that couldn't be looked up
+    Resolve { host: String, source: io::Error },
+    Connect { to: SocketAddr, source: io::Error },
+    Send { to: SocketAddr, source: io::Error },
+    Receive(io::Error),
+    /// a packet that isn't osc
+    Decode { from: SocketAddr, error: rosc::OscError },
+    Encode(rosc::OscError),
+    /// a target that couldn't be reached or looked up isn't being tried again yet
+    Backoff { to: String, retry_in: Duration },
+}
+
+impl NetError {
+    /// What kind of error it is and where, so errors like it can be kept quiet
+    pub fn key(&self) -> String {
+        match self {
+            Self::Bind { addr, .. } => format!("bind {}", addr),
+            Self::Resolve { host, .. } => format!("resolve {}", host),
+            Self::Connect { to, .. } => format!("connect {}", to),
+            Self::Backoff { to, .. } => format!("backoff {}", to),
+            Self::Send { to, .. } => format!("send {}", to),
+            Self::Receive(e) => format!("receive {:?}", e.kind()),
+            Self::Decode { from, .. } => format!("decode {}", from),
+            Self::Encode(_) => "encode".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Bind { addr, source } => write!(f, "failed to bind to {}: {}", addr, source),
+            Self::Resolve { host, source } => write!(f, "failed to look up {}: {}", host, source),
+            Self::Connect { to, source } => write!(f, "failed to connect to {}: {}", to, source),
+            Self::Send { to, source } => write!(f, "failed to send to {}: {}", to, source),
+            Self::Receive(e) => write!(f, "failed to receive: {}", e),
+            Self::Decode { from, error } => write!(f, "bad osc packet from {}: {}", from, error),
+            Self::Encode(e) => write!(f, "failed to encode osc: {}", e),
+            Self::Backoff { to, retry_in } => write!(f, "{} is unreachable, trying again in {:.1}s", to, retry_in.as_secs_f32()),
+        }
+    }
+}
+
+impl std::error::Error for NetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Bind { source, .. } | Self::Resolve { source, .. } | Self::Connect { source, .. } | Self::Send { source, .. } | Self::Receive(source) => Some(source),
+            Self::Decode { error, .. } | Self::Encode(error) => Some(error),
+            Self::Backoff { .. } => None,
+        }
+    }
+}
+
+/// Errors that go away by themselves, like the reset some systems report after a reply went nowhere
+pub fn is_transient(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Logs errors, but each kind only once every `interval`, with a count of the ones kept quiet in between
+pub struct LogLimiter {
+    interval: Duration,
+    /// when each kind was last logged, and how many have been kept quiet since
+    last: HashMap<String, (Instant, usize)>,
+}
+
+impl LogLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last: HashMap::new() }
+    }
+
+    /// Returns whether it was logged
+    pub fn log(&mut self, e: &NetError) -> bool {
+        self.log_at(e, Instant::now())
+    }
+
+    fn log_at(&mut self, e: &NetError, now: Instant) -> bool {
+        match self.last.get_mut(&e.key()) {
+            Some((at, quiet)) if now.duration_since(*at) < self.interval => {
+                *quiet += 1;
+                false
+            }
+            Some((at, quiet)) => {
+                println!("{} ({} more like it)", e, quiet);
+                *at = now;
+                *quiet = 0;
+                true
+            }
+            None => {
+                println!("{}", e);
+                self.last.insert(e.key(), (now, 0));
+                true
+            }
+        }
+    }
+}
+
+impl Default for LogLimiter {
+    fn default() -> Self {
+        Self::new(LOG_INTERVAL)
+    }
+}
+
+/// Waits longer and longer between tries, up to `max`
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    delay: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self { min, max, delay: min, retry_at: None }
+    }
+
+    /// How long until it's worth trying again. Zero if it is now
+    pub fn wait(&self, now: Instant) -> Duration {
+        self.retry_at.map_or(Duration::ZERO, |t| t.saturating_duration_since(now))
+    }
+
+    pub fn failed(&mut self, now: Instant) {
+        self.retry_at = Some(now + self.delay);
+        self.delay = (self.delay * 2).min(self.max);
+    }
+
+    pub fn succeeded(&mut self) {
+        self.delay = self.min;
+        self.retry_at = None;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(250), Duration::from_secs(10))
+    }
+}
+
+/// Keep trying `bind` with back-off until it works
+pub fn bind_with_backoff<T>(addr: SocketAddr, bind: impl Fn(SocketAddr) -> io::Result<T>) -> T {
+    let mut backoff = Backoff::default();
+    let mut log = LogLimiter::default();
+    loop {
+        match bind(addr) {
+            Ok(s) => return s,
+            Err(source) => {
+                log.log(&NetError::Bind { addr, source });
+                backoff.failed(Instant::now());
+                std::thread::sleep(backoff.wait(Instant::now()));
+            }
+        }
+    }
+}
+
+fn strip_brackets(host: &str) -> &str {
+    host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host)
+}
+
+/// Split `host:port`, where the host can be a name, an ipv4 address, or an ipv6 address in brackets
+pub fn split_host_port(s: &str) -> Option<(&str, u16)> {
+    let (host, port) = s.trim().rsplit_once(':')?;
+    if host.contains(':') && !host.starts_with('[') {
+        return None;
+    }
+    Some((strip_brackets(host), port.parse().ok()?))
+}
+
+/// Parse an address or look up a hostname. An empty host means every interface
+pub fn resolve(host: &str, port: u16) -> Result<SocketAddr, NetError> {
+    let host = strip_brackets(host.trim());
+    if host.is_empty() {
+        return Ok(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)));
+    }
+    if let Ok(ip) = IpAddr::from_str(host) {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    (host, port).to_socket_addrs()
+        .and_then(|mut addrs| addrs.next().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses")))
+        .map_err(|source| NetError::Resolve { host: host.to_string(), source })
+}
+
+/// `resolve`, trying again with back-off until it works
+pub fn resolve_with_backoff(host: &str, port: u16) -> SocketAddr {
+    let mut backoff = Backoff::default();
+    let mut log = LogLimiter::default();
+    loop {
+        match resolve(host, port) {
+            Ok(a) => return a,
+            Err(e) => {
+                log.log(&e);
+                backoff.failed(Instant::now());
+                std::thread::sleep(backoff.wait(Instant::now()));
+            }
+        }
+    }
+}
+
+/// Read the `multicast` groups in the settings
+pub fn parse_groups(groups: &[String]) -> Result<Vec<IpAddr>, String> {
+    groups.iter()
+        .map(|g| match IpAddr::from_str(g.trim()) {
+            Ok(ip) if ip.is_multicast() => Ok(ip),
+            Ok(ip) => Err(format!("{} isn't a multicast group", ip)),
+            Err(e) => Err(format!("bad multicast group {:?}: {}", g, e)),
+        })
+        .collect()
+}
+
+/// The address to send to from `socket`. Ipv6 sockets listening on every interface take ipv4 as well, but only
+/// send to it written as ipv6
+fn for_socket(socket: &UdpSocket, to: SocketAddr) -> SocketAddr {
+    match (socket.local_addr(), to) {
+        (Ok(SocketAddr::V6(_)), SocketAddr::V4(v4)) => SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+        _ => to,
+    }
+}
+
+/// Somewhere to send to, by address or hostname. Hostnames are looked up when they're first needed, and again with
+/// back-off until they're found
+#[derive(Debug)]
+pub struct Remote {
+    pub host: String,
+    pub port: u16,
+    resolved: Mutex<(Option<SocketAddr>, Backoff)>,
+}
+
+impl Remote {
+    pub fn new(host: &str, port: u16) -> Self {
+        let host = strip_brackets(host.trim()).to_string();
+        let addr = IpAddr::from_str(&host).ok().map(|ip| SocketAddr::new(ip, port));
+        Self {
+            host,
+            port,
+            resolved: Mutex::new((addr, Backoff::default())),
+        }
+    }
+
+    /// Where it is now
+    pub fn addr(&self) -> Result<SocketAddr, NetError> {
+        let mut resolved = self.resolved.lock().unwrap();
+        let (addr, backoff) = &mut *resolved;
+        if let Some(a) = addr {
+            return Ok(*a);
+        }
+        let now = Instant::now();
+        let retry_in = backoff.wait(now);
+        if !retry_in.is_zero() {
+            return Err(NetError::Backoff { to: self.to_string(), retry_in });
+        }
+        match resolve(&self.host, self.port) {
+            Ok(a) => {
+                backoff.succeeded();
+                *addr = Some(a);
+                Ok(a)
+            }
+            Err(e) => {
+                backoff.failed(now);
+                Err(e)
+            }
+        }
+    }
+
+    /// Look a hostname up again next time, in case it's moved. Addresses stay as they are
+    pub fn forget(&self) {
+        if IpAddr::from_str(&self.host).is_err() {
+            self.resolved.lock().unwrap().0 = None;
+        }
+    }
+}
+
+impl From<SocketAddr> for Remote {
+    fn from(addr: SocketAddr) -> Self {
+        Self::new(&addr.ip().to_string(), addr.port())
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Sockets to send udp from: one bound to `src`, and one on any port for the other kind of address, so targets can
+/// be ipv4 or ipv6
+pub struct UdpSender {
+    v4: Option<UdpSocket>,
+    v6: Option<UdpSocket>,
+}
+
+impl UdpSender {
+    /// Any port will do if `src` is taken, so the show doesn't wait on it
+    pub fn bind(src: SocketAddr) -> Result<Self, NetError> {
+        let socket = UdpSocket::bind(src)
+            .or_else(|e| {
+                println!("failed to bind to {}, sending from another port: {}", src, e);
+                UdpSocket::bind(SocketAddr::new(src.ip(), 0))
+            })
+            .map_err(|source| NetError::Bind { addr: src, source })?;
+        Ok(if src.is_ipv4() {
+            Self { v4: Some(socket), v6: UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).ok() }
+        } else {
+            Self { v4: UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok(), v6: Some(socket) }
+        })
+    }
+
+    pub fn send_to(&self, buf: &[u8], to: SocketAddr) -> Result<(), NetError> {
+        let socket = if to.is_ipv4() { &self.v4 } else { &self.v6 };
+        let socket = socket.as_ref()
+            .ok_or_else(|| NetError::Send { to, source: io::Error::new(io::ErrorKind::Unsupported, "no socket for this kind of address") })?;
+        socket.send_to(buf, to)
+            .map(|_| ())
+            .map_err(|source| NetError::Send { to, source })
+    }
+}
+
+/// Bind to `addr` and join `groups`. Groups that can't be joined are logged rather than holding up the socket
+fn bind_joined(addr: SocketAddr, groups: &[IpAddr]) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(addr)?;
+    for group in groups {
+        let joined = match group {
+            IpAddr::V4(g) => socket.join_multicast_v4(g, &Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(g) => socket.join_multicast_v6(g, 0),
+        };
+        if let Err(e) = joined {
+            println!("failed to join multicast group {} on {}: {}", group, addr, e);
+        }
+    }
+    Ok(socket)
+}
+
+/// A udp socket that's bound again if it stops working. Clones share the socket
+#[derive(Clone)]
+pub struct SharedSocket {
+    addr: SocketAddr,
+    /// multicast groups to join
+    groups: Arc<Vec<IpAddr>>,
+    /// `None` while it's being bound again
+    socket: Arc<RwLock<Option<Arc<UdpSocket>>>>,
+}
+
+impl SharedSocket {
+    /// Bind to `addr` and join `groups`, waiting until it can be
+    pub fn bind(addr: SocketAddr, groups: &[IpAddr]) -> Self {
+        let socket = bind_with_backoff(addr, |a| bind_joined(a, groups));
+        // rebinding goes back to the same port, even if any port would have done the first time
+        let addr = socket.local_addr().unwrap_or(addr);
+        Self {
+            addr,
+            groups: Arc::new(groups.to_vec()),
+            socket: Arc::new(RwLock::new(Some(Arc::new(socket)))),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn get(&self) -> Option<Arc<UdpSocket>> {
+        self.socket.read().unwrap().clone()
+    }
+
+    /// Drop the socket and bind a new one, waiting until it can be
+    pub fn rebind(&self) {
+        *self.socket.write().unwrap() = None;
+        let socket = bind_with_backoff(self.addr, |a| bind_joined(a, &self.groups));
+        *self.socket.write().unwrap() = Some(Arc::new(socket));
+    }
+
+    pub fn send_to(&self, buf: &[u8], to: SocketAddr) -> Result<(), NetError> {
+        let socket = self.get()
+            .ok_or_else(|| NetError::Send { to, source: io::Error::new(io::ErrorKind::NotConnected, "socket is being bound again") })?;
+        socket.send_to(buf, for_socket(&socket, to))
+            .map(|_| ())
+            .map_err(|source| NetError::Send { to, source })
+    }
+
+    /// Wait for a packet. Errors that go away by themselves are logged and skipped, and anything else gets the
+    /// socket bound again. Ipv4 senders are given as ipv4 even on an ipv6 socket
+    pub fn recv_from(&self, buf: &mut [u8], log: &mut LogLimiter) -> (usize, SocketAddr) {
+        loop {
+            let result = match self.get() {
+                Some(s) => s.recv_from(buf),
+                None => Err(io::Error::new(io::ErrorKind::NotConnected, "socket is being bound again")),
+            };
+            match result {
+                Ok((n, from)) => return (n, SocketAddr::new(from.ip().to_canonical(), from.port())),
+                Err(e) if is_transient(&e) => {
+                    log.log(&NetError::Receive(e));
+                }
+                Err(e) => {
+                    log.log(&NetError::Receive(e));
+                    self.rebind();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_error(port: u16) -> NetError {
+        NetError::Send { to: SocketAddr::from(([127, 0, 0, 1], port)), source: io::ErrorKind::ConnectionRefused.into() }
+    }
+
+    #[test]
+    fn test_log_limiter() {
+        let mut log = LogLimiter::new(Duration::from_secs(5));
+        let now = Instant::now();
+        assert!(log.log_at(&send_error(1), now));
+        assert!(!log.log_at(&send_error(1), now + Duration::from_secs(1)));
+        // somewhere else is kept apart
+        assert!(log.log_at(&send_error(2), now + Duration::from_secs(1)));
+        assert!(log.log_at(&send_error(1), now + Duration::from_secs(6)));
+        assert_eq!(log.last[&send_error(1).key()], (now + Duration::from_secs(6), 0));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut b = Backoff::new(Duration::from_secs(1), Duration::from_secs(3));
+        let now = Instant::now();
+        assert_eq!(b.wait(now), Duration::ZERO);
+        b.failed(now);
+        assert_eq!(b.wait(now), Duration::from_secs(1));
+        b.failed(now);
+        assert_eq!(b.wait(now), Duration::from_secs(2));
+        b.failed(now);
+        b.failed(now);
+        assert_eq!(b.wait(now), Duration::from_secs(3));
+        assert_eq!(b.wait(now + Duration::from_secs(5)), Duration::ZERO);
+        b.succeeded();
+        b.failed(now);
+        assert_eq!(b.wait(now), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_bind_when_free() {
+        // something else has the port for a while
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        let freed = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            drop(taken);
+        });
+        let socket = SharedSocket::bind(addr, &[]);
+        freed.join().unwrap();
+        assert_eq!(socket.get().unwrap().local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_rebind() {
+        let socket = SharedSocket::bind("127.0.0.1:0".parse().unwrap(), &[]);
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.rebind();
+        // the same port, still receiving
+        assert_eq!(socket.get().unwrap().local_addr().unwrap(), socket.addr());
+        sender.send_to(b"hi", socket.addr()).unwrap();
+        let mut buf = [0u8; 8];
+        let (n, from) = socket.recv_from(&mut buf, &mut LogLimiter::default());
+        assert_eq!((&buf[..n], from), (&b"hi"[..], sender.local_addr().unwrap()));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let addr = |s: &str| SocketAddr::from_str(s).unwrap();
+        assert_eq!(resolve("10.0.0.5", 9000).unwrap(), addr("10.0.0.5:9000"));
+        assert_eq!(resolve("::1", 9000).unwrap(), addr("[::1]:9000"));
+        assert_eq!(resolve("[fe80::1]", 9000).unwrap(), addr("[fe80::1]:9000"));
+        assert_eq!(resolve("", 9000).unwrap(), addr("0.0.0.0:9000"));
+        assert!(resolve("localhost", 9000).unwrap().ip().is_loopback());
+
+        assert_eq!(split_host_port("10.0.0.5:9000"), Some(("10.0.0.5", 9000)));
+        assert_eq!(split_host_port("[::1]:9000"), Some(("::1", 9000)));
+        assert_eq!(split_host_port("audio.local:9000"), Some(("audio.local", 9000)));
+        assert_eq!(split_host_port("::1"), None);
+        assert_eq!(split_host_port("audio.local"), None);
+
+        let remote = Remote::new("localhost", 9000);
+        assert!(remote.addr().unwrap().ip().is_loopback());
+        assert_eq!(remote.to_string(), "localhost:9000");
+        assert_eq!(Remote::from(addr("[::1]:9000")).to_string(), "[::1]:9000");
+
+        assert_eq!(parse_groups(&["239.1.2.3".into(), "ff02::1234".into()]).unwrap().len(), 2);
+        assert!(parse_groups(&["10.0.0.1".into()]).is_err());
+    }
+
+    #[test]
+    fn test_ipv6() {
+        // not every machine has ipv6
+        let Ok(v6) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        let sender = UdpSender::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        sender.send_to(b"v6", v6.local_addr().unwrap()).unwrap();
+        let mut buf = [0u8; 8];
+        let n = v6.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"v6");
+
+        // every interface, ipv4 included
+        let socket = SharedSocket::bind("[::]:0".parse().unwrap(), &[]);
+        let v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        v4.send_to(b"v4", ("127.0.0.1", socket.addr().port())).unwrap();
+        let (n, from) = socket.recv_from(&mut buf, &mut LogLimiter::default());
+        assert_eq!((&buf[..n], from), (&b"v4"[..], v4.local_addr().unwrap()));
+        socket.send_to(b"back", from).unwrap();
+        let n = v4.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"back");
+    }
+}
@@ -1,234 +1,868 @@
-use std::{sync::{Arc, Mutex}, collections::VecDeque, error::Error, time::Duration};
+//! OSC in and out of the simulator.
+//!
+//! The simulator listens on `watch_port` for
+//! * `/accel [id] x y z` - a reading from the watch. Shaking it hard enough cracks the ice once the show has started,
+//!   see [`crate::accel`] for what counts as hard enough. With more than one watch, see [`crate::sensors`] for
+//!   which is which. Sensors with their own `port` send to that instead
+//! * `/impact x y amount [dx dy]` - hit the ice with `amount` stress at `x y`, given as fractions of the screen
+//!   from the top left. `dx dy` sends the crack off in a direction, with y pointing down
+//! * `/param name value [layer]` - set one of the `GraphParams` of every layer, or of one layer
+//! * `/start`, `/stop` - the same as typing them into the console
+//! * `/pause [0|1]` - stop or restart the simulation. Toggles if there's no argument
+//! * `/reset` - put the ice back the way it was when the simulator started
+//! * `/snapshot/save name`, `/snapshot/load name` - save the ice to `snapshots/name/` or load it back
+//! * `/color r g b` - crack color from 0 to 1, instead of the one that changes over the show
+//! * `/rewind/impacts n`, `/rewind/seconds t` - undo the last `n` impacts or go back `t` seconds
+//! * `/status` - replies to the sender with
+//!   `/status running paused elapsed cracked_percent fps queue_depth pending_commands`, see [`Status`]
+//! * `/calibrate [rest_secs hit_secs [0|1]]` - tune the watch, see [`crate::calibration`]
+//! * `/status/subscribe [port]`, `/status/unsubscribe [port]` - start or stop getting `/status` `status_rate` times
+//!   a second. Goes to the port the message came from unless another one is given
+//!
+//! The same messages can also come over tcp on `tcp_port`, SLIP framed, see [`crate::tcp`]. Replies go back over the
+//! connection. Targets can be sent to over tcp too, with `"transport": "tcp"`.
+//!
+//! Messages can come in bundles, nested or not. Timetagged ones are held back a little to even out their delivery,
+//! or until their time if it's set in the future, see [`crate::schedule`] and `bundle_latency` in the settings.
+//!
+//! Inputs are ignored while a replay is running, but status queries still work. Bad packets, targets that go away
+//! and sockets that stop working are logged and got around, see [`crate::net`].
+//! Numbers can be ints or floats. The simulator sends `/start`, `/crack value`, `/four` and `/stop` to every target
+//! in the settings, remapped and scaled for each one, along with the messages in [`crate::crack_messages`] if
+//! `crack_messages` is set.
+use std::{sync::{Arc, Mutex}, collections::VecDeque, error::Error, time::{Duration, Instant, SystemTime}};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use rosc::{encoder, OscType, OscMessage, OscPacket};
-use std::net::{SocketAddrV4, Ipv4Addr, UdpSocket};
-use std::fs::File;
-use std::io::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::net::{SocketAddr, Ipv4Addr, TcpListener, TcpStream};
+use std::io::Write;
 use rand::random;
 use colored::Colorize;
 
-use crate::REPEAT_AMT;
-
-#[derive(Serialize, Deserialize)]
-struct IpSettings {
-    audio_ip: String,
-    audio_port: u16,
-    src_ip: String,
-    watch_port: u16,
-    src_port: u16,
-}
+use crate::accel::AccelFilter;
+use crate::calibration::{CalibrateRequest, Calibration, CalibrationResult};
+use crate::{CRACK_STATS, REPEAT_AMT, SIM_STATUS, TIMER};
+use crate::replay::{self, log_input, show_time, Input};
+use crate::schedule::{unpack, Queue, Scheduler};
+use crate::sensors::{CrackSource, Sensors};
+use crate::settings::read_settings;
+use crate::simulation::{Impact, Region, SimCommand};
+use crate::simulation::timeline::Rewind;
+use crate::status::Status;
+use crate::targets::Target;
+use crate::net::{bind_with_backoff, parse_groups, resolve, resolve_with_backoff, split_host_port, LogLimiter, NetError, SharedSocket, UdpSender};
+use crate::tcp;
 
 pub struct CrackNotifier {
-    audio_target: SocketAddrV4,
-    udp_socket: UdpSocket,
-    update_buf: Arc<Mutex<VecDeque<f32>>>,
-}
-
-fn read_settings() -> Result<IpSettings, Box<dyn Error>> {
-    let mut file = File::open("settings.json")?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let settings: IpSettings = serde_json::from_str(&contents)?;
-    Ok(settings)
+    targets: Vec<Target>,
+    udp: UdpSender,
+    update_buf: Arc<Mutex<VecDeque<SimCommand>>>,
+    log: Mutex<LogLimiter>,
 }
 
 
 impl CrackNotifier {
     #[inline]
-    pub fn new(update_buf: Arc<Mutex<VecDeque<f32>>>) -> Result<Self, Box<dyn Error>> {
+    pub fn new(update_buf: Arc<Mutex<VecDeque<SimCommand>>>) -> Result<Self, Box<dyn Error>> {
         let settings = read_settings()?;
-        let audio_target = SocketAddrV4::new(
-            Ipv4Addr::from_str(settings.audio_ip.as_str())
-                .expect("failed to parse audio_ip as an ipv4 address"),
-            settings.audio_port
-        );
-
-        let crack_src = SocketAddrV4::new(
-            Ipv4Addr::from_str(settings.src_ip.as_str())
-                .expect("failed to parse audio_ip as an ipv4 address"),
-            settings.src_port
-        );
-        let udp_socket = UdpSocket::bind(crack_src)?;
+        let targets = settings.targets()
+            .into_iter()
+            .map(Target::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let crack_src = resolve(&settings.src_ip, settings.src_port)
+            .unwrap_or_else(|e| {
+                println!("{}, sending from every interface", e);
+                SocketAddr::from((Ipv4Addr::UNSPECIFIED, settings.src_port))
+            });
+        let udp = UdpSender::bind(crack_src)?;
 
         Ok(
             Self {
-                audio_target,
-                udp_socket,
+                targets,
+                udp,
                 update_buf,
+                log: Mutex::new(LogLimiter::default()),
             }
         )
     }
 
+    /// Send a message to every target, mapped the way each one wants it, and hand a command to the simulation
     #[inline]
-    pub fn notify(&self, buf: Option<&Vec<u8>>, crack_value: Option<f32>) {
-        if let Some(v) = buf {
-            self.udp_socket.send_to(v, &self.audio_target)
-                .unwrap();
+    pub fn notify(&self, msg: Option<OscMessage>, command: Option<SimCommand>) {
+        if let Some(msg) = msg {
+            for target in &self.targets {
+                let Some(mapped) = target.map(&msg) else {
+                    continue;
+                };
+                // a target that's gone away doesn't hold up the others, or the show
+                let sent = encoder::encode(&OscPacket::Message(mapped))
+                    .map_err(NetError::Encode)
+                    .and_then(|msg_buf| target.send(&self.udp, &msg_buf));
+                if let Err(e) = sent {
+                    self.log.lock().unwrap().log(&e);
+                }
+            }
         }
     
-        if let Some(v) = crack_value {
+        if let Some(v) = command {
             self.update_buf.lock()
                 .unwrap()
                 .push_back(v);
         }
     }
 
+    /// `/crack value`, with the sensor's id after the value if it came from one of the `sensors`
     #[inline]
-    pub fn send_crack(&self, crack_value: f32) {
-        let msg_buf = encoder::encode(&OscPacket::Message(OscMessage {
+    pub fn send_crack(&self, crack_value: f32, source: Option<&CrackSource>) {
+        let mut msg = OscMessage {
             addr: "/crack".to_string(),
             args: vec![
                 OscType::Float(crack_value),
             ],
-        }))
-        .unwrap();
+        };
+        if let Some(s) = source {
+            msg.args.push(OscType::String(s.id.clone()));
+        }
+        let region = source.map(|s| s.region).unwrap_or_default();
     
-        self.notify(Some(&msg_buf), Some(crack_value));
+        self.notify(Some(msg), Some(SimCommand::Crack(crack_value, region)));
 
         
     }
 
-    pub fn send_cloned_crack(notifier: &Arc<Mutex<Self>>, crack_value: f32) {
+    pub fn send_cloned_crack(notifier: &Arc<Mutex<Self>>, crack_value: f32, source: Option<CrackSource>) {
         let not_ref = notifier.lock().unwrap();
-        not_ref.send_crack(crack_value);
+        not_ref.send_crack(crack_value, source.as_ref());
         drop(not_ref);
 
-        if *REPEAT_AMT.read().unwrap() > 0 {
-            let cloned = Arc::clone(&notifier);
+        // replays have the echoes logged
+        if *REPEAT_AMT.read().unwrap() > 0 && !replay::replaying() {
+            let cloned = Arc::clone(notifier);
             std::thread::spawn(move || {
                 for _ in 0..*REPEAT_AMT.read().unwrap() {
                     std::thread::sleep(Duration::from_millis(random::<u64>() % 500 + 300));
-                    let not_ref = cloned.lock().unwrap();
-                    not_ref.send_crack((crack_value + (random::<f32>() * 200_f32 - 100_f32)).max(500_f32).min(1000_f32));
-                    drop(not_ref);
+                    Self::send_echo(&cloned, (crack_value + (random::<f32>() * 200_f32 - 100_f32)).clamp(500_f32, 1000_f32), source.clone());
                 }
             });
         }
     }
+
+    /// Send one of the `REPEAT_AMT` echoes of a crack. Echoes land in the same region as the crack
+    pub fn send_echo(notifier: &Arc<Mutex<Self>>, crack_value: f32, source: Option<CrackSource>) {
+        let input = match &source {
+            Some(s) => Input::SensorEcho { sensor: s.sensor, value: crack_value },
+            None => Input::Echo(crack_value),
+        };
+        log_input(show_time(), input);
+        notifier.lock().unwrap().send_crack(crack_value, source.as_ref());
+    }
 }
 
+/// The show as seen by the threads handling input: the console, the control socket and replays
+pub struct Show {
+    update_buf: Arc<Mutex<VecDeque<SimCommand>>>,
+    /// set once the show starts
+    notifier: Mutex<Option<Arc<Mutex<CrackNotifier>>>>,
+    stopped: AtomicBool,
+    /// where to broadcast the status to
+    subscribers: Mutex<Vec<SocketAddr>>,
+    calibration: Mutex<Option<Calibration>>,
+}
 
-pub fn read_watch_task(notifier: Arc<Mutex<CrackNotifier>>) {
-    // initialize sockets
-    const MOVING_AVG_SIZE: usize = 4;
+impl Show {
+    pub fn new(update_buf: Arc<Mutex<VecDeque<SimCommand>>>) -> Arc<Self> {
+        Arc::new(Self {
+            update_buf,
+            notifier: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+            subscribers: Mutex::new(Vec::new()),
+            calibration: Mutex::new(None),
+        })
+    }
 
-    let settings = read_settings()
-        .expect("failed to read settings file");
-    let watch_src = SocketAddrV4::new(
-        Ipv4Addr::from_str(settings.src_ip.as_str())
-            .expect("failed to parse audio_ip as an ipv4 address"),
-        settings.watch_port
-    );
-
-    let watch_socket = UdpSocket::bind(watch_src)
-        .expect(format!("failed to bined to socket {:?}", watch_src).as_str());
-    
-    let msg_buf = encoder::encode(&OscPacket::Message(OscMessage {                     
+    /// Start the show and its timeline, unless it's already going
+    pub fn start(&self) -> Arc<Mutex<CrackNotifier>> {
+        let mut notifier = self.notifier.lock().unwrap();
+        if let Some(n) = notifier.as_ref() {
+            return Arc::clone(n);
+        }
+        let n = start(Arc::clone(&self.update_buf));
+        // replays have the cues logged
+        if !replay::replaying() {
+            let crack_notifier_tmp = Arc::clone(&n);
+            std::thread::spawn(move || {
+                time_controller(crack_notifier_tmp);
+            });
+        }
+        *notifier = Some(Arc::clone(&n));
+        n
+    }
+
+    /// `None` until the show starts
+    pub fn notifier(&self) -> Option<Arc<Mutex<CrackNotifier>>> {
+        self.notifier.lock().unwrap().clone()
+    }
+
+    pub fn command(&self, c: SimCommand) {
+        command(&self.update_buf, c);
+    }
+
+    /// Stop the show, if it's started
+    pub fn stop(&self) {
+        if let Some(n) = self.notifier() {
+            self.stopped.store(true, Ordering::Relaxed);
+            stop(n);
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        let started = self.notifier.lock().unwrap().is_some();
+        Status {
+            running: started && !self.stopped.load(Ordering::Relaxed),
+            elapsed: if started { show_time() as f32 } else { 0_f32 },
+            cracked_percent: CRACK_STATS.read().unwrap().cracked_fraction * 100_f32,
+            pending_commands: self.update_buf.lock().unwrap().len(),
+            ..*SIM_STATUS.read().unwrap()
+        }
+    }
+
+    pub fn subscribe(&self, addr: SocketAddr) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if !subscribers.contains(&addr) {
+            println!("{} subscribed to the status", addr);
+            subscribers.push(addr);
+        }
+    }
+
+    pub fn unsubscribe(&self, addr: SocketAddr) {
+        self.subscribers.lock().unwrap().retain(|a| *a != addr);
+    }
+
+    /// Start calibrating the watch with the next reading from it, starting over if a calibration is already going
+    pub fn calibrate(&self, request: CalibrateRequest) {
+        log_input(show_time(), Input::Calibrate(request));
+        *self.calibration.lock().unwrap() = Some(Calibration::new(request));
+    }
+
+    /// Hand a reading to the calibration, if one is going. Returns false if there isn't one
+    fn calibration_reading(&self, filter: &mut AccelFilter, t: f64, accel: [f32; 3]) -> bool {
+        let mut calibration = self.calibration.lock().unwrap();
+        let Some(c) = calibration.as_mut() else {
+            return false;
+        };
+        log_input(t, Input::Accel(accel));
+        let apply = c.request().apply;
+        match c.push(filter, t, accel) {
+            None => return true,
+            Some(Ok(result)) => self.finish_calibration(filter, result, apply),
+            Some(Err(e)) => println!("calibration failed: {}", e),
+        }
+        *calibration = None;
+        true
+    }
+
+    fn finish_calibration(&self, filter: &mut AccelFilter, result: CalibrationResult, apply: bool) {
+        println!("noise: {:.1} on average, {:.1} at most. hits: {:?}", result.noise_mean, result.noise_max, result.hits);
+        println!("suggested settings:\n\"accel_stages\": {},\n\"stress_mapping\": {}",
+            serde_json::to_string(&result.stages).unwrap(), serde_json::to_string(&result.stress_mapping).unwrap());
+        if !apply {
+            return;
+        }
+        match AccelFilter::new(&result.stages) {
+            Ok(f) => *filter = f,
+            Err(e) => {
+                println!("failed to apply calibration: {}", e);
+                return;
+            }
+        }
+        // not logged, replays calibrate again and get the same mapping
+        self.update_buf.lock().unwrap().push_back(SimCommand::StressMapping(result.stress_mapping));
+        println!("applied calibration");
+    }
+}
+
+/// Act on an input that happened `t` seconds into the show. The control socket and replays both come through here.
+/// Inputs that need the show to have started are ignored until it has. Calibrations only listen to the default sensor
+pub fn handle_input(show: &Show, sensors: &mut Sensors, t: f64, input: Input) {
+    if let Input::Command(c) = input {
+        show.command(c);
+        return;
+    }
+    if input == Input::Start {
+        show.start();
+        return;
+    }
+    if let Input::Calibrate(r) = input {
+        show.calibrate(r);
+        return;
+    }
+    if let Input::Accel(a) = input {
+        if show.calibration_reading(sensors.filter(None).unwrap(), t, a) {
+            return;
+        }
+    }
+    let Some(notifier) = show.notifier() else {
+        return;
+    };
+    match input {
+        Input::Accel(a) => accel(&notifier, sensors, None, t, a),
+        Input::SensorAccel { sensor, accel: a } => accel(&notifier, sensors, Some(sensor), t, a),
+        Input::Manual(v) => manual_crack(&notifier, v),
+        Input::Echo(v) => CrackNotifier::send_echo(&notifier, v, None),
+        Input::SensorEcho { sensor, value } => CrackNotifier::send_echo(&notifier, value, sensors.source(Some(sensor))),
+        Input::Cue(i) => cue(&notifier, i),
+        Input::Stop => show.stop(),
+        Input::Start | Input::Command(_) | Input::Calibrate(_) => unreachable!(),
+    }
+}
+
+/// Start the show: restart the clock and tell the audio side
+fn start(update_buf: Arc<Mutex<VecDeque<SimCommand>>>) -> Arc<Mutex<CrackNotifier>> {
+    let crack_notifier = CrackNotifier::new(update_buf)
+        .expect("failed to create crack_notifier");
+    *TIMER.write().unwrap() = Instant::now();
+    log_input(show_time(), Input::Start);
+
+    let msg = OscMessage {
         addr: "/start".to_string(),
         args: vec![],
-    }))
-    .unwrap();
-    
-    let not_ref = notifier.lock().unwrap();
-    not_ref.notify(Some(&msg_buf), None);
-    drop(not_ref);
+    };
+    crack_notifier.notify(Some(msg), None);
+    Arc::new(Mutex::new(crack_notifier))
+}
+
+/// Crack with a value typed into the console
+pub fn manual_crack(notifier: &Arc<Mutex<CrackNotifier>>, crack_value: f32) {
+    log_input(show_time(), Input::Manual(crack_value));
+    CrackNotifier::send_cloned_crack(notifier, crack_value, None);
+}
 
-    // spawn task
+/// Hand a command to the simulation, keeping it in the input log
+pub fn command(update_buf: &Mutex<VecDeque<SimCommand>>, c: SimCommand) {
+    log_input(show_time(), Input::Command(c.clone()));
+    update_buf.lock().unwrap().push_back(c);
+}
+
+/// Handle a reading from a sensor `t` seconds into the show. `None` is the default sensor
+pub fn accel(notifier: &Arc<Mutex<CrackNotifier>>, sensors: &mut Sensors, sensor: Option<usize>, t: f64, accel: [f32; 3]) {
+    log_input(t, match sensor {
+        Some(sensor) => Input::SensorAccel { sensor, accel },
+        None => Input::Accel(accel),
+    });
+    let Some(filter) = sensors.filter(sensor) else {
+        println!("no sensor {:?}", sensor);
+        return;
+    };
+    if let Some(v) = filter.push(t, accel) {
+        CrackNotifier::send_cloned_crack(notifier, v, sensors.source(sensor));
+    }
+}
+
+
+/// Listen on `watch_port` for the watch and anything else driving the show, and broadcast the status to subscribers.
+/// See the module docs for the addresses
+pub fn listen(show: Arc<Show>) {
+    let settings = read_settings()
+        .expect("failed to read settings file");
+    for s in &settings.status_subscribers {
+        let addr = split_host_port(s)
+            .ok_or_else(|| "expected host:port".to_string())
+            .and_then(|(host, port)| resolve(host, port).map_err(|e| e.to_string()));
+        match addr {
+            Ok(addr) => show.subscribe(addr),
+            Err(e) => println!("bad status subscriber {}: {}", s, e),
+        }
+    }
+    let interval = Duration::from_secs_f32(1_f32 / settings.status_rate.unwrap_or(4_f32).max(0.01));
+    let latency = settings.bundle_latency.unwrap_or(0.1);
+    let sensors = Sensors::from_settings(&settings)
+        .expect("bad sensors or accel_stages in the settings");
+    let groups = parse_groups(&settings.multicast)
+        .expect("bad multicast groups in the settings");
+
+    // sockets are bound on their own threads, since they wait for hostnames to be found and ports to come free
+    let (tx, rx) = mpsc::channel();
+    for port in sensors.ports() {
+        let (tx, src_ip, groups) = (tx.clone(), settings.src_ip.clone(), groups.clone());
+        std::thread::spawn(move || receive(SharedSocket::bind(resolve_with_backoff(&src_ip, port), &groups), tx, latency));
+    }
+    if let Some(port) = settings.tcp_port {
+        let (tx, src_ip) = (tx.clone(), settings.src_ip.clone());
+        std::thread::spawn(move || receive_tcp(bind_with_backoff(resolve_with_backoff(&src_ip, port), TcpListener::bind), tx, latency));
+    }
+    let status_show = Arc::clone(&show);
     std::thread::spawn(move || {
-        
-        let mut buf = [0u8; rosc::decoder::MTU];
-        let mut time = std::time::Instant::now();
-        let mut moving_avg_buf = VecDeque::from(vec![0.0; MOVING_AVG_SIZE]);
-        loop {
-            match watch_socket.recv_from(&mut buf) {
-                Ok((size, _addr)) => {
-                    let (_, packet) = rosc::decoder::decode_udp(&buf[..size]).unwrap();
-                    if let Ok(v) = handle_packet(packet) {
-                        moving_avg_buf.pop_front();
-                        moving_avg_buf.push_back(v);
-                        let mut moving_avg = 0.0;
-                        for i in 0..MOVING_AVG_SIZE {
-                            moving_avg += moving_avg_buf[i];
-                        }
-                        moving_avg /= MOVING_AVG_SIZE as f32;
-                        
-                        if moving_avg > 500.0  && time.elapsed().as_secs_f32() > 2.0 {
-                            time = std::time::Instant::now();
-                            CrackNotifier::send_cloned_crack(&notifier, moving_avg);
-                        }
-                    }
+        let socket = SharedSocket::bind(resolve_with_backoff(&settings.src_ip, settings.watch_port), &groups);
+        let status_socket = socket.clone();
+        std::thread::spawn(move || broadcast_status(status_socket, status_show, interval));
+        receive(socket, tx, latency);
+    });
+    std::thread::spawn(move || dispatch(rx, show, sensors));
+}
+
+/// Send the status to every subscriber from `socket`, `interval` apart
+fn broadcast_status(socket: SharedSocket, show: Arc<Show>, interval: Duration) {
+    let mut log = LogLimiter::default();
+    loop {
+        std::thread::sleep(interval);
+        let subscribers = show.subscribers.lock().unwrap().clone();
+        if subscribers.is_empty() {
+            continue;
+        }
+        let msg_buf = match encoder::encode(&OscPacket::Message(show.status().to_message())) {
+            Ok(b) => b,
+            Err(e) => {
+                log.log(&NetError::Encode(e));
+                continue;
+            }
+        };
+        for addr in subscribers {
+            if let Err(e) = socket.send_to(&msg_buf, addr) {
+                log.log(&e);
+            }
+        }
+    }
+}
+
+/// Where to send the reply to a `/status`
+enum Reply {
+    Udp(SharedSocket),
+    Tcp(Arc<Mutex<TcpStream>>),
+}
+
+/// A message waiting for its time
+struct Received {
+    msg: OscMessage,
+    from: SocketAddr,
+    /// the port it came in on
+    port: u16,
+    reply: Reply,
+}
+
+/// Unpack a packet and pass its messages on to `dispatch`, along with when each one is due. Returns false once
+/// `dispatch` has gone
+fn schedule(packet: OscPacket, scheduler: &mut Scheduler, tx: &Sender<(Instant, Received)>, from: SocketAddr, port: u16, reply: &Reply) -> bool {
+    let now = SystemTime::now();
+    unpack(packet).into_iter().all(|(time, msg)| {
+        let due = Instant::now() + scheduler.delay(from, time, now);
+        let reply = match reply {
+            Reply::Udp(s) => Reply::Udp(s.clone()),
+            Reply::Tcp(s) => Reply::Tcp(Arc::clone(s)),
+        };
+        tx.send((due, Received { msg, from, port, reply })).is_ok()
+    })
+}
+
+/// Handle everything that comes in on `socket`. Bundles are unpacked and their messages held back until they're due,
+/// see [`crate::schedule`]
+fn receive(socket: SharedSocket, tx: Sender<(Instant, Received)>, latency: f32) {
+    let port = socket.addr().port();
+    let mut scheduler = Scheduler::new(latency);
+    let mut log = LogLimiter::default();
+    let mut buf = [0u8; rosc::decoder::MTU];
+    loop {
+        let (size, addr) = socket.recv_from(&mut buf, &mut log);
+        match rosc::decoder::decode_udp(&buf[..size]) {
+            Ok((_, packet)) => {
+                if !schedule(packet, &mut scheduler, &tx, addr, port, &Reply::Udp(socket.clone())) {
+                    return;
+                }
+            }
+            Err(error) => {
+                log.log(&NetError::Decode { from: addr, error });
+            }
+        }
+    }
+}
+
+/// most tcp connections taken at once, each one gets a thread
+const MAX_TCP_CONNECTIONS: usize = 16;
+
+/// Take connections on `listener` and handle the SLIP framed packets that come in on them the same way as `receive`.
+/// Replies go back over the connection. Connections past `MAX_TCP_CONNECTIONS` are closed straight away, and ones
+/// that go quiet for `tcp::IDLE_TIMEOUT` are closed
+fn receive_tcp(listener: TcpListener, tx: Sender<(Instant, Received)>, latency: f32) {
+    let port = listener.local_addr().map_or(0, |a| a.port());
+    let mut log = LogLimiter::default();
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                log.log(&NetError::Receive(e));
+                continue;
+            }
+        };
+        // replies are sent from the dispatch thread, which mustn't get stuck behind a client that stopped reading
+        let (Ok(addr), Ok(()), Ok(reply)) = (stream.peer_addr(), stream.set_write_timeout(Some(tcp::WRITE_TIMEOUT)), stream.try_clone()) else {
+            continue;
+        };
+        let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+        if connections.load(Ordering::Relaxed) >= MAX_TCP_CONNECTIONS {
+            println!("too many tcp connections, closing the one from {}", addr);
+            continue;
+        }
+        connections.fetch_add(1, Ordering::Relaxed);
+        let connections = Arc::clone(&connections);
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            let reply = Reply::Tcp(Arc::new(Mutex::new(reply)));
+            let mut scheduler = Scheduler::new(latency);
+            let mut log = LogLimiter::default();
+            let result = tcp::read_packets(stream, tcp::IDLE_TIMEOUT, |bytes| match rosc::decoder::decode_udp(&bytes) {
+                Ok((_, packet)) => {
+                    schedule(packet, &mut scheduler, &tx, addr, port, &reply);
                 }
-                Err(e) => {
-                    println!("Error receiving from socket: {}", e);
-                    break;
+                Err(error) => {
+                    log.log(&NetError::Decode { from: addr, error });
                 }
+            });
+            if let Err(e) = result {
+                println!("lost tcp connection from {}: {}", addr, e);
             }
+            connections.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// Handle messages from `receive` and `receive_tcp` once they're due
+fn dispatch(rx: Receiver<(Instant, Received)>, show: Arc<Show>, mut sensors: Sensors) {
+    let mut queue = Queue::new();
+    loop {
+        while let Some(received) = queue.pop_due(Instant::now()) {
+            handle_received(received, &show, &mut sensors);
         }
-    });
+        let next = match queue.next_due() {
+            Some(due) => rx.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match next {
+            Ok((due, received)) => queue.push(due, received),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+fn handle_received(received: Received, show: &Show, sensors: &mut Sensors) {
+    let Received { msg, from: addr, port, reply } = received;
+    let request = match handle_message(msg) {
+        Ok(Request::Accel(id, accel)) => match sensors.identify(id.as_deref(), port, addr) {
+            Some(sensor) => Ok(Request::Input(Input::SensorAccel { sensor, accel })),
+            None => Ok(Request::Input(Input::Accel(accel))),
+        },
+        r => r,
+    };
+    match request {
+        Ok(Request::Input(input)) if replay::replaying() => println!("ignoring {:?} from {} during a replay", input, addr),
+        Ok(Request::Input(input)) => handle_input(show, sensors, show_time(), input),
+        Ok(Request::Status) => {
+            let sent = encoder::encode(&OscPacket::Message(show.status().to_message()))
+                .map_err(NetError::Encode)
+                .and_then(|msg_buf| match reply {
+                    Reply::Udp(socket) => socket.send_to(&msg_buf, addr),
+                    Reply::Tcp(stream) => stream.lock().unwrap().write_all(&tcp::encode(&msg_buf))
+                        .map_err(|source| NetError::Send { to: addr, source }),
+                });
+            if let Err(e) = sent {
+                println!("failed to send status: {}", e);
+            }
+        }
+        Ok(Request::Subscribe(port)) => show.subscribe(SocketAddr::new(addr.ip(), port.unwrap_or(addr.port()))),
+        Ok(Request::Unsubscribe(port)) => show.unsubscribe(SocketAddr::new(addr.ip(), port.unwrap_or(addr.port()))),
+        Ok(Request::Accel(..)) => unreachable!(),
+        Err(e) => println!("bad osc message from {}: {}", addr, e),
+    }
 }
 
-fn handle_packet<'a>(packet: OscPacket) -> Result<f32, &'a str> {
-    match packet {
-        OscPacket::Message(mut msg) => {
-            if msg.addr == "/accel" {
-                let mut tmp = 0.0;
-                for _ in 0..3 {
-                    if let Some(arg) = msg.args.pop() {
-                        if let Some(f) = arg.float() {
-                            tmp += f * f;
-                        } else {
-                            return Err("expected array from accel")
-                        }
-                    } else {
-                        return Err("expected arguments from accel")
-                    }
+/// Reads the arguments of a message in order
+struct Args {
+    addr: String,
+    args: std::vec::IntoIter<OscType>,
+}
+
+impl Args {
+    fn number(&mut self) -> Result<f32, String> {
+        self.opt_number()?.ok_or_else(|| format!("{} needs more arguments", self.addr))
+    }
+
+    /// `None` if there are no arguments left
+    fn opt_number(&mut self) -> Result<Option<f32>, String> {
+        match self.args.next() {
+            None => Ok(None),
+            Some(OscType::Float(f)) => Ok(Some(f)),
+            Some(OscType::Double(d)) => Ok(Some(d as f32)),
+            Some(OscType::Int(i)) => Ok(Some(i as f32)),
+            Some(OscType::Long(l)) => Ok(Some(l as f32)),
+            Some(a) => Err(format!("{} expected a number, got {:?}", self.addr, a)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        match self.args.next() {
+            Some(OscType::String(s)) => Ok(s),
+            a => Err(format!("{} expected a string, got {:?}", self.addr, a)),
+        }
+    }
+
+    /// an optional port number
+    fn port(&mut self) -> Result<Option<u16>, String> {
+        match self.opt_number()? {
+            Some(p) if p >= 1_f32 && p <= u16::MAX as f32 => Ok(Some(p as u16)),
+            Some(p) => Err(format!("{} got a bad port {}", self.addr, p)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// What a message on the control socket asks for
+#[derive(Debug, PartialEq)]
+enum Request {
+    Input(Input),
+    /// a reading, along with the id of the sensor if the message had one
+    Accel(Option<String>, [f32; 3]),
+    Status,
+    /// reply port, if it isn't the one the message came from
+    Subscribe(Option<u16>),
+    Unsubscribe(Option<u16>),
+}
+
+fn handle_message(mut msg: OscMessage) -> Result<Request, String> {
+    if msg.addr == "/accel" {
+        let id = match msg.args.len() {
+            4 => Some(match msg.args.remove(0) {
+                OscType::String(s) => s,
+                OscType::Int(i) => i.to_string(),
+                a => return Err(format!("expected a sensor id from accel, got {:?}", a)),
+            }),
+            _ => None,
+        };
+        let mut tmp = [0.0; 3];
+        for t in tmp.iter_mut().rev() {
+            if let Some(arg) = msg.args.pop() {
+                if let Some(f) = arg.float() {
+                    *t = f;
+                } else {
+                    return Err("expected array from accel".to_string())
                 }
-                Ok(tmp.sqrt())
             } else {
-                Err("expected accel")
+                return Err("expected arguments from accel".to_string())
             }
         }
-        _ => Err("Did not get expected packet type")
+        return Ok(Request::Accel(id, tmp));
     }
+
+    let mut args = Args { addr: msg.addr.clone(), args: msg.args.into_iter() };
+    let command = match msg.addr.as_str() {
+        "/start" => return Ok(Request::Input(Input::Start)),
+        "/stop" => return Ok(Request::Input(Input::Stop)),
+        "/status" => return Ok(Request::Status),
+        "/calibrate" => {
+            let mut request = CalibrateRequest::default();
+            if let Some(rest_secs) = args.opt_number()? {
+                request.rest_secs = rest_secs;
+                request.hit_secs = args.number()?;
+                request.apply = args.opt_number()?.is_some_and(|a| a != 0_f32);
+            }
+            return Ok(Request::Input(Input::Calibrate(request)));
+        }
+        "/status/subscribe" => return Ok(Request::Subscribe(args.port()?)),
+        "/status/unsubscribe" => return Ok(Request::Unsubscribe(args.port()?)),
+        "/impact" => {
+            let position = Some([args.number()?, args.number()?]);
+            let stress = args.number()?;
+            let direction = match args.opt_number()? {
+                Some(dx) => Some([dx, args.number()?]),
+                None => None,
+            };
+            SimCommand::Impact(Impact { position, region: Region::Anywhere, stress, direction })
+        }
+        "/param" => SimCommand::Param {
+            name: args.string()?,
+            value: args.number()?,
+            layer: args.opt_number()?.map(|l| l.max(0_f32) as usize),
+        },
+        "/pause" => SimCommand::Pause(args.opt_number()?.map(|p| p != 0_f32)),
+        "/reset" => SimCommand::Reset,
+        "/snapshot/save" => SimCommand::SaveSnapshot(args.string()?),
+        "/snapshot/load" => SimCommand::LoadSnapshot(args.string()?),
+        "/color" => SimCommand::Color([args.number()?, args.number()?, args.number()?]),
+        "/rewind/impacts" => SimCommand::Rewind(Rewind::Impacts(args.number()?.max(0_f32) as usize)),
+        "/rewind/seconds" => SimCommand::Rewind(Rewind::Seconds(args.number()?)),
+        a => return Err(format!("unknown address {}", a)),
+    };
+    Ok(Request::Input(Input::Command(command)))
 }
 
 pub fn time_controller(crack_notifier: Arc<Mutex<CrackNotifier>>) {
     for i in 0..=24 {
-        match i {
-            0..=10 | 13..=16 | 19..=22 => (),
-            11 | 17 | 23 => {
-                let string = format!("WARN: {}", i * 10).white().on_magenta().bold();
-                println!("{}", string);
-            }
-            12 => *REPEAT_AMT.write().unwrap() = 1,
-            18 => *REPEAT_AMT.write().unwrap() = 2,
-            24 => {
-                *REPEAT_AMT.write().unwrap() = 4;
-                let msg_buf = encoder::encode(&OscPacket::Message(OscMessage {        
-                    addr: "/four".to_string(),
-                    args: vec![],
-                }))
-                .unwrap();
-                crack_notifier.lock().unwrap().notify(Some(&msg_buf), None)
-            },
-            _ => unreachable!(),
-        }
-        let string = format!("TIME: {}", i * 10).green().bold();
-        println!("{}", string);
+        cue(&crack_notifier, i);
         std::thread::sleep(std::time::Duration::from_secs(10));
     }
 }
 
+/// Step `i` of the show timeline, `i * 10` seconds in
+pub fn cue(crack_notifier: &Arc<Mutex<CrackNotifier>>, i: usize) {
+    log_input(show_time(), Input::Cue(i));
+    match i {
+        0..=10 | 13..=16 | 19..=22 => (),
+        11 | 17 | 23 => {
+            let string = format!("WARN: {}", i * 10).white().on_magenta().bold();
+            println!("{}", string);
+        }
+        12 => *REPEAT_AMT.write().unwrap() = 1,
+        18 => *REPEAT_AMT.write().unwrap() = 2,
+        24 => {
+            *REPEAT_AMT.write().unwrap() = 4;
+            let msg = OscMessage {
+                addr: "/four".to_string(),
+                args: vec![],
+            };
+            crack_notifier.lock().unwrap().notify(Some(msg), None)
+        },
+        _ => (),
+    }
+    let string = format!("TIME: {}", i * 10).green().bold();
+    let stats = *CRACK_STATS.read().unwrap();
+    println!("{} ({:.2}% broken, {} cracks)", string, stats.cracked_fraction * 100_f32, stats.crack_networks);
+}
+
 pub fn stop(crack_notifier: Arc<Mutex<CrackNotifier>>) {
+    log_input(show_time(), Input::Stop);
     println!("sending /stop");
-    let msg_buf = encoder::encode(&OscPacket::Message(OscMessage {
-            
+    let msg = OscMessage {
         addr: "/stop".to_string(),
         args: vec![
             OscType::Int(0),
         ],
-    }))
-    .unwrap();
-    crack_notifier.lock().unwrap().notify(Some(&msg_buf), Some(-1_f32));
-}
\ No newline at end of file
+    };
+    crack_notifier.lock().unwrap().notify(Some(msg), Some(SimCommand::Stop));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::TargetSettings;
+    use crate::tcp::Transport;
+    use std::io::Read;
+    use std::net::UdpSocket;
+
+    fn message(addr: &str, args: Vec<OscType>) -> OscMessage {
+        OscMessage { addr: addr.to_string(), args }
+    }
+
+    #[test]
+    fn test_receive_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || receive_tcp(listener, tx, 0.1));
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        for addr in ["/status", "/stop"] {
+            let packet = encoder::encode(&OscPacket::Message(message(addr, vec![]))).unwrap();
+            stream.write_all(&tcp::encode(&packet)).unwrap();
+        }
+        for addr in ["/status", "/stop"] {
+            let (_, received) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(received.msg.addr, addr);
+            assert_eq!(received.port, port);
+            assert_eq!(received.from, stream.local_addr().unwrap());
+            assert!(matches!(received.reply, Reply::Tcp(_)));
+        }
+    }
+
+    #[test]
+    fn test_receive_bad_packet() {
+        let socket = SharedSocket::bind("127.0.0.1:0".parse().unwrap(), &[]);
+        let addr = socket.addr();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || receive(socket, tx, 0.1));
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"not osc", addr).unwrap();
+        sender.send_to(&encoder::encode(&OscPacket::Message(message("/stop", vec![]))).unwrap(), addr).unwrap();
+        // still going
+        let (_, received) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received.msg.addr, "/stop");
+    }
+
+    #[test]
+    fn test_notify_target_down() {
+        let audio = UdpSocket::bind("127.0.0.1:0").unwrap();
+        audio.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // the lights are off
+        let lights = TcpListener::bind("127.0.0.1:0").unwrap();
+        let lights_port = lights.local_addr().unwrap().port();
+        drop(lights);
+        let target = |port, transport| Target::new(TargetSettings { ip: "127.0.0.1".into(), port, transport, ..Default::default() }).unwrap();
+        let update_buf = Arc::new(Mutex::new(VecDeque::new()));
+        let notifier = CrackNotifier {
+            targets: vec![target(lights_port, Transport::Tcp), target(audio.local_addr().unwrap().port(), Transport::Udp)],
+            udp: UdpSender::bind("127.0.0.1:0".parse().unwrap()).unwrap(),
+            update_buf: Arc::clone(&update_buf),
+            log: Mutex::new(LogLimiter::default()),
+        };
+
+        let mut buf = [0u8; 64];
+        for _ in 0..2 {
+            notifier.send_crack(700.0, None);
+            let n = audio.recv(&mut buf).unwrap();
+            assert_eq!(rosc::decoder::decode_udp(&buf[..n]).unwrap().1, OscPacket::Message(message("/crack", vec![OscType::Float(700.0)])));
+        }
+        assert_eq!(update_buf.lock().unwrap().len(), 2);
+
+        // the lights come back
+        let lights = TcpListener::bind(("127.0.0.1", lights_port)).unwrap();
+        std::thread::sleep(Duration::from_millis(600));
+        notifier.notify(Some(message("/stop", vec![])), None);
+        let n = lights.accept().unwrap().0.read(&mut buf).unwrap();
+        assert_eq!(tcp::SlipDecoder::new().push(&buf[..n]), vec![encoder::encode(&OscPacket::Message(message("/stop", vec![]))).unwrap()]);
+    }
+
+    #[test]
+    fn test_handle_message() {
+        use OscType::{Float, Int, String as Str};
+        let command = |addr, args| match handle_message(message(addr, args)) {
+            Ok(Request::Input(Input::Command(c))) => c,
+            other => panic!("{} gave {:?}", addr, other),
+        };
+
+        assert_eq!(handle_message(message("/accel", vec![Float(1.0), Float(2.0), Float(3.0)])), Ok(Request::Accel(None, [1.0, 2.0, 3.0])));
+        assert_eq!(handle_message(message("/accel", vec![Str("left".into()), Float(1.0), Float(2.0), Float(3.0)])),
+            Ok(Request::Accel(Some("left".into()), [1.0, 2.0, 3.0])));
+        assert_eq!(handle_message(message("/accel", vec![Int(2), Float(1.0), Float(2.0), Float(3.0)])),
+            Ok(Request::Accel(Some("2".into()), [1.0, 2.0, 3.0])));
+        assert_eq!(handle_message(message("/start", vec![])), Ok(Request::Input(Input::Start)));
+        assert_eq!(command("/impact", vec![Float(0.5), Float(0.25), Int(40)]),
+            SimCommand::Impact(Impact { position: Some([0.5, 0.25]), region: Region::Anywhere, stress: 40.0, direction: None }));
+        assert_eq!(command("/impact", vec![Float(0.5), Float(0.25), Float(40.0), Float(1.0), Float(0.0)]),
+            SimCommand::Impact(Impact { position: Some([0.5, 0.25]), region: Region::Anywhere, stress: 40.0, direction: Some([1.0, 0.0]) }));
+        assert_eq!(command("/param", vec![Str("crack_threshold".into()), Float(2.5)]),
+            SimCommand::Param { name: "crack_threshold".into(), value: 2.5, layer: None });
+        assert_eq!(command("/param", vec![Str("crack_threshold".into()), Float(2.5), Int(1)]),
+            SimCommand::Param { name: "crack_threshold".into(), value: 2.5, layer: Some(1) });
+        assert_eq!(command("/pause", vec![]), SimCommand::Pause(None));
+        assert_eq!(command("/pause", vec![Int(0)]), SimCommand::Pause(Some(false)));
+        assert_eq!(command("/reset", vec![]), SimCommand::Reset);
+        assert_eq!(command("/snapshot/save", vec![Str("a".into())]), SimCommand::SaveSnapshot("a".into()));
+        assert_eq!(command("/color", vec![Float(1.0), Int(0), Float(0.5)]), SimCommand::Color([1.0, 0.0, 0.5]));
+        assert_eq!(command("/rewind/seconds", vec![Int(5)]), SimCommand::Rewind(Rewind::Seconds(5.0)));
+
+        assert!(handle_message(message("/impact", vec![Float(0.5)])).is_err());
+        assert!(handle_message(message("/impact", vec![Float(0.5), Float(0.5), Float(1.0), Float(1.0)])).is_err());
+        assert!(handle_message(message("/param", vec![Float(1.0), Float(1.0)])).is_err());
+        assert!(handle_message(message("/nope", vec![])).is_err());
+
+        assert_eq!(handle_message(message("/status", vec![])), Ok(Request::Status));
+        assert_eq!(handle_message(message("/calibrate", vec![])), Ok(Request::Input(Input::Calibrate(CalibrateRequest::default()))));
+        assert_eq!(handle_message(message("/calibrate", vec![Int(3), Float(8.0), Int(1)])),
+            Ok(Request::Input(Input::Calibrate(CalibrateRequest { rest_secs: 3.0, hit_secs: 8.0, apply: true }))));
+        assert!(handle_message(message("/calibrate", vec![Int(3)])).is_err());
+        assert_eq!(handle_message(message("/status/subscribe", vec![])), Ok(Request::Subscribe(None)));
+        assert_eq!(handle_message(message("/status/subscribe", vec![Int(9000)])), Ok(Request::Subscribe(Some(9000))));
+        assert_eq!(handle_message(message("/status/unsubscribe", vec![Int(9000)])), Ok(Request::Unsubscribe(Some(9000))));
+        assert!(handle_message(message("/status/subscribe", vec![Int(70000)])).is_err());
+    }
+}
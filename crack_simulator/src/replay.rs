@@ -0,0 +1,182 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{prelude::*, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::CalibrateRequest;
+use crate::osc::{handle_input, Show};
+use crate::sensors::Sensors;
+use crate::settings::read_settings;
+use crate::simulation::SimCommand;
+use crate::{FRAME, TIMER};
+
+/// Something from outside that changed what the simulation does
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Input {
+    /// `start` was typed into the console
+    Start,
+    /// an `/accel` packet from the watch
+    Accel([f32; 3]),
+    /// an `/accel` packet from one of the `sensors` in the settings
+    SensorAccel { sensor: usize, accel: [f32; 3] },
+    /// a crack value typed into the console. Empty lines are logged as the random value they turned into
+    Manual(f32),
+    /// one of the `REPEAT_AMT` echoes of a crack
+    Echo(f32),
+    SensorEcho { sensor: usize, value: f32 },
+    /// a step of the show timeline. These happen every 10 seconds
+    Cue(usize),
+    /// `stop` was typed into the console, or sent to the control socket
+    Stop,
+    /// from the keyboard or the control socket
+    Command(SimCommand),
+    /// `calibrate` from the console or the control socket
+    Calibrate(CalibrateRequest),
+}
+
+/// An input along with when it happened
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LoggedInput {
+    /// seconds since the show started
+    pub t: f64,
+    /// number of frames the simulation had drawn. Replays line inputs up with this, so the graph gets them
+    /// on the same frame it did live
+    pub frame: usize,
+    pub input: Input,
+}
+
+/// A file of inputs, one json object per line
+pub struct InputLog {
+    writer: BufWriter<File>,
+}
+
+impl InputLog {
+    pub fn create(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Flushes every line so a crash doesn't lose the end of the show
+    pub fn write(&mut self, logged: &LoggedInput) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(&mut self.writer, logged)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub fn read_log(path: &Path) -> Result<Vec<LoggedInput>, Box<dyn Error>> {
+    let mut inputs = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        inputs.push(serde_json::from_str(&line).map_err(|e| format!("line {}: {}", i + 1, e))?);
+    }
+    Ok(inputs)
+}
+
+lazy_static! {
+    static ref INPUT_LOG: Mutex<Option<InputLog>> = Mutex::new(None);
+}
+
+/// set while a replay is feeding the simulation, so nothing makes up inputs of its own
+static REPLAYING: AtomicBool = AtomicBool::new(false);
+
+pub fn replaying() -> bool {
+    REPLAYING.load(Ordering::Relaxed)
+}
+
+/// Seconds since the show started
+pub fn show_time() -> f64 {
+    TIMER.read().unwrap().elapsed().as_secs_f64()
+}
+
+/// Log every input from now on to a new `input_<unix time>.jsonl` file in `dir`. Returns the path of the file
+pub fn start_logging(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    std::fs::create_dir_all(dir)?;
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = dir.join(format!("input_{}.jsonl", secs));
+    *INPUT_LOG.lock().unwrap() = Some(InputLog::create(&path)?);
+    Ok(path)
+}
+
+/// Write an input that happened `t` seconds into the show to the log, if there is one
+pub fn log_input(t: f64, input: Input) {
+    let mut log = INPUT_LOG.lock().unwrap();
+    if let Some(l) = log.as_mut() {
+        let logged = LoggedInput {
+            t,
+            frame: FRAME.load(Ordering::Relaxed),
+            input,
+        };
+        if let Err(e) = l.write(&logged) {
+            println!("failed to log input, stopped logging: {}", e);
+            *log = None;
+        }
+    }
+}
+
+/// Feed a logged show back into the simulation in place of the console, the watch and the timeline.
+/// Each input is sent once the simulation has drawn as many frames as it had when the input was logged,
+/// so a seeded simulation cracks the same way it did live
+pub fn replay(path: &Path, show: &Show) -> Result<(), Box<dyn Error>> {
+    let inputs = read_log(path)?;
+    REPLAYING.store(true, Ordering::Relaxed);
+    println!("replaying {} inputs from {}", inputs.len(), path.display());
+
+    let mut sensors = Sensors::from_settings(&read_settings()?)?;
+    for logged in inputs {
+        while FRAME.load(Ordering::Relaxed) < logged.frame {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let frame = FRAME.load(Ordering::Relaxed);
+        if frame > logged.frame {
+            println!("replay is {} frames behind at {:?}", frame - logged.frame, logged);
+        }
+
+        handle_input(show, &mut sensors, logged.t, logged.input);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::timeline::Rewind;
+
+    #[test]
+    fn test_input_log() {
+        let path = std::env::temp_dir().join(format!("input_log_test_{}.jsonl", std::process::id()));
+        let inputs = [
+            LoggedInput { t: 0.0, frame: 120, input: Input::Start },
+            LoggedInput { t: 0.5, frame: 150, input: Input::Accel([1.0, -2.0, 300.5]) },
+            LoggedInput { t: 3.25, frame: 315, input: Input::Manual(750.0) },
+            LoggedInput { t: 3.75, frame: 345, input: Input::Echo(680.0) },
+            LoggedInput { t: 4.0, frame: 360, input: Input::SensorAccel { sensor: 1, accel: [0.5, 0.0, -1.0] } },
+            LoggedInput { t: 4.5, frame: 390, input: Input::SensorEcho { sensor: 1, value: 700.0 } },
+            LoggedInput { t: 10.0, frame: 720, input: Input::Cue(1) },
+            LoggedInput { t: 11.0, frame: 780, input: Input::Command(SimCommand::Rewind(Rewind::Impacts(1))) },
+            LoggedInput { t: 11.5, frame: 810, input: Input::Command(SimCommand::Param { name: "crack_threshold".into(), value: 2.0, layer: None }) },
+            LoggedInput { t: 11.75, frame: 825, input: Input::Calibrate(CalibrateRequest { rest_secs: 2.0, hit_secs: 5.0, apply: true }) },
+            LoggedInput { t: 12.0, frame: 840, input: Input::Stop },
+        ];
+        let mut log = InputLog::create(&path).unwrap();
+        for i in &inputs {
+            log.write(i).unwrap();
+        }
+        drop(log);
+
+        assert_eq!(read_log(&path).unwrap(), inputs);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+//! Unpacks OSC bundles and works out when to act on the messages in them.
+//!
+//! Senders' clocks are rarely set to ours, so timetags are only trusted for the spacing between messages from the
+//! same sender. Each sender's messages are held back so they come out `latency` behind the quickest that sender has
+//! ever got one to us, which smooths out messages that got bunched up on the way. Messages arriving later than that
+//! are acted on straight away. A timetag well ahead of that quickest delivery was set in the future on purpose, so
+//! the message is held until its time, up to `MAX_AHEAD`.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rosc::{OscMessage, OscPacket, OscTime};
+
+/// the timetag meaning "now"
+const IMMEDIATELY: OscTime = OscTime { seconds: 0, fractional: 1 };
+
+/// how fast the quickest delivery from a sender is forgotten, in seconds a second, so clocks drifting apart don't
+/// leave every message late
+const DRIFT: f64 = 0.001;
+
+/// seconds a timetag has to be ahead of a sender's quickest delivery to be taken as meant for later, rather than
+/// as a delivery quicker than any before
+const AHEAD: f64 = 0.25;
+
+/// longest a message meant for later is held, in seconds
+const MAX_AHEAD: f64 = 10.0;
+
+/// Flatten a packet into its messages, along with when each one was meant to happen. `None` means straight away
+pub fn unpack(packet: OscPacket) -> Vec<(Option<SystemTime>, OscMessage)> {
+    let mut out = Vec::new();
+    unpack_into(packet, None, &mut out);
+    out
+}
+
+fn unpack_into(packet: OscPacket, time: Option<SystemTime>, out: &mut Vec<(Option<SystemTime>, OscMessage)>) {
+    match packet {
+        OscPacket::Message(msg) => out.push((time, msg)),
+        OscPacket::Bundle(bundle) => {
+            // bundles inside bundles have their own timetags
+            let time = to_system_time(bundle.timetag).or(time);
+            for p in bundle.content {
+                unpack_into(p, time, out);
+            }
+        }
+    }
+}
+
+/// `None` for "now", and for times before 1970 that only come from senders with no idea what the time is
+fn to_system_time(t: OscTime) -> Option<SystemTime> {
+    if t == IMMEDIATELY || t.seconds < OscTime::try_from(UNIX_EPOCH).unwrap().seconds {
+        return None;
+    }
+    Some(t.into())
+}
+
+/// Works out how long to hold back messages with timetags
+pub struct Scheduler {
+    latency: f64,
+    /// for each sender, the smallest arrival time minus timetag seen, and when it was last updated
+    quickest: HashMap<SocketAddr, (f64, SystemTime)>,
+}
+
+impl Scheduler {
+    /// `latency` in seconds
+    pub fn new(latency: f32) -> Self {
+        Self {
+            latency: latency.max(0_f32) as f64,
+            quickest: HashMap::new(),
+        }
+    }
+
+    /// How long to wait before acting on a message from `from` that arrived at `now` and was meant for `time`
+    pub fn delay(&mut self, from: SocketAddr, time: Option<SystemTime>, now: SystemTime) -> Duration {
+        let Some(time) = time else {
+            return Duration::ZERO;
+        };
+        let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let offset = secs(now) - secs(time);
+        let quickest = match self.quickest.get(&from) {
+            Some((q, at)) => q + DRIFT * (secs(now) - secs(*at)).max(0_f64),
+            None => offset,
+        };
+        if offset < quickest - AHEAD {
+            // meant for later. Keep it for the tagged time, and out of the quickest delivery
+            return Duration::from_secs_f64((self.latency + quickest - offset).min(MAX_AHEAD));
+        }
+        let quickest = quickest.min(offset);
+        self.quickest.insert(from, (quickest, now));
+        Duration::from_secs_f64((self.latency - (offset - quickest)).max(0_f64))
+    }
+}
+
+/// Messages waiting for their time, soonest first. Messages due at the same time stay in the order they came in
+pub struct Queue<T> {
+    items: Vec<(Instant, T)>,
+}
+
+impl<T> Queue<T> {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub fn push(&mut self, due: Instant, item: T) {
+        let i = self.items.partition_point(|(d, _)| *d <= due);
+        self.items.insert(i, (due, item));
+    }
+
+    /// When the soonest item is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.items.first().map(|(d, _)| *d)
+    }
+
+    /// The soonest item, if it's due by `now`
+    pub fn pop_due(&mut self, now: Instant) -> Option<T> {
+        if self.next_due()? <= now {
+            Some(self.items.remove(0).1)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::OscBundle;
+
+    fn msg(addr: &str) -> OscMessage {
+        OscMessage { addr: addr.to_string(), args: vec![] }
+    }
+
+    #[test]
+    fn test_unpack() {
+        let t = OscTime::try_from(UNIX_EPOCH + Duration::from_secs(1000)).unwrap();
+        let t2 = OscTime::try_from(UNIX_EPOCH + Duration::from_secs(1001)).unwrap();
+        let packet = OscPacket::Bundle(OscBundle {
+            timetag: t,
+            content: vec![
+                OscPacket::Message(msg("/a")),
+                OscPacket::Bundle(OscBundle { timetag: t2, content: vec![OscPacket::Message(msg("/b"))] }),
+                OscPacket::Bundle(OscBundle { timetag: IMMEDIATELY, content: vec![OscPacket::Message(msg("/c"))] }),
+            ],
+        });
+        let at = |s| Some(UNIX_EPOCH + Duration::from_secs(s));
+        assert_eq!(unpack(packet), vec![(at(1000), msg("/a")), (at(1001), msg("/b")), (at(1000), msg("/c"))]);
+        assert_eq!(unpack(OscPacket::Message(msg("/d"))), vec![(None, msg("/d"))]);
+        // a sender that doesn't know what the time is
+        let packet = OscPacket::Bundle(OscBundle { timetag: OscTime { seconds: 5, fractional: 0 }, content: vec![OscPacket::Message(msg("/e"))] });
+        assert_eq!(unpack(packet), vec![(None, msg("/e"))]);
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let mut s = Scheduler::new(0.1);
+        let from: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        let other: SocketAddr = "10.0.0.3:9000".parse().unwrap();
+        // the sender's clock is 50 seconds behind ours
+        let sent = |ms: u64| Some(UNIX_EPOCH + Duration::from_millis(1_000_000 + ms));
+        let arrived = |ms: u64| UNIX_EPOCH + Duration::from_millis(1_050_000 + ms);
+        let close = |d: Duration, ms: u64| (d.as_secs_f64() - ms as f64 / 1000.0).abs() < 1e-3;
+
+        assert!(close(s.delay(from, sent(0), arrived(30)), 100));
+        // a quicker delivery, then two that got bunched up behind it
+        assert!(close(s.delay(from, sent(20), arrived(40)), 100));
+        assert!(close(s.delay(from, sent(40), arrived(100)), 60));
+        assert!(close(s.delay(from, sent(60), arrived(101)), 79));
+        // far too late to smooth out
+        assert!(close(s.delay(from, sent(80), arrived(500)), 0));
+        // other senders are kept separate, and messages without timetags go straight away
+        assert!(close(s.delay(other, sent(0), arrived(500)), 100));
+        assert_eq!(s.delay(from, None, arrived(600)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_scheduler_future() {
+        let mut s = Scheduler::new(0.1);
+        let from: SocketAddr = "10.0.0.2:9000".parse().unwrap();
+        // the sender's clock is 50 seconds behind ours
+        let sent = |ms: u64| Some(UNIX_EPOCH + Duration::from_millis(1_000_000 + ms));
+        let arrived = |ms: u64| UNIX_EPOCH + Duration::from_millis(1_050_000 + ms);
+        let close = |d: Duration, ms: u64| (d.as_secs_f64() - ms as f64 / 1000.0).abs() < 1e-3;
+
+        assert!(close(s.delay(from, sent(1000), arrived(1030)), 100));
+        // a bundle sent at the same time for two seconds later comes out two seconds after the message did
+        assert!(close(s.delay(from, sent(3000), arrived(1030)), 2100));
+        // and doesn't change when the sender's other messages come out
+        assert!(close(s.delay(from, sent(1010), arrived(1040)), 100));
+        // held for no longer than MAX_AHEAD
+        assert!(close(s.delay(from, sent(60_000), arrived(1040)), 10_000));
+    }
+
+    #[test]
+    fn test_queue() {
+        let now = Instant::now();
+        let mut q = Queue::new();
+        q.push(now + Duration::from_millis(20), "b");
+        q.push(now + Duration::from_millis(10), "a");
+        q.push(now + Duration::from_millis(20), "c");
+        assert_eq!(q.next_due(), Some(now + Duration::from_millis(10)));
+        assert_eq!(q.pop_due(now), None);
+        assert_eq!(q.pop_due(now + Duration::from_millis(30)), Some("a"));
+        assert_eq!(q.pop_due(now + Duration::from_millis(30)), Some("b"));
+        assert_eq!(q.pop_due(now + Duration::from_millis(30)), Some("c"));
+        assert_eq!(q.next_due(), None);
+    }
+}
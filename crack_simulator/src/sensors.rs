@@ -0,0 +1,171 @@
+//! Several watches at once, each cracking its own part of the ice.
+//!
+//! A reading belongs to the first sensor in `sensors` in the settings that matches it: by the id in
+//! `/accel id x y z`, then by the extra port it came in on, then by the address it came from. Anything else goes to
+//! the default sensor, which cracks anywhere.
+//!
+//! ```json
+//! "sensors": [
+//!     { "id": "left", "region": { "shape": "rect", "min": [0, 0], "max": [0.5, 1] } },
+//!     { "id": "right", "port": 12005, "region": { "shape": "around", "center": [0.75, 0.5], "radius": 0.2 },
+//!       "accel_stages": [{ "stage": "magnitude" }, { "stage": "hysteresis", "on": 600, "off": 300 }] }
+//! ]
+//! ```
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::accel::{default_stages, AccelFilter, Stage};
+use crate::settings::Settings;
+use crate::simulation::Region;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SensorSettings {
+    /// matches the id in `/accel id x y z`, and is sent along with the sensor's cracks
+    pub id: String,
+    /// extra port to listen on for just this sensor
+    pub port: Option<u16>,
+    /// `ip` or `ip:port` the sensor sends from
+    pub from: Option<String>,
+    /// where its cracks go
+    pub region: Region,
+    /// defaults to `accel_stages`
+    pub accel_stages: Option<Vec<Stage>>,
+}
+
+/// Where a crack came from, so it and its echoes land in the right place
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrackSource {
+    pub sensor: usize,
+    pub id: String,
+    pub region: Region,
+}
+
+struct Sensor {
+    settings: SensorSettings,
+    from: Option<SocketAddr>,
+    from_ip: Option<IpAddr>,
+    filter: AccelFilter,
+}
+
+/// Every sensor along with its filter
+pub struct Sensors {
+    default: AccelFilter,
+    sensors: Vec<Sensor>,
+}
+
+impl Sensors {
+    pub fn new(default_stages: &[Stage], sensors: &[SensorSettings]) -> Result<Self, Box<dyn Error>> {
+        let sensors = sensors.iter()
+            .map(|s| {
+                let (mut from, mut from_ip) = (None, None);
+                if let Some(f) = &s.from {
+                    match SocketAddr::from_str(f) {
+                        Ok(a) => from = Some(a),
+                        Err(_) => from_ip = Some(IpAddr::from_str(f).map_err(|e| format!("sensor {}: bad from {:?}: {}", s.id, f, e))?),
+                    }
+                }
+                let stages = s.accel_stages.as_deref().unwrap_or(default_stages);
+                Ok(Sensor {
+                    settings: s.clone(),
+                    from,
+                    from_ip,
+                    filter: AccelFilter::new(stages).map_err(|e| format!("sensor {}: {}", s.id, e))?,
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Self {
+            default: AccelFilter::new(default_stages)?,
+            sensors,
+        })
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<Self, Box<dyn Error>> {
+        let stages = settings.accel_stages.clone().unwrap_or_else(default_stages);
+        Self::new(&stages, &settings.sensors)
+    }
+
+    /// The sensor a reading came from, or `None` for the default sensor. `port` is the port it came in on
+    pub fn identify(&self, id: Option<&str>, port: u16, from: SocketAddr) -> Option<usize> {
+        if let Some(id) = id {
+            return self.sensors.iter().position(|s| s.settings.id == id);
+        }
+        self.sensors.iter().position(|s| s.settings.port == Some(port))
+            .or_else(|| self.sensors.iter().position(|s| s.from == Some(from) || s.from_ip == Some(from.ip())))
+    }
+
+    /// Extra ports to listen on
+    pub fn ports(&self) -> Vec<u16> {
+        self.sensors.iter().filter_map(|s| s.settings.port).collect()
+    }
+
+    pub fn filter(&mut self, sensor: Option<usize>) -> Option<&mut AccelFilter> {
+        match sensor {
+            Some(i) => self.sensors.get_mut(i).map(|s| &mut s.filter),
+            None => Some(&mut self.default),
+        }
+    }
+
+    /// `None` for the default sensor
+    pub fn source(&self, sensor: Option<usize>) -> Option<CrackSource> {
+        let i = sensor?;
+        let s = self.sensors.get(i)?;
+        Some(CrackSource {
+            sensor: i,
+            id: s.settings.id.clone(),
+            region: s.settings.region,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identify() {
+        let settings: Vec<SensorSettings> = serde_json::from_str(r#"[
+            { "id": "left", "region": { "shape": "rect", "min": [0, 0], "max": [0.5, 1] } },
+            { "id": "right", "port": 12005 },
+            { "id": "ip", "from": "10.0.0.7" },
+            { "id": "addr", "from": "10.0.0.8:9000", "accel_stages": [{ "stage": "magnitude" }] }
+        ]"#).unwrap();
+        let mut sensors = Sensors::new(&default_stages(), &settings).unwrap();
+        let addr = |s: &str| SocketAddr::from_str(s).unwrap();
+
+        assert_eq!(sensors.identify(Some("left"), 12004, addr("10.0.0.7:1")), Some(0));
+        assert_eq!(sensors.identify(Some("nobody"), 12004, addr("10.0.0.1:1")), None);
+        assert_eq!(sensors.identify(None, 12005, addr("10.0.0.7:1")), Some(1));
+        assert_eq!(sensors.identify(None, 12004, addr("10.0.0.7:1")), Some(2));
+        assert_eq!(sensors.identify(None, 12004, addr("10.0.0.8:9000")), Some(3));
+        assert_eq!(sensors.identify(None, 12004, addr("10.0.0.8:9001")), None);
+        assert_eq!(sensors.ports(), vec![12005]);
+
+        assert_eq!(sensors.source(None), None);
+        assert_eq!(sensors.source(Some(0)).unwrap().region, Region::Rect { min: [0.0, 0.0], max: [0.5, 1.0] });
+        // each sensor has its own chain
+        assert_eq!(sensors.filter(Some(3)).unwrap().push(0.0, [3.0, 4.0, 0.0]), Some(5.0));
+        assert_eq!(sensors.filter(None).unwrap().push(0.0, [3.0, 4.0, 0.0]), None);
+        assert!(sensors.filter(Some(4)).is_none());
+
+        let bad = [SensorSettings { from: Some("nowhere".into()), ..Default::default() }];
+        assert!(Sensors::new(&default_stages(), &bad).is_err());
+    }
+
+    #[test]
+    fn test_region_point() {
+        let rect = Region::Rect { min: [0.5, 0.0], max: [1.0, 0.5] };
+        assert_eq!(rect.point([0.0, 0.0], 2.0), Some([0.5, 0.0]));
+        assert_eq!(rect.point([0.5, 1.0], 2.0), Some([0.75, 0.5]));
+        let around = Region::Around { center: [0.5, 0.5], radius: 0.2 };
+        // a quarter turn, all the way out
+        let p = around.point([0.25, 1.0], 2.0).unwrap();
+        assert!((p[0] - 0.5).abs() < 1e-6 && (p[1] - 0.7).abs() < 1e-6);
+        let p = around.point([0.0, 1.0], 2.0).unwrap();
+        assert!((p[0] - 0.6).abs() < 1e-6 && (p[1] - 0.5).abs() < 1e-6);
+        assert_eq!(Region::Anywhere.point([0.5, 0.5], 2.0), None);
+    }
+}
@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::accel::Stage;
+use crate::sensors::SensorSettings;
+use crate::graphics::layer::LayerSettings;
+use crate::simulation::graph::BoundaryMode;
+use crate::simulation::StressMapping;
+use crate::targets::TargetSettings;
+
+#[derive(Serialize, Deserialize)]
+pub struct Settings {
+    /// where the audio rig listens, if `targets` isn't set
+    #[serde(default)]
+    pub audio_ip: String,
+    #[serde(default)]
+    pub audio_port: u16,
+    /// address to listen and send from. Empty, `0.0.0.0` or `::` for every interface. Can be a hostname
+    #[serde(default)]
+    pub src_ip: String,
+    pub watch_port: u16,
+    pub src_port: u16,
+    /// also take osc over tcp on this port, SLIP framed, for controllers that can't afford to lose a cue
+    #[serde(default)]
+    pub tcp_port: Option<u16>,
+
+    /// path to an image (.png) or polygon (.json) mask describing where ice exists
+    #[serde(default)]
+    pub mask: Option<String>,
+    /// path to an svg or json file of cracks that exist before the show starts
+    #[serde(default)]
+    pub scratches: Option<String>,
+    /// how stress behaves at the edges of the screen: "free", "clamped" or "wrapping"
+    #[serde(default)]
+    pub boundary: BoundaryMode,
+    /// sheets of ice from the surface down. Defaults to a single layer
+    #[serde(default)]
+    pub layers: Vec<LayerSettings>,
+    /// makes the graphs start out and crack the same way every time they get the same input
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// directory to save every frame of the show to as numbered pngs
+    #[serde(default)]
+    pub record_frames: Option<String>,
+    /// directory to log every input of the show to, a new `input_<unix time>.jsonl` file each run
+    #[serde(default)]
+    pub record_input: Option<String>,
+    /// input log to play back instead of listening to the console and the watch
+    #[serde(default)]
+    pub replay: Option<String>,
+    /// times a second to send `/status` to subscribers. Defaults to 4
+    #[serde(default)]
+    pub status_rate: Option<f32>,
+    /// `ip:port`s that always get status broadcasts, on top of anything that sends `/status/subscribe`
+    #[serde(default)]
+    pub status_subscribers: Vec<String>,
+    /// everywhere to send `/start`, `/crack`, `/four` and `/stop`, each with its own address mapping and scaling.
+    /// Defaults to just `audio_ip:audio_port`
+    #[serde(default)]
+    pub targets: Vec<TargetSettings>,
+    /// also send `/crack/grow` and `/crack/end` for the cracks the simulation draws
+    #[serde(default)]
+    pub crack_messages: bool,
+    /// how readings from the watch turn into cracks. See `accel` for the stages
+    #[serde(default)]
+    pub accel_stages: Option<Vec<Stage>>,
+    /// how crack values turn into stress
+    #[serde(default)]
+    pub stress_mapping: StressMapping,
+    /// watches besides the default one, each cracking its own part of the ice. See `sensors`
+    #[serde(default)]
+    pub sensors: Vec<SensorSettings>,
+    /// seconds to hold back timetagged osc messages to even out their delivery. Defaults to 0.1. See `schedule`
+    #[serde(default)]
+    pub bundle_latency: Option<f32>,
+    /// multicast groups to join on `watch_port` and the sensor ports. Needs `src_ip` to be every interface
+    #[serde(default)]
+    pub multicast: Vec<String>,
+}
+
+impl Settings {
+    /// `targets`, or the audio rig if there aren't any
+    pub fn targets(&self) -> Vec<TargetSettings> {
+        if self.targets.is_empty() {
+            vec![TargetSettings {
+                ip: self.audio_ip.clone(),
+                port: self.audio_port,
+                ..Default::default()
+            }]
+        } else {
+            self.targets.clone()
+        }
+    }
+}
+
+lazy_static! {
+    static ref SETTINGS_PATH: RwLock<PathBuf> = RwLock::new(PathBuf::from("settings.json"));
+}
+
+/// Read the settings from `path` from now on, instead of `settings.json` in the working directory
+pub fn set_settings_path(path: &Path) {
+    *SETTINGS_PATH.write().unwrap() = path.to_path_buf();
+}
+
+pub fn read_settings() -> Result<Settings, Box<dyn Error>> {
+    let path = SETTINGS_PATH.read().unwrap().clone();
+    let mut file = File::open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let settings: Settings = serde_json::from_str(&contents)?;
+    Ok(settings)
+}
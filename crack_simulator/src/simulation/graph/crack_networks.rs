@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// Keeps track of which cracks have grown into each other.
+/// Cracks are identified by the id of the first crack in their network
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct CrackNetworks {
+    parents: Vec<usize>,
+}
+
+impl CrackNetworks {
+    /// Start a new crack network and return its id
+    pub(super) fn new_crack(&mut self) -> usize {
+        self.parents.push(self.parents.len());
+        self.parents.len() - 1
+    }
+
+    /// Returns the id of the network that `crack` is a part of
+    pub fn find(&mut self, crack: usize) -> usize {
+        let mut root = crack;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // point everything on the way straight at the root
+        let mut cur = crack;
+        while self.parents[cur] != root {
+            let next = self.parents[cur];
+            self.parents[cur] = root;
+            cur = next;
+        }
+        root
+    }
+
+    /// Join two networks. The one that was started first keeps its id
+    pub(super) fn union(&mut self, a: usize, b: usize) -> usize {
+        let (a, b) = (self.find(a), self.find(b));
+        let (root, child) = if a < b { (a, b) } else { (b, a) };
+        self.parents[child] = root;
+        root
+    }
+
+    /// Number of cracks ever started, including ones that have since merged
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crack_networks() {
+        let mut n = CrackNetworks::default();
+        let a = n.new_crack();
+        let b = n.new_crack();
+        let c = n.new_crack();
+        assert_eq!(n.len(), 3);
+        assert_ne!(n.find(a), n.find(b));
+
+        assert_eq!(n.union(c, b), b);
+        assert_eq!(n.find(c), b);
+        assert_eq!(n.union(c, a), a);
+        assert_eq!(n.find(b), a);
+        assert_eq!(n.find(c), a);
+    }
+}
@@ -1,30 +1,28 @@
 use super::{node::NodeIndex, NodeMatrix, edge_update_list::EdgeUpdateList, propagation_vector::PVec};
-use super::CRACK_THRESHOLD;
-use super::DIR_PROPAGATION;
+use super::GraphParams;
+use super::energy::EnergyLedger;
 
-use rand::random;
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
 pub struct EdgeIndex {
     pub row: usize,
     pub col: usize,
     pub ty: usize,
 }
 
-#[derive(PartialEq, Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
+#[derive(PartialEq, Clone, Copy, Default, Debug, Serialize, Deserialize)]
 pub enum EdgeUpdateStatus {
+    #[default]
     NoUpdate,
     StressUpdate,
     PropogationUpdate,
 }
 
-impl Default for EdgeUpdateStatus {
-    fn default() -> Self {
-        Self::NoUpdate
-    }
-}
-
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Edge {
     /// implicit stress in the edge
     pub nodes: [NodeIndex; 2],
@@ -41,10 +39,13 @@ pub struct Edge {
     prop_vec_update: PVec,
     pub stress: f32,
     stress_update: f32,
+    /// id of the crack this edge belongs to once cracked
+    pub crack_id: Option<usize>,
 }
 
 impl Edge {
     #[inline]
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(stress: f32, n1: NodeIndex, s1: usize, n2: NodeIndex, s2: usize, row: usize, col: usize, ty: usize, matrix: &mut NodeMatrix) -> Self {
         debug_assert!(ty < 3);
         let out = Self {
@@ -107,7 +108,7 @@ impl Edge {
     }
 
     #[inline]
-    pub(super) fn update_total_stress(&mut self, update_list: &mut EdgeUpdateList) -> bool {
+    pub(super) fn update_total_stress(&mut self, update_list: &mut EdgeUpdateList, params: &GraphParams, ledger: Option<&mut EnergyLedger>, rng: &mut StdRng) -> bool {
         if self.cracked {
             debug_assert!(self.stress == 0_f32);
             if self.stress_update != 0_f32 {
@@ -118,24 +119,31 @@ impl Edge {
             }
             return false;
         }
+        let (held, incoming) = (self.stress, self.stress_update);
         self.commit_updates();
 
-        if self.stress > CRACK_THRESHOLD {
+        if self.stress > params.crack_threshold {
             // edge is cracking
             self.cracked = true;
+            if let Some(l) = ledger {
+                l.step.released_by_cracking += held as f64;
+            }
             
             if !self.prop_vec.is_zero() {
                 let mut crack_adjustment = self.ty_to_prop_vec();
                 if crack_adjustment * self.prop_vec < 0_f32 {
                     crack_adjustment = - crack_adjustment;
                 }
-                self.prop_vec = (self.prop_vec + crack_adjustment.scale(DIR_PROPAGATION * random::<f32>() * self.stress)).norm();
+                self.prop_vec = (self.prop_vec + crack_adjustment.scale(params.dir_propagation * rng.gen::<f32>() * self.stress)).norm();
             }
             
             self.set_scheduled_for_propagate_update();
             update_list.push(self.index);
             true
         } else {
+            if let Some(l) = ledger {
+                l.step.absorbed += incoming as f64;
+            }
             self.set_not_scheduled_for_update();
             false
         }
@@ -146,76 +154,87 @@ impl Edge {
     pub(super) fn get_adjacent_edges(index: EdgeIndex, n_cols: usize) -> [Option<EdgeIndex>; 4] {
         match index.ty {
             0 => {
-                let e1;
-                let e2;
-                let e3;
-                let e4;
-                if index.row == 0 {
-                    e1 = None;
-                    e2 = None;
+                let (e1, e2) = if index.row == 0 {
+                    (None, None)
+                } else if index.row % 2 == 0 {
+                    (
+                        Some(EdgeIndex { row: index.row - 1, col: index.col, ty: 1}),
+                        Some(EdgeIndex { row: index.row - 1, col: index.col, ty: 2}),
+                    )
                 } else {
-                    if index.row % 2 == 0 {
-                        e1 = Some(EdgeIndex { row: index.row - 1, col: index.col, ty: 1});
-                        e2 = Some(EdgeIndex { row: index.row - 1, col: index.col, ty: 2});
-                    } else {
-                        e1 = Some(EdgeIndex { row: index.row - 1, col: index.col + 1, ty: 1});
-                        e2 = Some(EdgeIndex { row: index.row - 1, col: index.col + 1, ty: 2});
-                    }
-                }
-                e3 = Some(EdgeIndex { row: index.row, col: index.col, ty: 2});
-                e4 = Some(EdgeIndex { row: index.row, col: index.col + 1, ty: 1});
+                    (
+                        Some(EdgeIndex { row: index.row - 1, col: index.col + 1, ty: 1}),
+                        Some(EdgeIndex { row: index.row - 1, col: index.col + 1, ty: 2}),
+                    )
+                };
+                let e3 = Some(EdgeIndex { row: index.row, col: index.col, ty: 2});
+                let e4 = Some(EdgeIndex { row: index.row, col: index.col + 1, ty: 1});
                 [e1, e2, e3, e4]
             },
             1 => {
-                let e1;
-                let e2;
-                let e3;
-                let e4;
-                if index.row % 2 == 1 && index.col == 0 {
-                    e1 = None;
-                    e2 = None;
+                let (e1, e2) = if index.row % 2 == 1 && index.col == 0 {
+                    (None, None)
                 } else {
-                    e1 = Some(EdgeIndex { row: index.row, col: index.col - 1, ty: 2});
-                    e2 = Some(EdgeIndex { row: index.row, col: index.col - 1, ty: 0});
-                }
+                    (
+                        Some(EdgeIndex { row: index.row, col: index.col - 1, ty: 2}),
+                        Some(EdgeIndex { row: index.row, col: index.col - 1, ty: 0}),
+                    )
+                };
                 
-                if index.row % 2 == 0 {
-                    e3 = Some(EdgeIndex { row: index.row + 1, col: index.col - 1, ty: 0});
+                let e3 = if index.row % 2 == 0 {
+                    Some(EdgeIndex { row: index.row + 1, col: index.col - 1, ty: 0})
                 } else {
-                    e3 = Some(EdgeIndex { row: index.row + 1, col: index.col, ty: 0});
-                }
-                e4 = Some(EdgeIndex { row: index.row, col: index.col, ty: 2});
+                    Some(EdgeIndex { row: index.row + 1, col: index.col, ty: 0})
+                };
+                let e4 = Some(EdgeIndex { row: index.row, col: index.col, ty: 2});
                 [e1, e2, e3, e4]
             },
             2 => {
-                let e1;
-                let e2;
-                let e3;
-                let e4;
-
-                e1 = Some(EdgeIndex { row: index.row, col: index.col, ty: 0});
-                if index.col == n_cols - 1 {
-                    e2 = None;
+                let e1 = Some(EdgeIndex { row: index.row, col: index.col, ty: 0});
+                let e2 = if index.col == n_cols - 1 {
+                    None
                 } else {
-                    e2 = Some(EdgeIndex { row: index.row, col: index.col + 1, ty: 1});
-                }
+                    Some(EdgeIndex { row: index.row, col: index.col + 1, ty: 1})
+                };
                 
-                e3 = Some(EdgeIndex { row: index.row, col: index.col, ty: 1});
-                if index.row % 2 == 0 {
+                let e3 = Some(EdgeIndex { row: index.row, col: index.col, ty: 1});
+                let e4 = if index.row % 2 == 0 {
                     if index.col == 0 {
-                        e4 = None;
+                        None
                     } else {
-                        e4 = Some(EdgeIndex { row: index.row + 1, col: index.col - 1, ty: 0});
-                    }       
+                        Some(EdgeIndex { row: index.row + 1, col: index.col - 1, ty: 0})
+                    }
                 } else {
-                    e4 = Some(EdgeIndex { row: index.row + 1, col: index.col, ty: 0});
-                }
+                    Some(EdgeIndex { row: index.row + 1, col: index.col, ty: 0})
+                };
                 [e1, e2, e3, e4]
             },
             _ => unreachable!(),
         }
     }
 
+    /// Same as `get_adjacent_edges` but for a graph with its left/right and top/bottom sides joined.
+    /// Every edge has all 4 neighbours, so this never returns `None`
+    #[inline]
+    pub(super) fn get_wrapped_adjacent_edges(index: EdgeIndex, n_rows: usize, n_cols: usize) -> [Option<EdgeIndex>; 4] {
+        let at = |dr: isize, dc: isize, ty: usize| Some(EdgeIndex {
+            row: (index.row as isize + dr).rem_euclid(n_rows as isize) as usize,
+            col: (index.col as isize + dc).rem_euclid(n_cols as isize) as usize,
+            ty,
+        });
+        let even = index.row % 2 == 0;
+        match index.ty {
+            0 => if even {
+                [at(-1, 0, 1), at(-1, 0, 2), at(0, 0, 2), at(0, 1, 1)]
+            } else {
+                [at(-1, 1, 1), at(-1, 1, 2), at(0, 0, 2), at(0, 1, 1)]
+            },
+            1 => [at(0, -1, 2), at(0, -1, 0), if even { at(1, -1, 0) } else { at(1, 0, 0) }, at(0, 0, 2)],
+            2 => [at(0, 0, 0), at(0, 1, 1), at(0, 0, 1), if even { at(1, -1, 0) } else { at(1, 0, 0) }],
+            _ => unreachable!(),
+        }
+    }
+
     pub fn ty_to_prop_vec(&self) -> PVec {
         match self.index.ty {
             0 => PVec::new(0.0, 1.0),
@@ -226,14 +245,12 @@ impl Edge {
     }
 
     pub fn verify(&self, n_matrix: &NodeMatrix) {
-        let indexes;
-        match self.index.ty {
-            0 => indexes = [0, 3],
-            1 => indexes = [4, 1],
-            2 => indexes = [5, 2],
+        let indexes = match self.index.ty {
+            0 => [0, 3],
+            1 => [4, 1],
+            2 => [5, 2],
             _ => unreachable!(),
-
-        }
+        };
 
         let n1 = n_matrix.get(self.nodes[0]);
         let n2 = n_matrix.get(self.nodes[1]);
@@ -264,6 +281,11 @@ impl Edge {
         self.update_status
     }
 
+    /// Stress held by the edge plus stress on its way in
+    pub fn total_stress(&self) -> f32 {
+        self.stress + self.stress_update
+    }
+
     pub fn add_stress(&mut self, stress: f32, update_list: &mut EdgeUpdateList) {
         self.stress += stress;
         if self.update_status != EdgeUpdateStatus::StressUpdate {
@@ -271,6 +293,15 @@ impl Edge {
             update_list.push(self.index);
         }
     }
+
+    /// Add stress moving along a unit vector `dir`
+    pub fn add_directed_stress(&mut self, stress: f32, dir: PVec, update_list: &mut EdgeUpdateList) {
+        Self::combine_stress(&mut self.stress, &mut self.prop_vec, stress, dir);
+        if self.update_status != EdgeUpdateStatus::StressUpdate {
+            self.set_scheduled_for_stress_update();
+            update_list.push(self.index);
+        }
+    }
 }
 
 
@@ -282,10 +313,10 @@ mod tests {
     fn test_get_adjacent_edges() {
         let e = EdgeIndex { row: 0, col: 0, ty: 0};
 
-        let mut e3 = e.clone();
+        let mut e3 = e;
         e3.ty = 2;
 
-        let mut e4 = e.clone();
+        let mut e4 = e;
         e4.ty = 1;
         e4.col = 1;
         let o = Edge::get_adjacent_edges(e, 10);
@@ -1,6 +1,8 @@
 use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
 use super::EdgeIndex;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct EdgeUpdateList {
     pub v: VecDeque<EdgeIndex>
 }
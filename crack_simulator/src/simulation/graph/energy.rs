@@ -0,0 +1,98 @@
+use std::ops::AddAssign;
+
+/// Where stress went during some number of steps.
+/// Stress is either held by an unbroken edge or moving along a crack
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EnergyFlow {
+    /// added from outside with `Graph::add_stress`
+    pub injected: f64,
+    /// moving stress that came to rest in an edge that didn't crack
+    pub absorbed: f64,
+    /// held stress freed by an edge cracking. Cracking doesn't use any stress up, it releases it
+    pub released_by_cracking: f64,
+    /// made splitting moving stress between the two weakest paths, or lost if it's negative.
+    /// Their biases only add up to 1 when both paths are equally weak, and stress with no direction is sent in full
+    /// both ways
+    pub split_gain: f64,
+    /// lost to `propagation_const` on every hop, or cleared by seeded scratches
+    pub dissipated: f64,
+    /// escaped through the border of the graph
+    pub lost_at_border: f64,
+    /// too small to pass on. See `MIN_STRESS`
+    pub cut_off: f64,
+}
+
+impl EnergyFlow {
+    /// Change in the total stress of the graph
+    pub fn net(&self) -> f64 {
+        self.injected + self.split_gain - self.dissipated - self.lost_at_border - self.cut_off
+    }
+}
+
+impl AddAssign for EnergyFlow {
+    fn add_assign(&mut self, o: Self) {
+        self.injected += o.injected;
+        self.absorbed += o.absorbed;
+        self.released_by_cracking += o.released_by_cracking;
+        self.split_gain += o.split_gain;
+        self.dissipated += o.dissipated;
+        self.lost_at_border += o.lost_at_border;
+        self.cut_off += o.cut_off;
+    }
+}
+
+/// Running record of where stress goes. A step ends every time stress finishes propagating
+#[derive(Clone, Debug, Default)]
+pub struct EnergyLedger {
+    /// flows in the step that's in progress
+    pub step: EnergyFlow,
+    /// flows in the last finished step
+    pub last_step: EnergyFlow,
+    /// flows in every finished step since the ledger was started
+    pub total: EnergyFlow,
+    /// total stress in the graph when the ledger was started
+    pub start_energy: f64,
+    pub steps: usize,
+}
+
+impl EnergyLedger {
+    pub fn new(start_energy: f64) -> Self {
+        Self {
+            start_energy,
+            ..Default::default()
+        }
+    }
+
+    pub(super) fn end_step(&mut self) {
+        self.total += self.step;
+        self.last_step = std::mem::take(&mut self.step);
+        self.steps += 1;
+    }
+
+    /// The total stress the graph should be holding if nothing has gone missing
+    pub fn expected_energy(&self) -> f64 {
+        self.start_energy + self.total.net() + self.step.net()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_energy_ledger() {
+        let mut l = EnergyLedger::new(10.0);
+        l.step.injected = 5.0;
+        l.step.dissipated = 1.0;
+        assert_eq!(l.expected_energy(), 14.0);
+        l.end_step();
+        assert_eq!(l.step, EnergyFlow::default());
+        assert_eq!(l.last_step.injected, 5.0);
+        l.step.lost_at_border = 2.0;
+        l.step.split_gain = 0.5;
+        assert_eq!(l.expected_energy(), 12.5);
+        l.end_step();
+        assert_eq!(l.total.injected, 5.0);
+        assert_eq!(l.steps, 2);
+    }
+}
@@ -0,0 +1,34 @@
+use super::edge::EdgeIndex;
+
+/// Something that happened while stress moved through a graph.
+/// Positions are in ndc, cracks are identified by their crack network id
+#[allow(unused)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum CrackEvent {
+    /// An edge cracked away from any existing crack
+    CrackStarted {
+        crack: usize,
+        position: [f32; 2],
+    },
+    /// Any edge cracking, including the first edge of a crack
+    EdgeCracked {
+        crack: usize,
+        edge: EdgeIndex,
+        position: [f32; 2],
+        /// stress in the edge when it gave way
+        energy: f32,
+        /// direction the stress was travelling in. Zero if the stress had no direction yet
+        direction: [f32; 2],
+        /// the edge split off from the side of a crack rather than growing from its tip
+        branched: bool,
+    },
+    /// A crack closed a loop and cut a piece out of the ice
+    FragmentFormed {
+        crack: usize,
+        position: [f32; 2],
+    },
+    /// All stress has stopped moving. `cracks` grew since the last time the graph settled
+    PropagationSettled {
+        cracks: Vec<usize>,
+    },
+}
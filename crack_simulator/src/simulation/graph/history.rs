@@ -0,0 +1,196 @@
+use std::collections::{HashSet, VecDeque};
+
+use rand::rngs::StdRng;
+
+use super::Graph;
+use super::crack_networks::CrackNetworks;
+use super::edge::{Edge, EdgeIndex};
+use super::edge_update_list::EdgeUpdateList;
+use super::energy::EnergyLedger;
+
+/// Edges as they were before they were first changed after a keyframe
+#[derive(Clone, Default)]
+pub(super) struct EdgeJournal {
+    touched: HashSet<EdgeIndex>,
+    saved: Vec<Edge>,
+}
+
+impl EdgeJournal {
+    pub(super) fn save(&mut self, e: &Edge) {
+        if self.touched.insert(e.index) {
+            self.saved.push(e.clone());
+        }
+    }
+}
+
+/// Everything needed to put a graph back the way it was. Only the edges that changed after the keyframe are kept
+pub(super) struct Keyframe {
+    update_edge_list: EdgeUpdateList,
+    crack_networks: CrackNetworks,
+    propagating: bool,
+    active_cracks: Vec<usize>,
+    cracked_edges: usize,
+    energy_ledger: Option<EnergyLedger>,
+    rng: StdRng,
+    /// edges changed between this keyframe and the next one
+    edges: EdgeJournal,
+}
+
+impl Graph {
+    /// Remember the current state so the graph can be rewound to it.
+    /// Only the edges that change afterwards are copied, so keyframes are cheap
+    pub fn keyframe(&mut self) {
+        let edges = self.edge_matrix.journal.replace(EdgeJournal::default()).unwrap_or_default();
+        if let Some(k) = self.keyframes.back_mut() {
+            k.edges = edges;
+        }
+        self.keyframes.push_back(Keyframe {
+            update_edge_list: self.update_edge_list.clone(),
+            crack_networks: self.crack_networks.clone(),
+            propagating: self.propagating,
+            active_cracks: self.active_cracks.clone(),
+            cracked_edges: self.cracked_edges.len(),
+            energy_ledger: self.energy_ledger.clone(),
+            rng: self.rng.clone(),
+            edges: EdgeJournal::default(),
+        });
+    }
+
+    pub fn keyframe_count(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    /// Forget keyframe `k` to save memory, counting from the oldest one kept. It can't be the newest one
+    pub fn drop_keyframe(&mut self, k: usize) {
+        assert!(k + 1 < self.keyframes.len(), "can only drop keyframes that have a newer one");
+        let dropped = self.keyframes.remove(k).unwrap();
+        // the keyframe before now has to cover the changes after the dropped one too
+        if let Some(prev) = k.checked_sub(1).map(|p| &mut self.keyframes[p]) {
+            for e in &dropped.edges.saved {
+                prev.edges.save(e);
+            }
+        }
+    }
+
+    /// Forget every keyframe and stop keeping track of changes
+    pub fn clear_history(&mut self) {
+        self.keyframes = VecDeque::new();
+        self.edge_matrix.journal = None;
+    }
+
+    /// Put the graph back the way it was when keyframe `k` was taken, counting from the oldest one kept.
+    /// Every later keyframe is forgotten
+    #[allow(clippy::result_unit_err)]
+    pub fn rewind_to_keyframe(&mut self, k: usize) -> Result<(), ()> {
+        if k >= self.keyframes.len() {
+            return Err(());
+        }
+        // newest first, so each edge ends up with the value saved right after keyframe k
+        let open = self.edge_matrix.journal.replace(EdgeJournal::default()).unwrap_or_default();
+        self.restore_edges(open);
+        while self.keyframes.len() > k + 1 {
+            let keyframe = self.keyframes.pop_back().unwrap();
+            self.restore_edges(keyframe.edges);
+        }
+        let keyframe = self.keyframes.back_mut().unwrap();
+        let edges = std::mem::take(&mut keyframe.edges);
+
+        self.update_edge_list = keyframe.update_edge_list.clone();
+        self.crack_networks = keyframe.crack_networks.clone();
+        self.propagating = keyframe.propagating;
+        self.active_cracks = keyframe.active_cracks.clone();
+        self.cracked_edges.truncate(keyframe.cracked_edges);
+        // stats reset after the keyframe stay reset
+        self.stats_start = self.stats_start.min(self.cracked_edges.len());
+        self.energy_ledger = keyframe.energy_ledger.clone();
+        self.rng = keyframe.rng.clone();
+        if let Some(t) = self.transmitted.as_mut() {
+            t.clear();
+        }
+        self.restore_edges(edges);
+        Ok(())
+    }
+
+    fn restore_edges(&mut self, journal: EdgeJournal) {
+        for e in journal.saved {
+            let i = e.index;
+            self.edge_matrix.v[i.row][i.col][i.ty] = Some(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewind_to_keyframe() {
+        let mut g = Graph::seeded(50, 50, super::super::BoundaryMode::Free, 3);
+        g.main_loop();
+        g.keyframe();
+        let start = (g.total_energy(), g.cracked_edges.clone());
+
+        let hit = |g: &mut Graph| {
+            let i = g.get_random_edge_index().unwrap();
+            g.add_stress(i, 20.0).unwrap();
+            for _ in 0..20 {
+                g.main_loop();
+            }
+        };
+        hit(&mut g);
+        g.keyframe();
+        let middle = (g.total_energy(), g.cracked_edges.clone());
+        hit(&mut g);
+        hit(&mut g);
+        let end = (g.total_energy(), g.cracked_edges.clone());
+        assert_ne!(middle, end);
+
+        g.rewind_to_keyframe(1).unwrap();
+        assert_eq!((g.total_energy(), g.cracked_edges.clone()), middle);
+        assert!(g.validate().is_ok());
+        // the rng is rewound too, so the same hits crack the same way
+        hit(&mut g);
+        hit(&mut g);
+        assert_eq!((g.total_energy(), g.cracked_edges.clone()), end);
+
+        g.keyframe();
+        hit(&mut g);
+        g.drop_keyframe(1);
+        g.rewind_to_keyframe(0).unwrap();
+        assert_eq!((g.total_energy(), g.cracked_edges.clone()), start);
+        assert_eq!(g.keyframe_count(), 1);
+        assert!(g.rewind_to_keyframe(1).is_err());
+        assert!(g.validate().is_ok());
+    }
+
+    #[test]
+    fn test_reset_stats_keeps_keyframes() {
+        let mut g = Graph::seeded(50, 50, super::super::BoundaryMode::Free, 3);
+        g.set_node_ndcs(0.5, 0.0, 1.0 / 50.0, 1.0 / 50.0);
+        g.main_loop();
+        let hit = |g: &mut Graph| {
+            let i = g.get_random_edge_index().unwrap();
+            g.add_stress(i, 20.0).unwrap();
+            for _ in 0..20 {
+                g.main_loop();
+            }
+        };
+        hit(&mut g);
+        g.keyframe();
+        let cracked = g.stats(50, 50).cracked_edges;
+        assert!(cracked > 0);
+        hit(&mut g);
+
+        g.reset_stats();
+        assert_eq!(g.stats(50, 50).cracked_edges, 0);
+        assert_eq!(g.keyframe_count(), 1);
+        hit(&mut g);
+        assert_eq!(g.stats(50, 50).cracked_edges, g.cracked_edges.len() - g.stats_start);
+
+        // the reset still holds after rewinding past it, and the cracks from before the keyframe are all there
+        g.rewind_to_keyframe(0).unwrap();
+        assert_eq!(g.cracked_edges.len(), cracked);
+        assert_eq!(g.stats(50, 50).cracked_edges, 0);
+        assert!(g.validate().is_ok());
+    }
+}
@@ -1,13 +1,22 @@
 use std::{fs::File, path::Path};
+use std::collections::{HashSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
 
 use node::Node;
 use edge::Edge;
 use edge_update_list::EdgeUpdateList;
-use rand::random;
+use crack_networks::CrackNetworks;
+use events::CrackEvent;
+use stats::CrackStats;
+use energy::EnergyLedger;
+use history::{EdgeJournal, Keyframe};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 use crate::graphics::vertex::Vertex;
 
 
-use self::{node::NodeIndex, edge::{EdgeUpdateStatus}};
+use self::{node::NodeIndex, edge::{EdgeUpdateStatus}, propagation_vector::PVec};
 use self::edge::EdgeIndex;
 use std::io::Write;
 
@@ -15,6 +24,13 @@ pub mod node;
 pub mod edge;
 mod edge_update_list;
 mod propagation_vector;
+pub mod crack_networks;
+pub mod events;
+pub mod stats;
+pub mod energy;
+pub mod validate;
+pub mod snapshot;
+pub mod history;
 
 const WEAKEST_PATH_BIAS: f32 = 3_f32;
 const CRACK_THRESHOLD: f32 = 1.8_f32;
@@ -26,12 +42,77 @@ const CRACKED_STRESS_REP: f32 = 1.0;
 
 const MIN_STRESS: f32 = 0.0000001;
 
+/// random picks `get_random_edge_index` makes before looking through every edge
+const RANDOM_EDGE_TRIES: usize = 64;
+
+/// Tunable constants of the simulation. Defaults to the constants above
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct GraphParams {
+    /// how strongly stress prefers the weaker of two paths
+    pub weakest_path_bias: f32,
+    /// stress above which an edge cracks
+    pub crack_threshold: f32,
+    /// fraction of stress passed on to each adjacent edge
+    pub propagation_const: f32,
+    /// how much a crack bends towards the direction of the cracked edge
+    pub dir_propagation: f32,
+    /// stress that a cracked edge looks like it holds when picking a path
+    pub cracked_stress_rep: f32,
+}
+
+impl Default for GraphParams {
+    fn default() -> Self {
+        Self {
+            weakest_path_bias: WEAKEST_PATH_BIAS,
+            crack_threshold: CRACK_THRESHOLD,
+            propagation_const: PROPOGATION_CONST,
+            dir_propagation: DIR_PROPAGATION,
+            cracked_stress_rep: CRACKED_STRESS_REP,
+        }
+    }
+}
+
+impl GraphParams {
+    /// Set a param by its field name
+    pub fn set(&mut self, name: &str, value: f32) -> Result<(), Box<dyn std::error::Error>> {
+        let param = match name {
+            "weakest_path_bias" => &mut self.weakest_path_bias,
+            "crack_threshold" => &mut self.crack_threshold,
+            "propagation_const" => &mut self.propagation_const,
+            "dir_propagation" => &mut self.dir_propagation,
+            "cracked_stress_rep" => &mut self.cracked_stress_rep,
+            _ => return Err(format!("unknown param: {}", name).into()),
+        };
+        *param = value;
+        Ok(())
+    }
+}
+
+/// What happens to stress that reaches the border of the graph
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BoundaryMode {
+    /// stress escapes through the border
+    #[default]
+    Free,
+    /// stress is reflected back into the graph
+    Clamped,
+    /// left/right and top/bottom sides are joined. Requires an even number of rows
+    Wrapping,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct NodeMatrix {
     v: Vec<Vec<Node>>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct EdgeMatrix {
-    v: Vec<Vec<[Option<Edge>; 3]>>
+    v: Vec<Vec<[Option<Edge>; 3]>>,
+    /// only kept while the graph has keyframes
+    #[serde(skip)]
+    journal: Option<EdgeJournal>,
 }
 
 /// Graph of stress nodes
@@ -40,6 +121,7 @@ pub struct EdgeMatrix {
 ///  * * * *
 /// * * * *
 ///  * * * * 
+#[derive(Serialize, Deserialize)]
 pub struct Graph {
     /// number of rows in graph
     rows: usize,
@@ -52,25 +134,119 @@ pub struct Graph {
     edge_matrix: EdgeMatrix,
 
     update_edge_list: EdgeUpdateList,
+
+    boundary: BoundaryMode,
+
+    params: GraphParams,
+
+    crack_networks: CrackNetworks,
+    #[serde(skip)]
+    subscribers: Vec<Sender<CrackEvent>>,
+    /// true while stress injected since the last time the graph settled is still moving
+    propagating: bool,
+    /// cracks that have grown since the graph last settled
+    active_cracks: Vec<usize>,
+
+    /// every edge that's cracked, oldest first
+    cracked_edges: Vec<EdgeIndex>,
+    /// how many of `cracked_edges` cracked before the stats were last reset
+    stats_start: usize,
+    /// number of edges in the sheet
+    total_edges: usize,
+
+    /// only kept while energy tracking is turned on
+    #[serde(skip)]
+    energy_ledger: Option<EnergyLedger>,
+
+    /// every random choice the graph makes comes from here, so seeded graphs always behave the same.
+    /// Snapshots don't keep it, loaded graphs get a fresh random one
+    #[serde(skip, default = "StdRng::from_entropy")]
+    rng: StdRng,
+
+    /// oldest first. See `keyframe`
+    #[serde(skip)]
+    keyframes: VecDeque<Keyframe>,
+
+    /// stress passed along cracks since `couple_to` last took it, while a graph below is coupled to this one
+    #[serde(skip)]
+    transmitted: Option<Vec<(EdgeIndex, f32, PVec)>>,
 }
 
 impl Graph {
     /// rows = number of rows (y axis), cols = num calls (x axis)
+    #[allow(unused)]
     #[inline]
     pub fn new(rows: usize, cols: usize) -> Self {
+        Self::with_boundary(rows, cols, BoundaryMode::Free)
+    }
+
+    #[inline]
+    pub fn with_boundary(rows: usize, cols: usize, boundary: BoundaryMode) -> Self {
+        Self::build(rows, cols, boundary, StdRng::from_entropy())
+    }
+
+    /// A graph that makes the same random choices every time it's given the same seed
+    pub fn seeded(rows: usize, cols: usize, boundary: BoundaryMode, seed: u64) -> Self {
+        Self::build(rows, cols, boundary, StdRng::seed_from_u64(seed))
+    }
+
+    fn build(rows: usize, cols: usize, boundary: BoundaryMode, rng: StdRng) -> Self {
+        assert!(boundary != BoundaryMode::Wrapping || rows % 2 == 0, "wrapping graphs need an even number of rows");
         let mut out = Self {
             rows,
             cols,
+            boundary,
+            params: GraphParams::default(),
+            crack_networks: CrackNetworks::default(),
+            subscribers: Vec::new(),
+            propagating: false,
+            active_cracks: Vec::new(),
+            cracked_edges: Vec::new(),
+            stats_start: 0,
+            total_edges: 0,
+            energy_ledger: None,
+            rng,
             node_matrix: NodeMatrix { v: Vec::with_capacity(rows) },
-            edge_matrix: EdgeMatrix { v: Vec::with_capacity(rows) },
+            edge_matrix: EdgeMatrix { v: Vec::with_capacity(rows), journal: None },
             update_edge_list: EdgeUpdateList::new(rows * cols * 3),
+            keyframes: VecDeque::new(),
+            transmitted: None,
         };
         out.init();
         out
     }
     
+    /// Build a graph covering a `width` x `height` pixel screen, with edges about a pixel long
+    pub fn for_screen(width: u32, height: u32, boundary: BoundaryMode, seed: Option<u64>) -> Self {
+        let exact_rows = height as f32 / 3_f32.sqrt() * 2_f32;
+        let screen_rows = exact_rows.ceil() as usize;
+        let (rows, cols) = Self::screen_dimensions(width, height, boundary);
+        let mut graph = match seed {
+            Some(seed) => Self::seeded(rows, cols, boundary, seed),
+            None => Self::with_boundary(rows, cols, boundary),
+        };
+        // an extra row for wrapping squeezes the rows together so the last one is still on the screen
+        let y_scale = screen_rows as f32 / exact_rows / height as f32 * (screen_rows - 1) as f32 / (rows - 1) as f32;
+        graph.set_node_ndcs(0.5, 0.0, 1.0 / width as f32, y_scale);
+        graph
+    }
+
+    /// rows and columns of the graph `for_screen` builds
+    pub fn screen_dimensions(width: u32, height: u32, boundary: BoundaryMode) -> (usize, usize) {
+        let mut rows = (height as f32 / 3_f32.sqrt() * 2_f32).ceil() as usize;
+        if boundary == BoundaryMode::Wrapping && rows % 2 == 1 {
+            rows += 1;
+        }
+        (rows, width as usize + 1)
+    }
+
+    /// Start making the same random choices as any other graph given `seed`. For seeded runs from a snapshot
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
     #[inline]
-    fn init<'b>(&mut self) {
+    fn init(&mut self) {
         // initialize node matrix
         for r in 0..self.rows {
             let mut cur = Vec::with_capacity(self.cols);
@@ -87,7 +263,7 @@ impl Graph {
                 // fill in horizontal edges for this row
                 if x < self.cols - 1 {
                     // if we aren't on the last col, link this node to the node adjacent to the right
-                    cur[0] = Some(Edge::new(Self::get_init_implicit_edge_stress(), [y, x].into(), 0, [y, x + 1].into(), 3, y, x, 0, &mut self.node_matrix));
+                    cur[0] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 0, [y, x + 1].into(), 3, y, x, 0, &mut self.node_matrix));
                 } else {
                     cur[0] = None;
                 }
@@ -97,15 +273,15 @@ impl Graph {
                     if y % 2 != 1 {
                         // if we're on an even row (including row 0)
                         if x > 0 {
-                            cur[1] = Some(Edge::new(Self::get_init_implicit_edge_stress(), [y, x].into(), 4, [y + 1, x - 1].into(), 1, y, x, 1, &mut self.node_matrix));
+                            cur[1] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 4, [y + 1, x - 1].into(), 1, y, x, 1, &mut self.node_matrix));
                         } else {
                             cur[1] = None;
                         }
-                        cur[2] = Some(Edge::new(Self::get_init_implicit_edge_stress(), [y, x].into(), 5, [y + 1, x].into(), 2, y, x, 2, &mut self.node_matrix));
+                        cur[2] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 5, [y + 1, x].into(), 2, y, x, 2, &mut self.node_matrix));
                     } else {
-                        cur[1] = Some(Edge::new(Self::get_init_implicit_edge_stress(), [y, x].into(), 4, [y + 1, x].into(), 1, y, x, 1, &mut self.node_matrix));
+                        cur[1] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 4, [y + 1, x].into(), 1, y, x, 1, &mut self.node_matrix));
                         if x < self.cols - 1 {
-                            cur[2] = Some(Edge::new(Self::get_init_implicit_edge_stress(), [y, x].into(), 5, [y + 1, x + 1].into(), 2, y, x, 2, &mut self.node_matrix));
+                            cur[2] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 5, [y + 1, x + 1].into(), 2, y, x, 2, &mut self.node_matrix));
                         } else {
                             cur[2] = None;
                         }
@@ -119,18 +295,58 @@ impl Graph {
             }
             self.edge_matrix.v.push(cur_vec);
         }
+        if self.boundary == BoundaryMode::Wrapping {
+            self.init_wrapped_edges();
+        }
         debug_assert!(self.update_edge_list.size() == 0);
         for e in &mut self.edge_matrix.v {
             for ee in e {
-                for eee in ee {
-                    if let Some(e) = eee {
-                        self.update_edge_list.push(e.index);
-                        e.set_scheduled_for_stress_update();
-                    }
+                for e in ee.iter_mut().flatten() {
+                    self.update_edge_list.push(e.index);
+                    e.set_scheduled_for_stress_update();
                 }
             }
         }
-        debug_assert!(self.update_edge_list.size() < self.rows * self.cols * 3);
+        debug_assert!(self.update_edge_list.size() <= self.rows * self.cols * 3);
+        self.total_edges = self.update_edge_list.size();
+    }
+
+    /// Add the edges that join the right side to the left side and the bottom to the top
+    fn init_wrapped_edges(&mut self) {
+        let (rows, cols) = (self.rows, self.cols);
+        for y in 0..rows {
+            let x = cols - 1;
+            let below = (y + 1) % rows;
+            self.edge_matrix.v[y][x][0] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 0, [y, 0].into(), 3, y, x, 0, &mut self.node_matrix));
+            if y % 2 != 1 {
+                self.edge_matrix.v[y][0][1] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, 0].into(), 4, [below, x].into(), 1, y, 0, 1, &mut self.node_matrix));
+            } else {
+                self.edge_matrix.v[y][x][2] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 5, [below, 0].into(), 2, y, x, 2, &mut self.node_matrix));
+            }
+        }
+
+        // the last row is odd
+        let y = rows - 1;
+        for x in 0..cols {
+            self.edge_matrix.v[y][x][1] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 4, [0, x].into(), 1, y, x, 1, &mut self.node_matrix));
+            if x < cols - 1 {
+                self.edge_matrix.v[y][x][2] = Some(Edge::new(Self::get_init_implicit_edge_stress(&mut self.rng), [y, x].into(), 5, [0, x + 1].into(), 2, y, x, 2, &mut self.node_matrix));
+            }
+        }
+    }
+
+    #[inline]
+    fn get_adjacent_edges(&self, index: EdgeIndex) -> [Option<EdgeIndex>; 4] {
+        match self.boundary {
+            BoundaryMode::Wrapping => Edge::get_wrapped_adjacent_edges(index, self.rows, self.cols),
+            _ => Edge::get_adjacent_edges(index, self.cols),
+        }
+    }
+
+    /// Triangles on the seam of a wrapped graph would stretch across the whole screen
+    #[inline]
+    fn spans_seam(ns: &[NodeIndex; 3]) -> bool {
+        ns.iter().any(|a| ns.iter().any(|b| a.row.abs_diff(b.row) > 1 || a.col.abs_diff(b.col) > 1))
     }
 
     #[allow(unused)]
@@ -157,15 +373,93 @@ impl Graph {
         self.edge_matrix.get_mut(i)
     }
 
+    pub fn set_params(&mut self, params: GraphParams) {
+        self.params = params;
+    }
+
+    pub fn params(&self) -> GraphParams {
+        self.params
+    }
+
+    /// Returns a channel that receives every crack event from now on
+    #[allow(unused)]
+    pub fn subscribe(&mut self) -> Receiver<CrackEvent> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn emit(&mut self, event: CrackEvent) {
+        // drop subscribers that have gone away
+        self.subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    #[allow(clippy::result_unit_err)]
     pub fn add_stress(&mut self, i: EdgeIndex, stress: f32) -> Result<(), ()> {
         if let Some(e) = self.edge_matrix.get_mut(i) {
             e.add_stress(stress, &mut self.update_edge_list);
+            self.propagating = true;
+            if let Some(l) = self.energy_ledger.as_mut() {
+                l.step.injected += stress as f64;
+            }
             Ok(())
         } else {
             Err(())
         }
     }
 
+    /// Add stress that sets off a crack heading in `dir`, given in screen space with y pointing down
+    #[allow(clippy::result_unit_err)]
+    pub fn add_directed_stress(&mut self, i: EdgeIndex, stress: f32, dir: [f32; 2]) -> Result<(), ()> {
+        // rows run down the screen and columns across it
+        let dir = PVec::new(dir[1], dir[0]).norm();
+        if dir.is_zero() {
+            return self.add_stress(i, stress);
+        }
+        if let Some(e) = self.edge_matrix.get_mut(i) {
+            e.add_directed_stress(stress, dir, &mut self.update_edge_list);
+            self.propagating = true;
+            if let Some(l) = self.energy_ledger.as_mut() {
+                l.step.injected += stress as f64;
+            }
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Start keeping the stress passed along cracks, for a graph below to take its share of with `couple_to`
+    pub fn keep_transmitted(&mut self) {
+        self.transmitted.get_or_insert_with(Vec::new);
+    }
+
+    /// Pass `coupling` of the stress that moved along cracks in `above` since the last call on to the same edges of
+    /// this graph, heading the same way. `above` must be keeping its transmitted stress
+    pub fn couple_to(&mut self, above: &mut Graph, coupling: f32) {
+        let Some(transmitted) = above.transmitted.as_mut() else {
+            return;
+        };
+        for (i, stress, dir) in transmitted.drain(..) {
+            let stress = stress * coupling;
+            if stress <= MIN_STRESS {
+                continue;
+            }
+            // masks take out the same edges from every layer
+            let Some(e) = self.edge_matrix.get_mut(i) else {
+                continue;
+            };
+            e.add_stress_update(stress, dir);
+            if e.get_update_status() != EdgeUpdateStatus::StressUpdate {
+                e.set_scheduled_for_stress_update();
+                self.update_edge_list.push(e.index);
+            }
+            self.propagating = true;
+            if let Some(l) = self.energy_ledger.as_mut() {
+                l.step.injected += stress as f64;
+            }
+        }
+    }
+
     /// Sets the ndc values for all nodes in the graph
     /// # Arguments
     /// * `screen_width` - The width of the screen in pixels
@@ -188,9 +482,53 @@ impl Graph {
         }
     }
 
-    fn get_init_implicit_edge_stress() -> f32 {
+    /// Removes every edge with a node outside of the ice.
+    /// `inside` is given the ndc of a node, so `set_node_ndcs` must be called first.
+    /// Stress that reaches the new boundary escapes, the same as at the borders of the graph.
+    /// Keyframes are forgotten
+    pub fn apply_mask<F: Fn(Vertex) -> bool>(&mut self, inside: F) {
+        self.clear_history();
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                for ty in 0..3 {
+                    let remove = match self.edge_matrix.v[r][c][ty].as_ref() {
+                        Some(e) => e.nodes.iter().any(|n| {
+                            !inside(self.node_matrix.get(*n).ndc.expect("node ndcs must be set before masking"))
+                        }),
+                        None => false,
+                    };
+                    if remove {
+                        let e = self.edge_matrix.v[r][c][ty].take().unwrap();
+                        self.total_edges -= 1;
+                        for n in e.nodes {
+                            let node = self.node_matrix.get_mut(n);
+                            for slot in node.edges.iter_mut() {
+                                if *slot == Some(e.index) {
+                                    *slot = None;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let edge_matrix = &self.edge_matrix;
+        self.update_edge_list.v.retain(|e| edge_matrix.get(*e).is_some());
+        let kept_before_reset = self.cracked_edges[..self.stats_start].iter()
+            .filter(|e| edge_matrix.get(**e).is_some())
+            .count();
+        self.cracked_edges.retain(|e| edge_matrix.get(*e).is_some());
+        self.stats_start = kept_before_reset;
+    }
+
+    fn get_init_implicit_edge_stress(rng: &mut StdRng) -> f32 {
         // TODO randomize here?
-        random()
+        rng.gen()
+    }
+
+    /// Returns the number of rows and columns of nodes
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.rows, self.cols)
     }
 
     pub fn get_update_amt(&self) -> usize {
@@ -202,31 +540,100 @@ impl Graph {
         //println!("update edge stress size: {}", update_n);
         for _ in 0..update_n {
             if let Some(e) = self.edge_matrix.get_mut(self.update_edge_list.pop().expect("shouldn't be none")) {
-                if e.update_total_stress(&mut &mut self.update_edge_list) {
+                if e.update_total_stress(&mut self.update_edge_list, &self.params, self.energy_ledger.as_mut(), &mut self.rng) {
+                    let index = e.index;
+                    self.on_edge_cracked(index);
                     // if an edge cracked, add triangles to triangle update list
                     if let Some(l) = triangle_update_list.as_mut() {
-                        let a_edges = Edge::get_adjacent_edges(e.index, self.cols);
-                        
-                        for g in 0..2 {
-                            if let Some(oe1) = a_edges[2 * g] {
-                                if let Some(oe2) = a_edges[2 * g + 1] {
-                                    if let Some(e1) = self.edge_matrix.get(oe1) {
-                                        if let Some(e2) = self.edge_matrix.get(oe2) {
-                                            let mut ns = [NodeIndex::default(); 3];
-                                            ns[0] = e1.nodes[0];
-                                            ns[1] = e1.nodes[1];
-                                            ns[2] = if e2.nodes[0] == ns[0] || e2.nodes[0] == ns[1] {
-                                                e2.nodes[1]
-                                            } else {
-                                                e2.nodes[0]
-                                            };
-    
-                                            for i in 0..3 {
-                                                l.push(self.node_matrix.get(ns[i]).ndc.expect("shouldn't be none"));
-                                            }
-                                        }
-                                    }
-                                }
+                        self.push_crack_triangles(index, l);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the ndc halfway along an edge
+    fn edge_position(&self, e: &Edge) -> [f32; 2] {
+        let a = self.node_matrix.get(e.nodes[0]).ndc.unwrap_or_else(|| [0_f32, 0_f32].into()).position;
+        let b = self.node_matrix.get(e.nodes[1]).ndc.unwrap_or_else(|| [0_f32, 0_f32].into()).position;
+        [(a[0] + b[0]) / 2_f32, (a[1] + b[1]) / 2_f32]
+    }
+
+    /// Add a freshly cracked edge to the crack network it touches, or start a new one.
+    /// Returns the crack id, whether it's a new crack, whether the edge closed a loop and whether it branched off
+    /// from a node that already had two cracked edges
+    fn join_crack_network(&mut self, index: EdgeIndex) -> (usize, bool, bool, bool) {
+        let nodes = self.edge_matrix.get(index).unwrap().nodes;
+        let mut touching: [Vec<usize>; 2] = Default::default();
+        for (k, n) in nodes.iter().enumerate() {
+            for e in self.node_matrix.get(*n).edges.iter().flatten() {
+                if *e == index {
+                    continue;
+                }
+                if let Some(id) = self.edge_matrix.get(*e).and_then(|e| e.crack_id) {
+                    touching[k].push(self.crack_networks.find(id));
+                }
+            }
+        }
+        // both ends already being part of the same network means we've gone all the way around something
+        let closes_loop = touching[0].iter().any(|r| touching[1].contains(r));
+        let branched = touching.iter().any(|t| t.len() >= 2);
+        let (mut id, started) = match touching.iter().flatten().next() {
+            Some(r) => (*r, false),
+            None => (self.crack_networks.new_crack(), true),
+        };
+        for r in touching.iter().flatten() {
+            id = self.crack_networks.union(id, *r);
+        }
+        self.edge_matrix.get_mut(index).unwrap().crack_id = Some(id);
+        self.cracked_edges.push(index);
+        (id, started, closes_loop, branched)
+    }
+
+    fn on_edge_cracked(&mut self, index: EdgeIndex) {
+        let (crack, started, closes_loop, branched) = self.join_crack_network(index);
+        if !self.active_cracks.contains(&crack) {
+            self.active_cracks.push(crack);
+        }
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let edge = self.edge_matrix.get(index).unwrap();
+        let position = self.edge_position(edge);
+        let energy = edge.stress;
+        let direction = [edge.prop_vec.x(), edge.prop_vec.y()];
+        if started {
+            self.emit(CrackEvent::CrackStarted { crack, position });
+        }
+        self.emit(CrackEvent::EdgeCracked { crack, edge: index, position, energy, direction, branched });
+        if closes_loop {
+            self.emit(CrackEvent::FragmentFormed { crack, position });
+        }
+    }
+
+    /// Push the two triangles on either side of a cracked edge
+    fn push_crack_triangles(&self, index: EdgeIndex, l: &mut Vec<Vertex>) {
+        let a_edges = self.get_adjacent_edges(index);
+        for g in 0..2 {
+            if let Some(oe1) = a_edges[2 * g] {
+                if let Some(oe2) = a_edges[2 * g + 1] {
+                    if let Some(e1) = self.edge_matrix.get(oe1) {
+                        if let Some(e2) = self.edge_matrix.get(oe2) {
+                            let mut ns = [NodeIndex::default(); 3];
+                            ns[0] = e1.nodes[0];
+                            ns[1] = e1.nodes[1];
+                            ns[2] = if e2.nodes[0] == ns[0] || e2.nodes[0] == ns[1] {
+                                e2.nodes[1]
+                            } else {
+                                e2.nodes[0]
+                            };
+                            if self.boundary == BoundaryMode::Wrapping && Self::spans_seam(&ns) {
+                                continue;
+                            }
+
+                            for n in ns {
+                                l.push(self.node_matrix.get(n).ndc.expect("shouldn't be none"));
                             }
                         }
                     }
@@ -235,8 +642,200 @@ impl Graph {
         }
     }
 
-    fn weak_path_bias_fn(ratio: f32) -> f32 {
-        0.5 * (WEAKEST_PATH_BIAS * (ratio - 1_f32)).tanh() + 0.5
+    /// Returns the triangles on either side of every cracked edge, for redrawing all the cracks at once
+    pub fn crack_triangles(&self) -> Vec<Vertex> {
+        let mut out = Vec::new();
+        for e in self.edge_matrix.v.iter().flatten().flatten().flatten() {
+            if e.cracked {
+                self.push_crack_triangles(e.index, &mut out);
+            }
+        }
+        out
+    }
+
+    /// Returns the ndc end points of every cracked edge.
+    /// Edges that wrap around the sides of the graph are left out
+    pub fn cracked_segments(&self) -> Vec<[Vertex; 2]> {
+        let mut out = Vec::new();
+        for e in self.edge_matrix.v.iter().flatten().flatten().flatten() {
+            if !e.cracked {
+                continue;
+            }
+            let (Some(a), Some(b)) = (self.node_matrix.get(e.nodes[0]).ndc, self.node_matrix.get(e.nodes[1]).ndc) else {
+                continue;
+            };
+            if (a.position[0] - b.position[0]).abs() > 1_f32 || (a.position[1] - b.position[1]).abs() > 1_f32 {
+                continue;
+            }
+            out.push([a, b]);
+        }
+        out
+    }
+
+    /// Returns the node closest to an ndc point that still has edges.
+    /// Looks at every node, so this is slow on big graphs
+    pub fn closest_node(&self, v: Vertex) -> Option<NodeIndex> {
+        let mut out = None;
+        let mut best = f32::INFINITY;
+        for n in self.node_matrix.v.iter().flatten() {
+            if n.edges.iter().any(|e| e.is_some()) {
+                let p = n.ndc.expect("node ndcs must be set first").position;
+                let d = (p[0] - v.position[0]).powi(2) + (p[1] - v.position[1]).powi(2);
+                if d < best {
+                    best = d;
+                    out = Some(n.index);
+                }
+            }
+        }
+        out
+    }
+
+    /// Returns a random uncracked edge touching the node closest to an ndc point
+    pub fn edge_near(&mut self, v: Vertex) -> Option<EdgeIndex> {
+        let node = self.node_matrix.get(self.closest_node(v)?);
+        let edges: Vec<EdgeIndex> = node.edges.iter()
+            .flatten()
+            .filter(|e| self.edge_matrix.get(**e).is_some_and(|e| !e.cracked))
+            .copied()
+            .collect();
+        if edges.is_empty() {
+            None
+        } else {
+            Some(edges[self.rng.gen_range(0..edges.len())])
+        }
+    }
+
+    /// Crack the edges along a path of ndc points without propagating any stress.
+    /// Each segment is walked across the lattice one node at a time, always moving to the neighbour
+    /// closest to the end of the segment.
+    pub fn crack_path(&mut self, path: &[Vertex], mut triangle_update_list: Option<&mut Vec<Vertex>>) {
+        let dist = |a: Vertex, b: Vertex| {
+            let (dx, dy) = (a.position[0] - b.position[0], a.position[1] - b.position[1]);
+            dx * dx + dy * dy
+        };
+        let Some(first) = path.first() else {
+            return;
+        };
+        let Some(mut cur) = self.closest_node(*first) else {
+            return;
+        };
+
+        for target in &path[1..] {
+            loop {
+                let node = self.node_matrix.get(cur);
+                let mut best = dist(node.ndc.unwrap(), *target);
+                let mut next = None;
+                for e in node.edges.iter().flatten() {
+                    let other = self.edge_matrix.get(*e).unwrap().traverse(cur);
+                    let d = dist(self.node_matrix.get(other).ndc.unwrap(), *target);
+                    if d < best {
+                        best = d;
+                        next = Some((*e, other));
+                    }
+                }
+                let Some((e, other)) = next else {
+                    break;
+                };
+
+                let edge = self.edge_matrix.get_mut(e).unwrap();
+                if !edge.cracked {
+                    edge.cracked = true;
+                    if let Some(l) = self.energy_ledger.as_mut() {
+                        l.step.dissipated += edge.stress as f64;
+                    }
+                    edge.stress = 0_f32;
+                    self.join_crack_network(e);
+                    if let Some(l) = triangle_update_list.as_mut() {
+                        self.push_crack_triangles(e, l);
+                    }
+                }
+                cur = other;
+            }
+        }
+    }
+
+    /// Measure how broken the graph is. `screen_width` and `screen_height` are used to convert
+    /// crack lengths to pixels, so `set_node_ndcs` must be called for `crack_length_px` to be non zero
+    pub fn stats(&mut self, screen_width: u32, screen_height: u32) -> CrackStats {
+        // the lattice is regular, so every edge of a type is the same length on screen
+        let mut px_len = [0_f32; 3];
+        if self.rows > 1 && self.cols > 1 {
+            let ends: [(NodeIndex, NodeIndex); 3] = [
+                ([0, 0].into(), [0, 1].into()),
+                ([0, 1].into(), [1, 0].into()),
+                ([0, 0].into(), [1, 0].into()),
+            ];
+            for (ty, (a, b)) in ends.iter().enumerate() {
+                if let (Some(a), Some(b)) = (self.node_matrix.get(*a).ndc, self.node_matrix.get(*b).ndc) {
+                    let dx = (a.position[0] - b.position[0]) * screen_width as f32 / 2_f32;
+                    let dy = (a.position[1] - b.position[1]) * screen_height as f32 / 2_f32;
+                    px_len[ty] = (dx * dx + dy * dy).sqrt();
+                }
+            }
+        }
+
+        let cracked_edges = &self.cracked_edges[self.stats_start..];
+        let mut networks = HashSet::new();
+        for e in cracked_edges {
+            if let Some(id) = self.edge_matrix.get(*e).and_then(|e| e.crack_id) {
+                networks.insert(self.crack_networks.find(id));
+            }
+        }
+
+        CrackStats {
+            cracked_edges: cracked_edges.len(),
+            crack_length: cracked_edges.len() as f32,
+            crack_length_px: cracked_edges.iter().map(|e| px_len[e.ty]).sum(),
+            crack_networks: networks.len(),
+            cracked_fraction: if self.total_edges == 0 {
+                0_f32
+            } else {
+                cracked_edges.len() as f32 / self.total_edges as f32
+            },
+            queue_depth: self.update_edge_list.size(),
+            fractal_dimension: stats::box_counting_dimension(cracked_edges, self.rows, self.cols),
+        }
+    }
+
+    /// Start measuring from scratch. Cracks that already exist are no longer counted
+    #[allow(unused)]
+    pub fn reset_stats(&mut self) {
+        self.stats_start = self.cracked_edges.len();
+    }
+
+    /// Start recording where stress goes, from the current state of the graph
+    pub fn start_energy_ledger(&mut self) {
+        self.energy_ledger = Some(EnergyLedger::new(self.total_energy()));
+    }
+
+    #[allow(unused)]
+    pub fn stop_energy_ledger(&mut self) {
+        self.energy_ledger = None;
+    }
+
+    pub fn energy_ledger(&self) -> Option<&EnergyLedger> {
+        self.energy_ledger.as_ref()
+    }
+
+    /// Sum of all the stress in the graph, held or moving
+    pub fn total_energy(&self) -> f64 {
+        self.edge_matrix.v.iter()
+            .flatten()
+            .flatten()
+            .flatten()
+            .map(|e| e.total_stress() as f64)
+            .sum()
+    }
+
+    /// Difference between the stress in the graph and what the ledger says should be there.
+    /// Anything that isn't close to 0 means stress is appearing or disappearing without being recorded
+    #[allow(unused)]
+    pub fn energy_imbalance(&self) -> Option<f64> {
+        self.energy_ledger.as_ref().map(|l| self.total_energy() - l.expected_energy())
+    }
+
+    fn weak_path_bias_fn(&self, ratio: f32) -> f32 {
+        0.5 * (self.params.weakest_path_bias * (ratio - 1_f32)).tanh() + 0.5
     }
 
     pub fn update_graph_stress_propagation(&mut self) {
@@ -269,6 +868,10 @@ impl Graph {
                 a.prop_vec_update = prop_vec
             */
 
+            // where this edge's stress ends up, for the energy ledger
+            let (mut sent, mut cut_off, mut dissipated, mut lost_at_border, mut budget, mut gained) = (0_f32, 0_f32, 0_f32, 0_f32, 0_f32, 0_f32);
+            let pc = self.params.propagation_const;
+
             let edge = self.edge_matrix.get(e).unwrap();
             if edge.prop_vec.is_zero() {
                 //println!("prop vec ZERO index {:?}", edge.index);
@@ -276,13 +879,13 @@ impl Graph {
                 let mut dir = edge.ty_to_prop_vec();
                 let added_stress = edge.stress;
 
-                let adjacent_edges = Edge::get_adjacent_edges(edge.index, self.cols);
+                let adjacent_edges = self.get_adjacent_edges(edge.index);
                 let mut prop_amounts: [Option<f32>; 4] = [None; 4];
                 for i in 0..4 {
                     if let Some(e) = adjacent_edges[i] {
                         if let Some(ee) = self.edge_matrix.get(e) {
                             if ee.cracked {
-                                prop_amounts[i] = Some(CRACKED_STRESS_REP);
+                                prop_amounts[i] = Some(self.params.cracked_stress_rep);
                             } else {
                                 prop_amounts[i] = Some(ee.stress);
                             }
@@ -290,31 +893,65 @@ impl Graph {
                     }
                 }
 
+                // all of it goes each way, the bias only decides what's too small to send. A clamped border sends what
+                // was headed into it back the other way
+                let open = [
+                    prop_amounts[0].is_some() && prop_amounts[1].is_some(),
+                    prop_amounts[2].is_some() && prop_amounts[3].is_some(),
+                ];
+                let blocked = [
+                    adjacent_edges[0].is_none() || adjacent_edges[1].is_none(),
+                    adjacent_edges[2].is_none() || adjacent_edges[3].is_none(),
+                ];
+                let bias: f32 = self.rng.gen();
+                let mut shares = [bias, 1.0 - bias];
+                // times over each side is sent the stress
+                let mut sends = [1_f32, 1_f32];
+                if self.boundary == BoundaryMode::Clamped {
+                    for n in 0..2 {
+                        if !open[n] && blocked[n] && open[1 - n] {
+                            shares[1 - n] += shares[n];
+                            shares[n] = 0_f32;
+                            sends[1 - n] += sends[n];
+                            sends[n] = 0_f32;
+                        }
+                    }
+                }
                 let mut i = 0;
-                let bias: f32 = random();
-                let biases = [bias, 1.0 - bias];
-                for n in 0..2 {
-                    if prop_amounts[i].is_some() && prop_amounts[i + 1].is_some() {
+                for (n, (share, send)) in shares.into_iter().zip(sends).enumerate() {
+                    if open[n] {
+                        budget += added_stress * share * pc;
+                        dissipated += added_stress * share * (1_f32 - pc);
                         let mut scaled_props: [f32; 2] = [0_f32; 2];
                         // unwrapped prop_amts
                         let prop_amounts_ = [prop_amounts[i].unwrap().max(MIN_STRESS), prop_amounts[i + 1].unwrap().max(MIN_STRESS)];
-                        scaled_props[0] = Self::weak_path_bias_fn(prop_amounts_[0] / prop_amounts_[1]);
-                        scaled_props[1] = Self::weak_path_bias_fn(prop_amounts_[1] / prop_amounts_[0]);
+                        scaled_props[0] = self.weak_path_bias_fn(prop_amounts_[0] / prop_amounts_[1]);
+                        scaled_props[1] = self.weak_path_bias_fn(prop_amounts_[1] / prop_amounts_[0]);
+                        gained += ((scaled_props[0] + scaled_props[1]) * send - share) * added_stress * pc;
 
                         //println!("Scaled props {:?}", scaled_props);
                         
                         for j in 0..2 {
                             let e = self.edge_matrix.get_mut(adjacent_edges[i + j].unwrap()).unwrap();
-                            let amt = scaled_props[j] * added_stress * PROPOGATION_CONST * biases[n]; // divide by 2 cuz half goes in each direction
-                            if amt > MIN_STRESS {
-                                e.add_stress_update(scaled_props[j] * added_stress * PROPOGATION_CONST, dir);
+                            let full = scaled_props[j] * added_stress * self.params.propagation_const;
+                            let amt = full * send;
+                            if full * share > MIN_STRESS {
+                                e.add_stress_update(amt, dir);
+                                sent += amt;
+                                if let Some(t) = self.transmitted.as_mut() {
+                                    t.push((e.index, amt, dir));
+                                }
                                 if e.get_update_status() != EdgeUpdateStatus::StressUpdate {
                                     e.set_scheduled_for_stress_update();
                                     self.update_edge_list.push(e.index);
                                 }
+                            } else {
+                                cut_off += amt;
                             }
                             
                         }
+                    } else {
+                        lost_at_border += added_stress * share;
                     }
                     dir = -dir;
                     i = 2;
@@ -325,10 +962,10 @@ impl Graph {
             } else {
                 //println!("prop non zero index: {:?}, dir: {:?}", edge.index, edge.prop_vec);
                 // propogate stress in the direction of this pvec
-                let dir = edge.prop_vec;
+                let mut dir = edge.prop_vec;
                 let added_stress = edge.stress;
                 
-                let adjacent_edges = Edge::get_adjacent_edges(edge.index, self.cols);
+                let adjacent_edges = self.get_adjacent_edges(edge.index);
                 let inversions = match edge.index.ty {
                     0 => [1_f32, 1_f32, -1_f32, -1_f32],
                     1 => [-1_f32, 1_f32, -1_f32, 1_f32],
@@ -336,54 +973,96 @@ impl Graph {
                     _ => unreachable!(),
                 };
 
-                let mut pre_prop_ratio = [(0_f32, None); 2];
-                let mut a = 0;
-                for i in 0..4 {
-                    if let Some(e) = adjacent_edges[i] {
-                        if let Some(ee) = self.edge_matrix.get_mut(e) {
-                            let amt = (ee.ty_to_prop_vec() * dir) * inversions[i]
-                                * if ee.cracked {
-                                    CRACKED_STRESS_REP
-                                } else {
-                                    1.0
-                                };
-                            if amt > 0_f32 {
-                                //println!("added edge {:?} with amt: {}", ee.index, amt);
-                                pre_prop_ratio[a] = (amt.max(MIN_STRESS), Some(e));
-                                a += 1
-                            }
-                        }
-                    }
+                let (mut pre_prop_ratio, mut a) = self.get_forward_edges(&adjacent_edges, &inversions, dir);
+                if a < 2 && self.boundary == BoundaryMode::Clamped && adjacent_edges.iter().any(|e| e.is_none()) {
+                    // bounce off of the border
+                    dir = -dir;
+                    (pre_prop_ratio, a) = self.get_forward_edges(&adjacent_edges, &inversions, dir);
                 }
-                debug_assert!(a < 3);
 
                 if a == 2 {
                     //println!("found 2");
+                    budget = added_stress * pc;
+                    dissipated = added_stress * (1_f32 - pc);
                     let mut scaled_props = [0_f32; 2];
-                    scaled_props[0] = Self::weak_path_bias_fn(pre_prop_ratio[0].0 / pre_prop_ratio[1].0);
-                    scaled_props[1] = Self::weak_path_bias_fn(pre_prop_ratio[1].0 / pre_prop_ratio[0].0);
+                    scaled_props[0] = self.weak_path_bias_fn(pre_prop_ratio[0].0 / pre_prop_ratio[1].0);
+                    scaled_props[1] = self.weak_path_bias_fn(pre_prop_ratio[1].0 / pre_prop_ratio[0].0);
+                    gained = (scaled_props[0] + scaled_props[1] - 1_f32) * added_stress * pc;
 
                     //println!("scaled: {:?}", scaled_props);
 
                     for i in 0..2 {
                         let e = self.edge_matrix.get_mut(pre_prop_ratio[i].1.unwrap()).unwrap();
-                        let amt = scaled_props[i] * added_stress * PROPOGATION_CONST;
+                        let amt = scaled_props[i] * added_stress * self.params.propagation_const;
                         if amt > MIN_STRESS {
-                            e.add_stress_update(scaled_props[i] * added_stress * PROPOGATION_CONST, dir);
+                            e.add_stress_update(amt, dir);
+                            sent += amt;
+                            if let Some(t) = self.transmitted.as_mut() {
+                                t.push((e.index, amt, dir));
+                            }
                             if e.get_update_status() != EdgeUpdateStatus::StressUpdate {
                                 e.set_scheduled_for_stress_update();
                                 self.update_edge_list.push(e.index);
                             }
+                        } else {
+                            cut_off += amt;
                         }
                         
                     }
+                } else if adjacent_edges.iter().any(|e| e.map_or(true, |e| self.edge_matrix.get(e).is_none())) {
+                    lost_at_border = added_stress;
+                } else {
+                    // nowhere to go
+                    dissipated = added_stress;
                 }
             }
+            // everything that was passed on went somewhere
+            debug_assert!((sent + cut_off - budget - gained).abs() <= 1e-4 * budget.max(1_f32), "sent {} + cut off {} != {} + {}", sent, cut_off, budget, gained);
+            if let Some(l) = self.energy_ledger.as_mut() {
+                l.step.split_gain += gained as f64;
+                l.step.dissipated += dissipated as f64;
+                l.step.lost_at_border += lost_at_border as f64;
+                l.step.cut_off += cut_off as f64;
+            }
             let edge = self.edge_matrix.get_mut(e).unwrap();
             edge.prop_vec = Default::default();
             edge.stress = 0.0;
             edge.set_update_status_propogated();
         }
+
+        if let Some(l) = self.energy_ledger.as_mut() {
+            l.end_step();
+        }
+        if self.propagating && self.update_edge_list.size() == 0 {
+            self.propagating = false;
+            let cracks = std::mem::take(&mut self.active_cracks);
+            self.emit(CrackEvent::PropagationSettled { cracks });
+        }
+    }
+
+    /// Returns the adjacent edges that lie in the direction of `dir` along with how much they line up with it
+    fn get_forward_edges(&self, adjacent_edges: &[Option<EdgeIndex>; 4], inversions: &[f32; 4], dir: PVec) -> ([(f32, Option<EdgeIndex>); 2], usize) {
+        let mut pre_prop_ratio = [(0_f32, None); 2];
+        let mut a = 0;
+        for i in 0..4 {
+            if let Some(e) = adjacent_edges[i] {
+                if let Some(ee) = self.edge_matrix.get(e) {
+                    let amt = (ee.ty_to_prop_vec() * dir) * inversions[i]
+                        * if ee.cracked {
+                            self.params.cracked_stress_rep
+                        } else {
+                            1.0
+                        };
+                    if amt > 0_f32 {
+                        //println!("added edge {:?} with amt: {}", ee.index, amt);
+                        pre_prop_ratio[a] = (amt.max(MIN_STRESS), Some(e));
+                        a += 1
+                    }
+                }
+            }
+        }
+        debug_assert!(a < 3);
+        (pre_prop_ratio, a)
     }
 
     fn valid_edge_assert_not(&mut self, s: EdgeUpdateStatus) -> bool {
@@ -463,12 +1142,32 @@ impl Graph {
         }
     }
 
-    pub fn get_random_edge_index(&self) -> EdgeIndex {
-        let att = EdgeIndex { row: (random::<usize>() % (self.rows - 2)) + 1, col: (random::<usize>() % (self.cols - 2)) + 1, ty: random::<usize>() % 3 };
-        if self.edge_matrix.get(att).expect("shouldn't be none").cracked {
-            self.get_random_edge_index()
+    /// A random number from 0 to 1, from the same rng as everything else so seeded runs and rewinds repeat
+    pub fn random_fraction(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    /// A random uncracked edge away from the border. `None` if there aren't any left
+    pub fn get_random_edge_index(&mut self) -> Option<EdgeIndex> {
+        for _ in 0..RANDOM_EDGE_TRIES {
+            let att = EdgeIndex { row: self.rng.gen_range(1..self.rows - 1), col: self.rng.gen_range(1..self.cols - 1), ty: self.rng.gen_range(0..3) };
+            // edges may have been removed by a mask
+            if self.edge_matrix.get(att).is_some_and(|e| !e.cracked) {
+                return Some(att);
+            }
+        }
+        // most of the sheet is masked off or cracked, so pick from what's left
+        let live: Vec<EdgeIndex> = self.edge_matrix.v[1..self.rows - 1].iter()
+            .flat_map(|r| &r[1..self.cols - 1])
+            .flatten()
+            .flatten()
+            .filter(|e| !e.cracked)
+            .map(|e| e.index)
+            .collect();
+        if live.is_empty() {
+            None
         } else {
-            att
+            Some(live[self.rng.gen_range(0..live.len())])
         }
     }
 }
@@ -489,7 +1188,11 @@ impl EdgeMatrix {
     }
 
     pub fn get_mut(&mut self, i: EdgeIndex) -> Option<&mut Edge> {
-        self.v[i.row][i.col][i.ty].as_mut()
+        let e = self.v[i.row][i.col][i.ty].as_mut()?;
+        if let Some(j) = self.journal.as_mut() {
+            j.save(e);
+        }
+        Some(e)
     }
 }
 
@@ -551,13 +1254,57 @@ mod tests {
         g.debug_print(Some(Path::new("test")));
     }
 
+    #[test]
+    fn test_apply_mask() {
+        let mut g = Graph::new(100, 100);
+        g.set_node_ndcs(0.5, 0.0, 1.0 / 100.0, 1.0 / 100.0);
+        // keep a disc in the middle of the screen
+        g.apply_mask(|v| v.position[0] * v.position[0] + v.position[1] * v.position[1] < 0.25);
+
+        for r in 0..g.rows {
+            for c in 0..g.cols {
+                let n = g.node_matrix.get([r, c].into());
+                let p = n.ndc.unwrap().position;
+                for e in n.edges.iter().flatten() {
+                    assert!(p[0] * p[0] + p[1] * p[1] < 0.25);
+                    assert!(g.edge_matrix.get(*e).is_some());
+                }
+            }
+        }
+        assert!(g.update_edge_list.v.iter().all(|e| g.edge_matrix.get(*e).is_some()));
+
+        for _ in 0..5 {
+            let i = g.get_random_edge_index().unwrap();
+            g.add_stress(i, 100.0).unwrap();
+        }
+        for _ in 0..50 {
+            g.main_loop();
+        }
+    }
+
+    #[test]
+    fn test_random_edge_small_mask() {
+        let mut g = Graph::new(100, 100);
+        g.set_node_ndcs(0.5, 0.0, 1.0 / 100.0, 1.0 / 100.0);
+        // only a few edges left, too few to hit by chance
+        g.apply_mask(|v| v.position[0] * v.position[0] + v.position[1] * v.position[1] < 0.0009);
+        for _ in 0..10 {
+            let i = g.get_random_edge_index().unwrap();
+            assert!(g.edge_matrix.get(i).is_some_and(|e| !e.cracked));
+        }
+
+        // no ice at all
+        g.apply_mask(|_| false);
+        assert_eq!(g.get_random_edge_index(), None);
+    }
+
     #[test]
     fn test_get_adjacent_edges_rand() {
         let g = Graph::new(100, 100);
         for e in &g.edge_matrix.v {
             for ee in e {
-                for i in 0..3 {
-                    if let Some(edge) = ee[i].as_ref() {
+                for (i, eee) in ee.iter().enumerate() {
+                    if let Some(edge) = eee.as_ref() {
                         if edge.index.col != 0 && edge.index.col != 99 && edge.index.row != 0 && edge.index.row != 99 {
                             let adjacent_edges = Edge::get_adjacent_edges(edge.index, g.cols);
                             let e1;
@@ -596,4 +1343,343 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_get_wrapped_adjacent_edges() {
+        let g = Graph::with_boundary(100, 100, BoundaryMode::Wrapping);
+        for r in 0..g.rows {
+            for c in 0..g.cols {
+                let n = g.node_matrix.get([r, c].into());
+                assert!(n.edges.iter().all(|e| e.is_some()));
+            }
+        }
+
+        // unlike the unwrapped graph, the border edges have to line up too
+        for e in &g.edge_matrix.v {
+            for ee in e {
+                for (i, eee) in ee.iter().enumerate() {
+                    let edge = eee.as_ref().expect("wrapped graphs have every edge");
+                    let adjacent_edges = g.get_adjacent_edges(edge.index);
+                    let expected = match i {
+                        0 => [
+                            g.node_matrix.get(edge.nodes[0]).edges[1],
+                            g.node_matrix.get(edge.nodes[1]).edges[2],
+                            g.node_matrix.get(edge.nodes[0]).edges[5],
+                            g.node_matrix.get(edge.nodes[1]).edges[4],
+                        ],
+                        1 => [
+                            g.node_matrix.get(edge.nodes[1]).edges[2],
+                            g.node_matrix.get(edge.nodes[0]).edges[3],
+                            g.node_matrix.get(edge.nodes[1]).edges[0],
+                            g.node_matrix.get(edge.nodes[0]).edges[5],
+                        ],
+                        2 => [
+                            g.node_matrix.get(edge.nodes[0]).edges[0],
+                            g.node_matrix.get(edge.nodes[1]).edges[1],
+                            g.node_matrix.get(edge.nodes[0]).edges[4],
+                            g.node_matrix.get(edge.nodes[1]).edges[3],
+                        ],
+                        _ => unreachable!(),
+                    };
+                    assert_eq!(adjacent_edges, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_boundary_modes_main_loop() {
+        for mode in [BoundaryMode::Free, BoundaryMode::Clamped, BoundaryMode::Wrapping] {
+            let mut g = Graph::with_boundary(50, 50, mode);
+            g.set_node_ndcs(0.5, 0.0, 1.0 / 50.0, 1.0 / 50.0);
+            g.main_loop();
+            // right up against the corner
+            g.add_stress(EdgeIndex { row: 0, col: 0, ty: 0 }, 100.0).unwrap();
+            g.add_stress(EdgeIndex { row: 48, col: 48, ty: 2 }, 100.0).unwrap();
+            let mut triangles = Vec::new();
+            for _ in 0..100 {
+                g.update_graph_edge_stresses(Some(&mut triangles));
+                g.update_graph_stress_propagation();
+            }
+            assert!(triangles.len() % 3 == 0);
+        }
+    }
+
+    #[test]
+    fn test_clamped_keeps_energy() {
+        // a crack on the top border, half of whose stress heads off the screen
+        let index = EdgeIndex { row: 0, col: 10, ty: 0 };
+        let after = |mode| {
+            let mut g = Graph::seeded(20, 20, mode, 3);
+            g.main_loop();
+            g.add_stress(index, 100.0).unwrap();
+            g.update_graph_edge_stresses(None);
+            let (before, moving) = (g.total_energy(), g.edge_matrix.get(index).unwrap().stress as f64);
+            g.start_energy_ledger();
+            g.update_graph_stress_propagation();
+            let gain = g.energy_ledger().unwrap().last_step.split_gain;
+            (before, moving, gain, g.total_energy())
+        };
+        let (before, moving, gain, clamped) = after(BoundaryMode::Clamped);
+        let (_, _, _, free) = after(BoundaryMode::Free);
+        let pc = GraphParams::default().propagation_const as f64;
+        // clamped only loses what every hop loses, and sends back exactly what was blocked
+        assert!((clamped - (before - moving * (1.0 - pc) + gain)).abs() < 1e-2, "{} {} {} {}", before, moving, gain, clamped);
+        assert!(free < clamped - moving / 10.0);
+    }
+
+    #[test]
+    fn test_undirected_sends_both_ways() {
+        // away from the border, stress with no direction goes in full to both sides whatever the boundary
+        for mode in [BoundaryMode::Free, BoundaryMode::Clamped, BoundaryMode::Wrapping] {
+            let mut g = Graph::seeded(20, 20, mode, 5);
+            g.main_loop();
+            let index = EdgeIndex { row: 10, col: 10, ty: 0 };
+            let adjacent: Vec<EdgeIndex> = g.get_adjacent_edges(index).into_iter().flatten().collect();
+            assert_eq!(adjacent.len(), 4);
+            // equally weak paths split the stress evenly
+            for e in &adjacent {
+                g.edge_matrix.get_mut(*e).unwrap().stress = 1.0;
+            }
+            g.edge_matrix.get_mut(index).unwrap().stress = 100.0;
+            g.update_edge_list.push(index);
+            g.update_graph_stress_propagation();
+            let expected = 1.0 + 0.5 * 100.0 * g.params.propagation_const;
+            for e in &adjacent {
+                let total = g.edge_matrix.get(*e).unwrap().total_stress();
+                assert!((total - expected).abs() < 1e-3, "{:?} {:?}: {} != {}", mode, e, total, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_couple_to() {
+        let mut above = Graph::seeded(40, 40, BoundaryMode::Free, 1);
+        let mut below = Graph::seeded(40, 40, BoundaryMode::Free, 2);
+        above.main_loop();
+        below.main_loop();
+        above.keep_transmitted();
+        above.add_stress(EdgeIndex { row: 20, col: 20, ty: 0 }, 100.0).unwrap();
+        below.start_energy_ledger();
+        let mut moved = 0_f64;
+        for _ in 0..20 {
+            above.main_loop();
+            moved += above.transmitted.as_ref().unwrap().iter().map(|t| t.1 as f64).sum::<f64>();
+            below.couple_to(&mut above, 0.5);
+            assert!(above.transmitted.as_ref().unwrap().is_empty());
+            below.main_loop();
+        }
+        // the stress below only comes from above, and moves along cracks of its own once it's there
+        let l = below.energy_ledger().unwrap();
+        assert!(moved > 100.0);
+        assert!(((l.total.injected + l.step.injected) - moved * 0.5).abs() < 1e-2 * moved);
+        assert!(!below.cracked_edges.is_empty());
+
+        // nothing gets through without coupling
+        let mut below = Graph::seeded(40, 40, BoundaryMode::Free, 2);
+        below.main_loop();
+        let start = below.total_energy();
+        above.add_stress(EdgeIndex { row: 10, col: 10, ty: 0 }, 100.0).unwrap();
+        above.main_loop();
+        below.couple_to(&mut above, 0.0);
+        assert_eq!(below.total_energy(), start);
+    }
+
+    #[test]
+    fn test_wrapping_crosses_seam() {
+        // a / edge on the left side, half of whose stress heads off the screen
+        let index = EdgeIndex { row: 11, col: 0, ty: 1 };
+        for mode in [BoundaryMode::Free, BoundaryMode::Wrapping] {
+            let mut g = Graph::seeded(20, 20, mode, 1);
+            g.main_loop();
+            let far_side = |g: &Graph| -> f64 {
+                g.edge_matrix.v.iter().flatten().flatten().flatten()
+                    .filter(|e| e.index.col == g.cols - 1)
+                    .map(|e| e.total_stress() as f64)
+                    .sum()
+            };
+            let before = far_side(&g);
+            g.add_stress(index, 100.0).unwrap();
+            g.update_graph_edge_stresses(None);
+            g.update_graph_stress_propagation();
+            let gained = far_side(&g) - before;
+            match mode {
+                BoundaryMode::Wrapping => assert!(gained > 1.0, "nothing came out the right side"),
+                _ => assert_eq!(gained, 0.0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_for_screen_rows_on_screen() {
+        for boundary in [BoundaryMode::Free, BoundaryMode::Wrapping] {
+            // 120 / sqrt(3) * 2 rounds up to an odd number of rows, so wrapping adds one
+            let g = Graph::for_screen(64, 120, boundary, Some(0));
+            let last = g.node_matrix.get([g.rows - 1, 0].into()).ndc.unwrap().position[1];
+            assert!((-1.001..-0.95).contains(&last), "{:?} last row at {}", boundary, last);
+        }
+    }
+
+    #[test]
+    fn test_crack_path() {
+        let mut g = Graph::new(100, 100);
+        g.set_node_ndcs(0.5, 0.0, 1.0 / 100.0, 1.0 / 100.0);
+        g.main_loop();
+
+        let mut triangles = Vec::new();
+        g.crack_path(&[[-0.5, 0.0].into(), [0.5, 0.0].into(), [0.5, -0.5].into()], Some(&mut triangles));
+        let cracked: Vec<&Edge> = g.edge_matrix.v.iter()
+            .flatten()
+            .flatten()
+            .flatten()
+            .filter(|e| e.cracked)
+            .collect();
+        // roughly 50 columns across and 25 rows down
+        assert!(cracked.len() >= 70 && cracked.len() < 150);
+        assert!(cracked.iter().all(|e| e.stress == 0_f32));
+        assert!(!triangles.is_empty() && triangles.len() % 3 == 0);
+
+        // the cracks are permanent but stress still moves through the rest of the graph
+        let i = g.get_random_edge_index().unwrap();
+        g.add_stress(i, 50.0).unwrap();
+        for _ in 0..50 {
+            g.main_loop();
+        }
+    }
+
+    #[test]
+    fn test_params() {
+        let count_cracked = |g: &Graph| g.edge_matrix.v.iter()
+            .flatten()
+            .flatten()
+            .flatten()
+            .filter(|e| e.cracked)
+            .count();
+
+        let mut soft = Graph::new(50, 50);
+        let mut hard = Graph::new(50, 50);
+        hard.set_params(GraphParams { crack_threshold: 1000.0, ..Default::default() });
+        assert_eq!(soft.params(), GraphParams::default());
+        for g in [&mut soft, &mut hard] {
+            g.main_loop();
+            g.add_stress(EdgeIndex { row: 25, col: 25, ty: 0 }, 100.0).unwrap();
+            for _ in 0..20 {
+                g.main_loop();
+            }
+        }
+        assert!(count_cracked(&soft) > 0);
+        assert_eq!(count_cracked(&hard), 0);
+    }
+
+    #[test]
+    fn test_crack_events() {
+        let mut g = Graph::new(50, 50);
+        g.set_node_ndcs(0.5, 0.0, 1.0 / 50.0, 1.0 / 50.0);
+        g.main_loop();
+        let rx = g.subscribe();
+
+        g.add_stress(EdgeIndex { row: 25, col: 25, ty: 0 }, 100.0).unwrap();
+        for _ in 0..200 {
+            g.main_loop();
+        }
+        let events: Vec<CrackEvent> = rx.try_iter().collect();
+        assert!(matches!(events[0], CrackEvent::CrackStarted { .. }));
+        assert!(matches!(events[1], CrackEvent::EdgeCracked { edge: EdgeIndex { row: 25, col: 25, ty: 0 }, .. }));
+        assert!(matches!(events.last().unwrap(), CrackEvent::PropagationSettled { .. }));
+
+        let n_cracked = g.edge_matrix.v.iter().flatten().flatten().flatten().filter(|e| e.cracked).count();
+        let n_events = events.iter().filter(|e| matches!(e, CrackEvent::EdgeCracked { .. })).count();
+        assert_eq!(n_cracked, n_events);
+
+        // dropping the receiver unsubscribes
+        drop(rx);
+        let i = g.get_random_edge_index().unwrap();
+        g.add_stress(i, 100.0).unwrap();
+        g.main_loop();
+        assert!(g.subscribers.is_empty());
+    }
+
+    #[test]
+    fn test_fragment_formed() {
+        let mut g = Graph::new(10, 10);
+        g.set_node_ndcs(0.5, 0.0, 1.0 / 10.0, 1.0 / 10.0);
+        g.main_loop();
+        let rx = g.subscribe();
+
+        // crack all three sides of the triangle below node (4, 4)
+        let sides = [EdgeIndex { row: 4, col: 4, ty: 0 }, EdgeIndex { row: 4, col: 4, ty: 2 }, EdgeIndex { row: 4, col: 5, ty: 1 }];
+        for e in sides {
+            g.edge_matrix.get_mut(e).unwrap().add_stress(10.0, &mut g.update_edge_list);
+        }
+        g.update_graph_edge_stresses(None);
+        let events: Vec<CrackEvent> = rx.try_iter().collect();
+        let started = events.iter().filter(|e| matches!(e, CrackEvent::CrackStarted { .. })).count();
+        let fragments = events.iter().filter(|e| matches!(e, CrackEvent::FragmentFormed { .. })).count();
+        assert_eq!(started, 1);
+        assert_eq!(fragments, 1);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut g = Graph::new(100, 100);
+        g.set_node_ndcs(0.5, 0.0, 1.0 / 100.0, 1.0 / 100.0);
+        g.main_loop();
+        assert_eq!(g.stats(100, 100), CrackStats { queue_depth: g.get_update_amt(), ..Default::default() });
+
+        // two separate horizontal cracks
+        g.crack_path(&[[-1.0, 0.5].into(), [1.0, 0.5].into()], None);
+        g.crack_path(&[[-1.0, -0.5].into(), [1.0, -0.5].into()], None);
+        let stats = g.stats(100, 100);
+        assert_eq!(stats.crack_networks, 2);
+        assert_eq!(stats.crack_length, stats.cracked_edges as f32);
+        // each horizontal edge is one pixel across
+        assert!((stats.crack_length_px - stats.crack_length).abs() < 0.01);
+        assert!(stats.cracked_fraction > 0_f32 && stats.cracked_fraction < 0.02);
+        assert!((stats.fractal_dimension - 1_f32).abs() < 0.2, "{}", stats.fractal_dimension);
+
+        g.reset_stats();
+        assert_eq!(g.stats(100, 100).cracked_edges, 0);
+    }
+
+    #[test]
+    fn test_energy_conservation() {
+        for boundary in [BoundaryMode::Free, BoundaryMode::Clamped, BoundaryMode::Wrapping] {
+            let mut g = Graph::with_boundary(50, 50, boundary);
+            g.main_loop();
+            g.start_energy_ledger();
+            for _ in 0..5 {
+                let i = g.get_random_edge_index().unwrap();
+                g.add_stress(i, 20.0).unwrap();
+                for _ in 0..20 {
+                    g.main_loop();
+                }
+            }
+            let l = g.energy_ledger().unwrap();
+            assert_eq!(l.total.injected, 100.0);
+            assert!(l.total.released_by_cracking > 0.0);
+            assert!(l.total.dissipated > 0.0);
+            let imbalance = g.energy_imbalance().unwrap();
+            assert!(imbalance.abs() < 0.01, "{:?} imbalance: {}", boundary, imbalance);
+        }
+    }
+
+    #[test]
+    fn test_seeded() {
+        let run = |seed| {
+            let mut g = Graph::seeded(50, 50, BoundaryMode::Free, seed);
+            g.main_loop();
+            for _ in 0..3 {
+                let i = g.get_random_edge_index().unwrap();
+                g.add_stress(i, 20.0).unwrap();
+                for _ in 0..20 {
+                    g.main_loop();
+                }
+            }
+            (g.cracked_edges.clone(), g.total_energy())
+        };
+        let (cracks, energy) = run(7);
+        assert!(!cracks.is_empty());
+        assert_eq!(run(7), (cracks.clone(), energy));
+        assert_ne!(run(8).0, cracks);
+    }
+}
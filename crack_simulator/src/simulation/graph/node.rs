@@ -1,10 +1,11 @@
 use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
 
 use super::{NodeMatrix, EdgeMatrix};
 use super::edge::EdgeIndex;
 use crate::graphics::vertex::Vertex;
 
-#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct NodeIndex {
     pub row: usize,
     pub col: usize,
@@ -19,7 +20,7 @@ impl From<[usize; 2]> for NodeIndex {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Node {
     // the implicit stress in the node
     pub edges: [Option<EdgeIndex>; 6],
@@ -97,7 +98,7 @@ impl Node {
             let edge = e_matrix.get(self.edges[e].expect("edge shouldn't be None")).expect("edge shouldn't be None");
             match e {
                 0 | 4 | 5 => assert!(edge.nodes[0] == self.index),
-                1 | 2 | 3 => assert!(edge.nodes[1] == self.index),
+                1..=3 => assert!(edge.nodes[1] == self.index),
                 _ => unreachable!(),
             }
             edge.verify(n_matrix)
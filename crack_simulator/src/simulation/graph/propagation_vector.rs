@@ -1,8 +1,9 @@
 use std::ops::{Mul, Add, Neg};
+use serde::{Deserialize, Serialize};
 
 
 /// Propogation vector in edges
-#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct PVec {
     v: [f32; 2],
 }
@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use bincode::Options;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use super::Graph;
+
+/// first bytes of every snapshot. Bump the number when the layout of the graph changes
+const MAGIC: &[u8; 4] = b"ICE2";
+/// most bytes a row and column of the graph can unpack to. A fully cracked graph takes about half of this
+const CELL_SIZE: u64 = 1024;
+/// room for everything in the graph that isn't kept per row and column
+const EXTRA_SIZE: u64 = 1 << 20;
+
+/// most bytes a graph with `rows` and `cols` can unpack to, so a corrupt length can't ask for more memory than a
+/// real graph that size would need
+fn snapshot_limit((rows, cols): (usize, usize)) -> u64 {
+    (rows as u64).saturating_mul(cols as u64).saturating_mul(CELL_SIZE).saturating_add(EXTRA_SIZE)
+}
+
+/// the same encoding as `bincode::serialize`, so older snapshots still load
+fn bincode_options(limit: u64) -> impl Options {
+    bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+}
+
+impl Graph {
+    /// Save the whole state of the graph to a file.
+    /// Subscribers and the energy ledger aren't saved
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_snapshot(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Load a graph saved with `save_snapshot`. It has to have `dimensions` rows and columns, see `dimensions`
+    pub fn load_snapshot(path: &Path, dimensions: (usize, usize)) -> Result<Self, Box<dyn Error>> {
+        Self::read_snapshot(BufReader::new(File::open(path)?), dimensions)
+    }
+
+    pub fn write_snapshot<W: Write>(&self, mut w: W) -> Result<(), Box<dyn Error>> {
+        w.write_all(MAGIC)?;
+        let mut encoder = GzEncoder::new(w, Compression::fast());
+        bincode_options(snapshot_limit(self.dimensions())).serialize_into(&mut encoder, self)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    pub fn read_snapshot<R: Read>(r: R, dimensions: (usize, usize)) -> Result<Self, Box<dyn Error>> {
+        Self::read_snapshot_limited(r, dimensions, snapshot_limit(dimensions))
+    }
+
+    fn read_snapshot_limited<R: Read>(mut r: R, dimensions: (usize, usize), limit: u64) -> Result<Self, Box<dyn Error>> {
+        let mut magic = [0_u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err("not a graph snapshot, or one from an incompatible version".into());
+        }
+        // the graph starts with its rows and columns, so check them before unpacking the rest
+        let mut r = GzDecoder::new(r);
+        let mut header = [0_u8; 16];
+        r.read_exact(&mut header)?;
+        let (rows, cols): (u64, u64) = bincode_options(16).deserialize(&header)?;
+        if (rows, cols) != (dimensions.0 as u64, dimensions.1 as u64) {
+            return Err(format!("saved with {} rows and {} columns instead of {} and {}", rows, cols, dimensions.0, dimensions.1).into());
+        }
+        Ok(bincode_options(limit).deserialize_from(header.as_slice().chain(r))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let mut g = Graph::new(40, 40);
+        g.set_node_ndcs(0.5, 0.0, 1.0 / 40.0, 1.0 / 40.0);
+        let i = g.get_random_edge_index().unwrap();
+        g.add_stress(i, 20.0).unwrap();
+        for _ in 0..5 {
+            g.main_loop();
+        }
+
+        let mut buf = Vec::new();
+        g.write_snapshot(&mut buf).unwrap();
+        let mut loaded = Graph::read_snapshot(buf.as_slice(), (40, 40)).unwrap();
+        assert!(loaded.validate().is_ok());
+        assert_eq!(loaded.stats(40, 40), g.stats(40, 40));
+        assert_eq!(loaded.total_energy(), g.total_energy());
+
+        // both copies carry on the same way given the same seed. The rng isn't saved
+        g.reseed(3);
+        loaded.reseed(3);
+        g.main_loop();
+        loaded.main_loop();
+        assert_eq!(loaded.get_update_amt(), g.get_update_amt());
+
+        assert!(Graph::read_snapshot(&b"nope"[..], (40, 40)).is_err());
+        // a different size of graph is turned away before it's unpacked
+        assert!(Graph::read_snapshot(buf.as_slice(), (40, 41)).is_err());
+
+        // too big to unpack
+        assert!(Graph::read_snapshot_limited(buf.as_slice(), (40, 40), 1000).is_err());
+    }
+}
+
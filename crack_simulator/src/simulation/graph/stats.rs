@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+use super::edge::EdgeIndex;
+
+/// Snapshot of how broken a graph is
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CrackStats {
+    pub cracked_edges: usize,
+    /// total length of the cracks in edge lengths
+    pub crack_length: f32,
+    /// total length of the cracks in screen pixels
+    pub crack_length_px: f32,
+    /// number of separate groups of connected cracks
+    pub crack_networks: usize,
+    /// cracked edges / edges in the sheet
+    pub cracked_fraction: f32,
+    /// number of edges waiting to be updated
+    pub queue_depth: usize,
+    /// box-counting dimension of the cracks. 0 if there isn't enough to measure
+    pub fractal_dimension: f32,
+}
+
+/// Estimate the box-counting dimension of a set of edges.
+/// Counts the boxes containing an edge for box sizes of 2, 4, 8 ... edge lengths and fits a line
+/// to log(count) against log(1 / size)
+pub fn box_counting_dimension(edges: &[EdgeIndex], rows: usize, cols: usize) -> f32 {
+    if edges.len() < 2 {
+        return 0_f32;
+    }
+    let mut points = Vec::new();
+    let mut size = 2;
+    while size <= rows.min(cols) / 2 {
+        let (box_rows, box_cols) = (rows.div_ceil(size), cols.div_ceil(size));
+        let mut boxes = vec![false; box_rows * box_cols];
+        for e in edges {
+            boxes[(e.row / size) * box_cols + e.col / size] = true;
+        }
+        let count = boxes.iter().filter(|b| **b).count();
+        points.push(((1_f32 / size as f32).ln(), (count as f32).ln()));
+        size *= 2;
+    }
+    if points.len() < 2 {
+        return 0_f32;
+    }
+
+    // least squares slope
+    let n = points.len() as f32;
+    let mean_x = points.iter().map(|p| p.0).sum::<f32>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f32>() / n;
+    let cov: f32 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let var: f32 = points.iter().map(|p| (p.0 - mean_x) * (p.0 - mean_x)).sum();
+    cov / var
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_box_counting_dimension() {
+        // a straight line should be close to 1
+        let line: Vec<EdgeIndex> = (0..256).map(|c| EdgeIndex { row: 128, col: c, ty: 0 }).collect();
+        let d = box_counting_dimension(&line, 256, 256);
+        assert!((d - 1_f32).abs() < 0.05, "line dimension: {}", d);
+
+        // a filled area should be close to 2
+        let mut area = Vec::new();
+        for r in 0..256 {
+            for c in 0..256 {
+                area.push(EdgeIndex { row: r, col: c, ty: 0 });
+            }
+        }
+        let d = box_counting_dimension(&area, 256, 256);
+        assert!((d - 2_f32).abs() < 0.05, "area dimension: {}", d);
+
+        assert_eq!(box_counting_dimension(&[], 256, 256), 0_f32);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use super::Graph;
+use super::edge::{EdgeIndex, EdgeUpdateStatus};
+use super::node::NodeIndex;
+
+/// Most problems kept in a report. Anything after this is only counted
+const MAX_PROBLEMS: usize = 1000;
+
+/// Something wrong with the state of a graph
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    /// in the update queue but either missing from the graph or not marked as needing an update
+    BadQueueEntry(EdgeIndex),
+    /// in the update queue more than once
+    QueuedTwice(EdgeIndex),
+    /// marked as needing an update but not in the update queue
+    NotQueued(EdgeIndex),
+    /// the update queue holds both stress and propagation updates
+    MixedQueue,
+    /// a cracked edge holding stress it isn't about to propagate
+    CrackedWithStress(EdgeIndex, f32),
+    /// a propagation vector that is neither unit length nor zero. Holds the length
+    BadPropVec(EdgeIndex, f32),
+    NotANumber(EdgeIndex),
+    /// the first edge is adjacent to the second, but not the other way around
+    AsymmetricAdjacency(EdgeIndex, EdgeIndex),
+    /// a node and an edge that don't agree on whether they're connected
+    BadNodeLink(NodeIndex, EdgeIndex),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    /// the first `MAX_PROBLEMS` problems found
+    pub problems: Vec<Problem>,
+    /// number of problems found, including the ones that weren't kept
+    pub problem_count: usize,
+    pub edges_checked: usize,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.problem_count == 0
+    }
+
+    fn push(&mut self, p: Problem) {
+        if self.problems.len() < MAX_PROBLEMS {
+            self.problems.push(p);
+        }
+        self.problem_count += 1;
+    }
+}
+
+impl Graph {
+    /// Check the invariants of the graph. Only meaningful between updates, not halfway through one.
+    /// This walks the whole graph, so it's too slow to run every frame. See `Validator` for that
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let queued = self.check_queue(&mut report);
+        self.check_rows(0..self.validated_rows(), Some(&queued), &mut report);
+        report
+    }
+
+    /// rows of edges or nodes, whichever has more
+    fn validated_rows(&self) -> usize {
+        self.edge_matrix.v.len().max(self.node_matrix.v.len())
+    }
+
+    /// Checks the update queue and returns the edges in it
+    fn check_queue(&self, report: &mut ValidationReport) -> HashSet<EdgeIndex> {
+        let mut queued = HashSet::with_capacity(self.update_edge_list.size());
+        let mut queue_status = None;
+        for e in &self.update_edge_list.v {
+            if !queued.insert(*e) {
+                report.push(Problem::QueuedTwice(*e));
+                continue;
+            }
+            match self.edge_matrix.get(*e).map(|e| e.get_update_status()) {
+                None | Some(EdgeUpdateStatus::NoUpdate) => report.push(Problem::BadQueueEntry(*e)),
+                Some(s) => {
+                    if queue_status.is_some_and(|q| q != s) {
+                        report.push(Problem::MixedQueue);
+                    }
+                    queue_status = Some(s);
+                }
+            }
+        }
+        queued
+    }
+
+    /// Checks the edges and nodes in `rows`. Edges missing from the queue are only found if `queued` is given
+    fn check_rows(&self, rows: Range<usize>, queued: Option<&HashSet<EdgeIndex>>, report: &mut ValidationReport) {
+        let clamp = |len: usize| rows.start.min(len)..rows.end.min(len);
+        for e in self.edge_matrix.v[clamp(self.edge_matrix.v.len())].iter().flatten().flatten().flatten() {
+            report.edges_checked += 1;
+            let i = e.index;
+            if e.get_update_status() != EdgeUpdateStatus::NoUpdate && queued.is_some_and(|q| !q.contains(&i)) {
+                report.push(Problem::NotQueued(i));
+            }
+            if !e.total_stress().is_finite() || !e.prop_vec.x().is_finite() || !e.prop_vec.y().is_finite() {
+                report.push(Problem::NotANumber(i));
+                continue;
+            }
+            if e.cracked && e.stress != 0_f32 && e.get_update_status() != EdgeUpdateStatus::PropogationUpdate {
+                report.push(Problem::CrackedWithStress(i, e.stress));
+            }
+            let m = e.prop_vec.modulus();
+            if !e.prop_vec.is_zero() && (m - 1_f32).abs() > 0.01 {
+                report.push(Problem::BadPropVec(i, m));
+            }
+
+            for a in self.get_adjacent_edges(i).into_iter().flatten() {
+                if self.edge_matrix.get(a).is_some() && !self.get_adjacent_edges(a).contains(&Some(i)) {
+                    report.push(Problem::AsymmetricAdjacency(i, a));
+                }
+            }
+            for n in e.nodes {
+                if !self.node_matrix.get(n).edges.contains(&Some(i)) {
+                    report.push(Problem::BadNodeLink(n, i));
+                }
+            }
+        }
+
+        for n in self.node_matrix.v[clamp(self.node_matrix.v.len())].iter().flatten() {
+            for e in n.edges.iter().flatten() {
+                if !self.edge_matrix.get(*e).is_some_and(|e| e.nodes.contains(&n.index)) {
+                    report.push(Problem::BadNodeLink(n.index, *e));
+                }
+            }
+        }
+    }
+}
+
+/// Validates a graph a few rows at a time, so a full resolution graph can be checked during a show without
+/// holding up a frame. The graph keeps changing between calls, so edges missing from the update queue aren't
+/// looked for. Use `Graph::validate` for that
+#[derive(Default)]
+pub struct Validator {
+    next_row: usize,
+    report: ValidationReport,
+}
+
+impl Validator {
+    /// Check the next `rows` rows of `graph`. Once every row has been checked the update queue is too, and the
+    /// report for the whole graph is returned before starting over from the top
+    pub fn step(&mut self, graph: &Graph, rows: usize) -> Option<ValidationReport> {
+        let total = graph.validated_rows();
+        let end = (self.next_row + rows.max(1)).min(total);
+        graph.check_rows(self.next_row..end, None, &mut self.report);
+        self.next_row = end;
+        if end < total {
+            return None;
+        }
+        graph.check_queue(&mut self.report);
+        self.next_row = 0;
+        Some(std::mem::take(&mut self.report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::propagation_vector::PVec;
+    use super::super::BoundaryMode;
+
+    #[test]
+    fn test_validate() {
+        assert!(Graph::with_boundary(20, 20, BoundaryMode::Wrapping).validate().is_ok());
+        let mut g = Graph::new(50, 50);
+        assert!(g.validate().is_ok());
+        for _ in 0..3 {
+            let i = g.get_random_edge_index().unwrap();
+            g.add_stress(i, 20.0).unwrap();
+            for _ in 0..10 {
+                g.main_loop();
+                let report = g.validate();
+                assert!(report.is_ok(), "{:?}", report.problems);
+            }
+        }
+
+        let e = EdgeIndex { row: 10, col: 10, ty: 0 };
+        let edge = g.get_edge_mut(e).unwrap();
+        edge.stress = f32::NAN;
+        let e2 = EdgeIndex { row: 20, col: 20, ty: 1 };
+        let edge = g.get_edge_mut(e2).unwrap();
+        edge.cracked = true;
+        edge.stress = 1.0;
+        edge.prop_vec = PVec::new(2.0, 0.0);
+        let e3 = EdgeIndex { row: 30, col: 30, ty: 2 };
+        g.get_edge_mut(e3).unwrap().set_scheduled_for_stress_update();
+        g.node_matrix.get_mut([5, 5].into()).edges[0] = None;
+
+        let report = g.validate();
+        assert!(!report.is_ok());
+        assert!(report.problems.contains(&Problem::NotANumber(e)));
+        assert!(report.problems.contains(&Problem::CrackedWithStress(e2, 1.0)));
+        assert!(report.problems.contains(&Problem::BadPropVec(e2, 2.0)));
+        assert!(report.problems.contains(&Problem::NotQueued(e3)));
+        assert!(report.problems.contains(&Problem::BadNodeLink([5, 5].into(), EdgeIndex { row: 5, col: 5, ty: 0 })));
+    }
+
+    #[test]
+    fn test_validator() {
+        let mut g = Graph::new(50, 50);
+        let mut v = Validator::default();
+        let full = g.validate();
+        let mut steps = 1;
+        let report = loop {
+            if let Some(r) = v.step(&g, 7) {
+                break r;
+            }
+            steps += 1;
+        };
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.edges_checked, full.edges_checked);
+        assert_eq!(steps, g.validated_rows().div_ceil(7));
+
+        let e = EdgeIndex { row: 40, col: 10, ty: 1 };
+        g.get_edge_mut(e).unwrap().stress = f32::INFINITY;
+        g.update_edge_list.push(EdgeIndex { row: 3, col: 3, ty: 0 });
+        let report = loop {
+            if let Some(r) = v.step(&g, 7) {
+                break r;
+            }
+        };
+        assert!(report.problems.contains(&Problem::NotANumber(e)));
+        assert!(report.problems.contains(&Problem::QueuedTwice(EdgeIndex { row: 3, col: 3, ty: 0 })));
+    }
+}
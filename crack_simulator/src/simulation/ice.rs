@@ -0,0 +1,431 @@
+//! The layers of ice and everything that happens to them during a show, without a window to draw them in.
+//! `SimulationScreen` draws them with OpenGL and `Windowless` with the software renderer. Both take the same
+//! commands and run the same frames.
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+
+use crate::{CRACK_STATS, FRAME, SIM_STATUS, TIMER};
+use crate::graphics::{bloom_mix, crack_color_at, FADE_FRAMES};
+use crate::graphics::layer::LayerSettings;
+use crate::graphics::vertex::Vertex;
+use super::graph::{BoundaryMode, Graph};
+use super::graph::events::CrackEvent;
+use super::graph::stats::CrackStats;
+use super::graph::validate::Validator;
+use super::mask::Mask;
+use super::timeline::{Rewind, RewindPlan, Timeline};
+use super::{Impact, Region, SimCommand, StressMapping};
+
+/// where `/snapshot/save` puts snapshots, relative to where the simulator is run from
+const SNAPSHOT_DIR: &str = "snapshots";
+/// frames between updates of the shared crack stats
+const STATS_INTERVAL: usize = 60;
+/// frames it takes to validate every row of a layer that asks for it
+const VALIDATE_INTERVAL: usize = 60 * 60;
+
+/// A sheet of ice
+pub struct IceLayer {
+    pub graph: Graph,
+    pub settings: LayerSettings,
+    /// crack triangles to draw, from the frames since they were last drawn
+    pub vertices: Vec<Vertex>,
+    validator: Validator,
+}
+
+pub struct Ice {
+    pub width: u32,
+    pub height: u32,
+    /// sheets of ice from the surface down
+    pub layers: Vec<IceLayer>,
+    /// the graphs jumped to a different state, so every crack has to be drawn again instead of just `vertices`
+    pub redraw: bool,
+    pub fade_amt: f32,
+    /// set over osc, replaces the color that changes over the show
+    color_override: Option<[f32; 4]>,
+    timeline: Timeline,
+    paused: bool,
+    stress_mapping: StressMapping,
+    ending: bool,
+
+    frames: usize,
+    fps: f32,
+    fps_time: Instant,
+}
+
+impl Ice {
+    /// `layers` are listed from the surface down. No layers => a single default layer.
+    /// Each layer gets its own seed, counting up from `seed`
+    pub fn new(width: u32, height: u32, boundary: BoundaryMode, mut layers: Vec<LayerSettings>, seed: Option<u64>) -> Self {
+        if layers.is_empty() {
+            layers.push(LayerSettings::default());
+        }
+        let mut layers: Vec<IceLayer> = layers.into_iter()
+            .enumerate()
+            .map(|(i, settings)| {
+                let t = Instant::now();
+                println!("Building graph...");
+                let mut graph = Graph::for_screen(width, height, boundary, seed.map(|s| s.wrapping_add(i as u64)));
+                graph.set_params(settings.params);
+                graph.update_graph_edge_stresses(None);
+                if settings.track_energy {
+                    graph.start_energy_ledger();
+                }
+                println!("Finished building graph in {} seconds", t.elapsed().as_secs_f32());
+                IceLayer { graph, settings, vertices: Vec::with_capacity(256), validator: Validator::default() }
+            })
+            .collect();
+        Self::keep_transmitted(&mut layers);
+
+        Self {
+            width,
+            height,
+            layers,
+            redraw: false,
+            fade_amt: 1.0,
+            color_override: None,
+            timeline: Timeline::new(),
+            paused: false,
+            stress_mapping: StressMapping::default(),
+            ending: false,
+
+            frames: 0,
+            fps: 0.0,
+            fps_time: Instant::now(),
+        }
+    }
+
+    /// How crack values turn into stress from now on
+    pub fn set_stress_mapping(&mut self, mapping: StressMapping) {
+        self.stress_mapping = mapping;
+    }
+
+    /// Remove the ice outside of `mask` from every layer
+    pub fn set_mask(&mut self, mask: &Mask) {
+        let (width, height) = (self.width, self.height);
+        // the graphs forget their keyframes
+        self.timeline.clear();
+        for layer in self.layers.iter_mut() {
+            layer.graph.apply_mask(|v| {
+                let [x, y] = v.to_pixel(width, height);
+                mask.contains(x, y, width, height)
+            });
+            // the stress in the removed edges is gone, so start counting again
+            if layer.settings.track_energy {
+                layer.graph.start_energy_ledger();
+            }
+        }
+    }
+
+    /// Crack the surface layer along polylines given in screen pixels before the show starts
+    pub fn seed_scratches(&mut self, polylines: &[Vec<[f32; 2]>]) {
+        let surface = &mut self.layers[0];
+        for line in polylines {
+            let ndcs: Vec<Vertex> = line.iter()
+                .map(|p| Vertex::from_pixel(*p, self.width, self.height))
+                .collect();
+            surface.graph.crack_path(&ndcs, Some(&mut surface.vertices));
+        }
+    }
+
+    /// Subscribe to the crack events of a layer. Layer 0 is the surface
+    pub fn subscribe(&mut self, layer: usize) -> Receiver<CrackEvent> {
+        self.layers[layer].graph.subscribe()
+    }
+
+    /// Measure how broken a layer is. Layer 0 is the surface
+    pub fn stats(&mut self, layer: usize) -> CrackStats {
+        let (width, height) = (self.width, self.height);
+        self.layers[layer].graph.stats(width, height)
+    }
+
+    /// Color of the cracks right now
+    pub fn crack_color(&self) -> [f32; 4] {
+        self.color_override
+            .unwrap_or_else(|| crack_color_at((*TIMER).read().unwrap().elapsed().as_secs_f32()))
+    }
+
+    /// How much bloom to mix into each layer. Busy and deep layers glow more
+    pub fn bloom_mixes(&self) -> Vec<f32> {
+        self.layers.iter()
+            .map(|l| bloom_mix(l.graph.get_update_amt()) + l.settings.depth_cue().1)
+            .collect()
+    }
+
+    /// Hit the surface. Each layer below gets its `coupling` share of the stress that reached the layer above it, and
+    /// keeps getting its share of the stress that moves along the cracks above it, see `couple_layers`.
+    /// Returns false if there's no ice where the impact is, or none could be found in its region
+    pub fn impact(&mut self, impact: Impact) -> bool {
+        let (width, height) = (self.width, self.height);
+        let surface = &mut self.layers[0].graph;
+        let edge_at = |g: &mut Graph, [x, y]: [f32; 2]| g.edge_near(Vertex::from_pixel([x * width as f32, y * height as f32], width, height));
+        let index = match (impact.position, impact.region) {
+            (Some(p), _) => edge_at(surface, p),
+            (None, Region::Anywhere) => surface.get_random_edge_index(),
+            // a few tries, in case the region is partly off the ice
+            (None, region) => (0..10).find_map(|_| {
+                let r = [surface.random_fraction(), surface.random_fraction()];
+                edge_at(surface, region.point(r, width as f32 / height as f32)?)
+            }),
+        };
+        let Some(index) = index else {
+            return false;
+        };
+        let mut stress = impact.stress;
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            if i != 0 {
+                stress *= layer.settings.coupling;
+            }
+            // the same spot may already be cracked in the other layers
+            if layer.graph.get_edge(index).is_some_and(|e| !e.cracked) {
+                match impact.direction {
+                    Some(d) => layer.graph.add_directed_stress(index, stress, d).unwrap(),
+                    None => layer.graph.add_stress(index, stress).unwrap(),
+                }
+            }
+        }
+        true
+    }
+
+    /// Every layer with one below it keeps the stress moving along its cracks for `couple_layers`
+    fn keep_transmitted(layers: &mut [IceLayer]) {
+        let n = layers.len();
+        for layer in &mut layers[..n - 1] {
+            layer.graph.keep_transmitted();
+        }
+    }
+
+    /// Each layer below the surface gets its `coupling` share of the stress that moved along the cracks of the
+    /// layer above it since the last frame
+    fn couple_layers(&mut self) {
+        for i in 1..self.layers.len() {
+            let (above, below) = self.layers.split_at_mut(i);
+            let below = &mut below[0];
+            below.graph.couple_to(&mut above[i - 1].graph, below.settings.coupling);
+        }
+    }
+
+    /// Set one of the `GraphParams` of one layer, or of every layer
+    pub fn set_param(&mut self, name: &str, value: f32, layer: Option<usize>) -> Result<(), Box<dyn Error>> {
+        if let Some(l) = layer.filter(|l| *l >= self.layers.len()) {
+            return Err(format!("there's no layer {}", l).into());
+        }
+        for (i, l) in self.layers.iter_mut().enumerate() {
+            if layer.map_or(true, |layer| layer == i) {
+                l.settings.params.set(name, value)?;
+                l.graph.set_params(l.settings.params);
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot_dir(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(format!("bad snapshot name: {:?}", name).into());
+        }
+        Ok(Path::new(SNAPSHOT_DIR).join(name))
+    }
+
+    /// Save the ice of every layer to `snapshots/<name>/`
+    pub fn save_snapshot(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        let dir = Self::snapshot_dir(name)?;
+        std::fs::create_dir_all(&dir)?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            layer.graph.save_snapshot(&dir.join(format!("layer_{}.snap", i)))?;
+        }
+        Ok(())
+    }
+
+    /// Load the ice of every layer from `snapshots/<name>/`. Resets and rewinds go back to this from now on
+    pub fn load_snapshot(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let dir = Self::snapshot_dir(name)?;
+        // load every layer before changing anything, so a bad snapshot leaves the ice alone
+        let mut graphs = Vec::with_capacity(self.layers.len());
+        for (i, layer) in self.layers.iter().enumerate() {
+            let graph = Graph::load_snapshot(&dir.join(format!("layer_{}.snap", i)), layer.graph.dimensions())
+                .map_err(|e| format!("layer {} of {}: {}", i, name, e))?;
+            graphs.push(graph);
+        }
+        for (layer, graph) in self.layers.iter_mut().zip(graphs) {
+            layer.graph = graph;
+            layer.settings.params = layer.graph.params();
+            if layer.settings.track_energy {
+                layer.graph.start_energy_ledger();
+            }
+        }
+        Self::keep_transmitted(&mut self.layers);
+        self.timeline.clear();
+        self.keyframe();
+        self.redraw = true;
+        Ok(())
+    }
+
+    /// Act on every command waiting in `commands`
+    pub fn handle_commands(&mut self, commands: &Mutex<VecDeque<SimCommand>>) {
+        let mut commands = commands.lock().unwrap();
+        while let Some(command) = commands.pop_front() {
+            self.handle_command(command);
+        }
+    }
+
+    pub fn handle_command(&mut self, command: SimCommand) {
+        match command {
+            SimCommand::Crack(v, region) => {
+                let post = self.stress_mapping.stress(v);
+                println!("stress amt: {}", post);
+                self.handle_command(SimCommand::Impact(Impact { position: None, region, stress: post, direction: None }));
+            }
+            SimCommand::Impact(impact) => {
+                if self.impact(impact) {
+                    self.timeline.record_impact(impact);
+                } else {
+                    println!("no ice to hit at {:?}", impact.position);
+                }
+            }
+            SimCommand::Stop => self.ending = true,
+            SimCommand::Rewind(r) => self.rewind(r),
+            SimCommand::Param { name, value, layer } => {
+                if let Err(e) = self.set_param(&name, value, layer) {
+                    println!("failed to set {}: {}", name, e);
+                }
+            }
+            SimCommand::Pause(p) => {
+                self.paused = p.unwrap_or(!self.paused);
+                println!("{}", if self.paused { "paused" } else { "unpaused" });
+            }
+            SimCommand::Reset => {
+                let t = Instant::now();
+                if let Some(plan) = self.timeline.reset() {
+                    self.apply_plan(&plan);
+                }
+                self.ending = false;
+                self.fade_amt = 1.0;
+                println!("reset in {} seconds", t.elapsed().as_secs_f32());
+            }
+            SimCommand::SaveSnapshot(name) => {
+                let t = Instant::now();
+                match self.save_snapshot(&name) {
+                    Ok(()) => println!("saved snapshot {} in {} seconds", name, t.elapsed().as_secs_f32()),
+                    Err(e) => println!("failed to save snapshot {}: {}", name, e),
+                }
+            }
+            SimCommand::LoadSnapshot(name) => {
+                let t = Instant::now();
+                match self.load_snapshot(&name) {
+                    Ok(()) => println!("loaded snapshot {} in {} seconds", name, t.elapsed().as_secs_f32()),
+                    Err(e) => println!("failed to load snapshot {}: {}", name, e),
+                }
+            }
+            SimCommand::Color([r, g, b]) => self.color_override = Some([r, g, b, 1.0]),
+            SimCommand::StressMapping(m) => {
+                println!("stress mapping: {:?}", m);
+                self.stress_mapping = m;
+            }
+        }
+    }
+
+    /// Take a keyframe of every layer so the simulation can be rewound to now
+    pub fn keyframe(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.graph.keyframe();
+        }
+        if let Some(k) = self.timeline.keyframe() {
+            for layer in self.layers.iter_mut() {
+                layer.graph.drop_keyframe(k);
+            }
+        }
+    }
+
+    /// Go back to the nearest keyframe and simulate forward to where the rewind should end up
+    fn rewind(&mut self, r: Rewind) {
+        let t = Instant::now();
+        let Some(plan) = self.timeline.rewind(r) else {
+            println!("nothing to rewind");
+            return;
+        };
+        self.apply_plan(&plan);
+        println!("rewound {:?} to {:.1} seconds in {} seconds", r, plan.to as f32 / 60_f32, t.elapsed().as_secs_f32());
+    }
+
+    fn apply_plan(&mut self, plan: &RewindPlan) {
+        for layer in self.layers.iter_mut() {
+            layer.graph.rewind_to_keyframe(plan.keyframe)
+                .expect("every layer has the same keyframes");
+        }
+        let mut impacts = plan.impacts.iter().peekable();
+        for frame in plan.from..plan.to {
+            while let Some((_, impact)) = impacts.next_if(|i| i.0 == frame) {
+                self.impact(*impact);
+            }
+            // the same updates as a frame, without the drawing
+            for layer in self.layers.iter_mut() {
+                if layer.graph.get_update_amt() != 0 {
+                    layer.graph.update_graph_edge_stresses(None);
+                }
+                layer.graph.update_graph_stress_propagation();
+            }
+            self.couple_layers();
+        }
+        self.redraw = true;
+    }
+
+    /// First half of a frame: fade out if the show's ending, and crack every edge with enough stress.
+    /// The new cracks are added to each layer's `vertices` to be drawn
+    pub fn update_stresses(&mut self) {
+        if self.ending {
+            self.fade_amt -= 1_f32 / FADE_FRAMES;
+            if self.fade_amt <= 0_f32 {
+                self.fade_amt = 0_f32;
+                self.ending = false;
+            }
+        }
+        if self.paused {
+            return;
+        }
+        for layer in self.layers.iter_mut() {
+            if layer.graph.get_update_amt() != 0 {
+                layer.graph.update_graph_edge_stresses(Some(&mut layer.vertices));
+            }
+        }
+    }
+
+    /// Second half of a frame, once the cracks have been drawn: move the stress along them, keep the timeline going
+    /// and publish how the simulation is doing
+    pub fn propagate(&mut self) {
+        if !self.paused {
+            for layer in self.layers.iter_mut() {
+                layer.graph.update_graph_stress_propagation();
+            }
+            self.couple_layers();
+        }
+
+        // publish how broken the surface is about once a second
+        self.frames += 1;
+        FRAME.store(self.frames, Ordering::Relaxed);
+        if !self.paused && self.timeline.advance() {
+            self.keyframe();
+        }
+        if self.frames % STATS_INTERVAL == 0 {
+            self.fps = STATS_INTERVAL as f32 / self.fps_time.elapsed().as_secs_f32();
+            self.fps_time = Instant::now();
+            *CRACK_STATS.write().unwrap() = self.stats(0);
+        }
+        let mut status = SIM_STATUS.write().unwrap();
+        status.paused = self.paused;
+        status.fps = self.fps;
+        status.queue_depth = self.layers.iter().map(|l| l.graph.get_update_amt()).sum();
+        drop(status);
+        // a few rows a frame, a whole graph is too slow to check between two frames
+        for (i, layer) in self.layers.iter_mut().enumerate().filter(|(_, l)| l.settings.validate) {
+            let rows = layer.graph.dimensions().0.div_ceil(VALIDATE_INTERVAL);
+            match layer.validator.step(&layer.graph, rows) {
+                Some(report) if !report.is_ok() => println!("layer {} has {} problems: {:?}", i, report.problem_count, report.problems),
+                _ => (),
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Describes where ice exists on the screen.
+/// Coordinates are in pixels with the origin in the top left corner of the screen.
+pub enum Mask {
+    /// A bitmap stretched over the whole screen. `true` => ice
+    Image {
+        width: usize,
+        height: usize,
+        data: Vec<bool>,
+    },
+    /// A set of closed polygons. Points are inside if they're inside an odd number of polygons
+    Polygons(Vec<Vec<[f32; 2]>>),
+}
+
+impl Mask {
+    /// Load a mask from a file.
+    /// * `.png` => opaque bright pixels are ice
+    /// * `.json` => a list of polygons, each a list of `[x, y]` pixel coordinates
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => Self::load_png(path),
+            Some("json") => {
+                let mut file = File::open(path)?;
+                let mut contents = String::new();
+                file.read_to_string(&mut contents)?;
+                let polygons: Vec<Vec<[f32; 2]>> = serde_json::from_str(&contents)?;
+                Ok(Self::Polygons(polygons))
+            }
+            _ => Err(format!("unsupported mask file: {:?}", path).into()),
+        }
+    }
+
+    fn load_png(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+
+        let channels = info.color_type.samples();
+        let mut data = Vec::with_capacity(info.width as usize * info.height as usize);
+        for row in buf.chunks(info.line_size).take(info.height as usize) {
+            for px in row.chunks(channels).take(info.width as usize) {
+                let (luma, alpha) = match px.len() {
+                    1 => (px[0], 255),
+                    2 => (px[0], px[1]),
+                    3 => (((px[0] as u16 + px[1] as u16 + px[2] as u16) / 3) as u8, 255),
+                    _ => (((px[0] as u16 + px[1] as u16 + px[2] as u16) / 3) as u8, px[3]),
+                };
+                data.push(luma > 127 && alpha > 127);
+            }
+        }
+
+        Ok(Self::Image {
+            width: info.width as usize,
+            height: info.height as usize,
+            data,
+        })
+    }
+
+    /// Returns true if the pixel `(x, y)` of a `screen_width` x `screen_height` screen is ice
+    pub fn contains(&self, x: f32, y: f32, screen_width: u32, screen_height: u32) -> bool {
+        match self {
+            Self::Image { width, height, data } => {
+                if x < 0_f32 || y < 0_f32 {
+                    return false;
+                }
+                let c = (x / screen_width as f32 * *width as f32) as usize;
+                let r = (y / screen_height as f32 * *height as f32) as usize;
+                if c >= *width || r >= *height {
+                    return false;
+                }
+                data[r * width + c]
+            }
+            Self::Polygons(polygons) => {
+                polygons.iter()
+                    .filter(|p| Self::polygon_contains(p, x, y))
+                    .count() % 2 == 1
+            }
+        }
+    }
+
+    /// even-odd ray casting test
+    fn polygon_contains(polygon: &[[f32; 2]], x: f32, y: f32) -> bool {
+        let mut inside = false;
+        let mut j = polygon.len().wrapping_sub(1);
+        for i in 0..polygon.len() {
+            let [xi, yi] = polygon[i];
+            let [xj, yj] = polygon[j];
+            if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygon_mask() {
+        let square = vec![[10_f32, 10_f32], [90_f32, 10_f32], [90_f32, 90_f32], [10_f32, 90_f32]];
+        let hole = vec![[40_f32, 40_f32], [60_f32, 40_f32], [60_f32, 60_f32], [40_f32, 60_f32]];
+        let mask = Mask::Polygons(vec![square, hole]);
+
+        assert!(mask.contains(20.0, 20.0, 100, 100));
+        assert!(!mask.contains(5.0, 50.0, 100, 100));
+        assert!(!mask.contains(50.0, 50.0, 100, 100));
+        assert!(mask.contains(80.0, 50.0, 100, 100));
+    }
+
+    #[test]
+    fn test_image_mask() {
+        let mask = Mask::Image {
+            width: 2,
+            height: 2,
+            data: vec![true, false, false, true],
+        };
+
+        assert!(mask.contains(10.0, 10.0, 100, 100));
+        assert!(!mask.contains(60.0, 10.0, 100, 100));
+        assert!(!mask.contains(10.0, 60.0, 100, 100));
+        assert!(mask.contains(60.0, 60.0, 100, 100));
+        assert!(!mask.contains(100.0, 100.0, 100, 100));
+    }
+}
@@ -1 +1,106 @@
-pub mod graph;
\ No newline at end of file
+use serde::{Deserialize, Serialize};
+
+use timeline::Rewind;
+
+pub mod graph;
+pub mod mask;
+pub mod scratches;
+pub mod timeline;
+pub mod ice;
+
+/// Part of the screen, in fractions of the width and height from the top left
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum Region {
+    #[default]
+    Anywhere,
+    /// within `radius` of `center`. `radius` is a fraction of the screen height
+    Around { center: [f32; 2], radius: f32 },
+    Rect { min: [f32; 2], max: [f32; 2] },
+}
+
+impl Region {
+    /// A point in the region picked with two random numbers from 0 to 1, on a screen `aspect` times wider than it is
+    /// tall. `None` for `Anywhere`
+    pub fn point(&self, r: [f32; 2], aspect: f32) -> Option<[f32; 2]> {
+        match *self {
+            Self::Anywhere => None,
+            Self::Around { center, radius } => {
+                let angle = r[0] * std::f32::consts::TAU;
+                let dist = radius * r[1].sqrt();
+                Some([
+                    (center[0] + dist * angle.cos() / aspect).clamp(0_f32, 1_f32),
+                    (center[1] + dist * angle.sin()).clamp(0_f32, 1_f32),
+                ])
+            }
+            Self::Rect { min, max } => Some([min[0] + r[0] * (max[0] - min[0]), min[1] + r[1] * (max[1] - min[1])]),
+        }
+    }
+}
+
+/// Stress hitting the surface
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Impact {
+    /// fraction of the width and height of the screen from the top left. Somewhere in `region` if left out
+    pub position: Option<[f32; 2]>,
+    /// where to hit if there's no `position`
+    #[serde(default)]
+    pub region: Region,
+    pub stress: f32,
+    /// screen direction, y down, for the crack to head in. Any direction if left out
+    pub direction: Option<[f32; 2]>,
+}
+
+/// How crack values from the watch and the console turn into stress
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct StressMapping {
+    /// crack value that gives no stress
+    pub floor: f32,
+    /// crack values this far over `floor` give `MAX_STRESS`
+    pub range: f32,
+    /// over 1 keeps soft hits small and lets hard ones through
+    pub exponent: f32,
+}
+
+impl StressMapping {
+    pub const MAX_STRESS: f32 = 10000.0;
+
+    pub fn stress(&self, crack_value: f32) -> f32 {
+        let pre = (crack_value - self.floor).clamp(0_f32, Self::MAX_STRESS);
+        (pre / self.range).powf(self.exponent) * Self::MAX_STRESS
+    }
+}
+
+impl Default for StressMapping {
+    fn default() -> Self {
+        Self {
+            floor: 500.0 - 31.0,
+            range: 500.0,
+            exponent: 2.5,
+        }
+    }
+}
+
+/// Something for the simulation to do, sent from the threads handling input
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SimCommand {
+    /// a crack value from the watch or the console, roughly 500 to 1000, and where it goes
+    Crack(f32, Region),
+    Impact(Impact),
+    /// fade the show out
+    Stop,
+    Rewind(Rewind),
+    /// set one of the `GraphParams` of a layer, or of every layer
+    Param { name: String, value: f32, layer: Option<usize> },
+    /// stop or restart the simulation. Toggles if there's no value
+    Pause(Option<bool>),
+    /// put the ice back the way it was when the simulation started
+    Reset,
+    SaveSnapshot(String),
+    LoadSnapshot(String),
+    /// use this crack color instead of the one that changes over the show
+    Color([f32; 3]),
+    StressMapping(StressMapping),
+}
@@ -0,0 +1,298 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// number of line segments used to approximate a bezier curve
+const CURVE_SEGMENTS: usize = 8;
+
+/// Load a list of polylines describing cracks that exist before the show starts.
+/// Coordinates are in pixels with the origin in the top left corner of the screen.
+/// * `.json` => a list of polylines, each a list of `[x, y]` points
+/// * `.svg` => every `<path>` and `<polyline>` in the file. Transforms are ignored
+pub fn load_scratches(path: &Path) -> Result<Vec<Vec<[f32; 2]>>, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => Ok(serde_json::from_str(&contents)?),
+        Some("svg") => parse_svg(&contents),
+        _ => Err(format!("unsupported scratch file: {:?}", path).into()),
+    }
+}
+
+fn parse_svg(contents: &str) -> Result<Vec<Vec<[f32; 2]>>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    for d in attribute_values(contents, "path", "d") {
+        out.append(&mut parse_path_data(d)?);
+    }
+    for points in attribute_values(contents, "polyline", "points") {
+        let nums = parse_numbers(points)?;
+        out.push(nums.chunks_exact(2).map(|p| [p[0], p[1]]).collect());
+    }
+    out.retain(|l| l.len() > 1);
+    Ok(out)
+}
+
+/// Returns the values of `attr` for every `<tag ...>` element
+fn attribute_values<'a>(contents: &'a str, tag: &str, attr: &str) -> Vec<&'a str> {
+    let mut out = Vec::new();
+    let open = format!("<{}", tag);
+    let mut rest = contents;
+    while let Some(start) = rest.find(open.as_str()) {
+        rest = &rest[start + open.len()..];
+        // make sure we didn't match the start of a longer tag name
+        if !rest.starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>') {
+            continue;
+        }
+        let element = &rest[..rest.find('>').unwrap_or(rest.len())];
+        let mut search = element;
+        while let Some(i) = search.find(attr) {
+            let preceded_by_space = search[..i].ends_with(|c: char| c.is_whitespace());
+            let after = search[i + attr.len()..].trim_start();
+            search = &search[i + attr.len()..];
+            if !preceded_by_space {
+                continue;
+            }
+            if let Some(after) = after.strip_prefix('=') {
+                let after = after.trim_start();
+                if let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                    let value = &after[1..];
+                    if let Some(end) = value.find(quote) {
+                        out.push(&value[..end]);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn parse_numbers(s: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    let mut tokens = PathTokens::new(s);
+    while let Some(t) = tokens.next_number()? {
+        out.push(t);
+    }
+    Ok(out)
+}
+
+/// Flatten svg path data into polylines. Supports the M, L, H, V, C, S, Q, T and Z commands
+fn parse_path_data(d: &str) -> Result<Vec<Vec<[f32; 2]>>, Box<dyn Error>> {
+    let mut out: Vec<Vec<[f32; 2]>> = Vec::new();
+    let mut cur: Vec<[f32; 2]> = Vec::new();
+    let mut tokens = PathTokens::new(d);
+    let mut pos = [0_f32; 2];
+    let mut start = [0_f32; 2];
+    // last control point, used by the smooth curve commands
+    let mut last_ctrl: Option<[f32; 2]> = None;
+    let mut cmd = None;
+
+    loop {
+        if let Some(c) = tokens.next_command() {
+            cmd = Some(c);
+        } else if tokens.at_end() {
+            break;
+        }
+        let c = cmd.ok_or("path data must start with a command")?;
+        let rel = c.is_ascii_lowercase();
+        let offset = |p: [f32; 2], pos: [f32; 2]| if rel { [p[0] + pos[0], p[1] + pos[1]] } else { p };
+        match c.to_ascii_uppercase() {
+            'M' => {
+                let p = offset(tokens.point()?, pos);
+                if cur.len() > 1 {
+                    out.push(std::mem::take(&mut cur));
+                }
+                cur = vec![p];
+                pos = p;
+                start = p;
+                // further coordinate pairs are implicit line-tos
+                cmd = Some(if rel { 'l' } else { 'L' });
+                last_ctrl = None;
+            }
+            'L' => {
+                pos = offset(tokens.point()?, pos);
+                cur.push(pos);
+                last_ctrl = None;
+            }
+            'H' => {
+                let x = tokens.number()?;
+                pos[0] = if rel { pos[0] + x } else { x };
+                cur.push(pos);
+                last_ctrl = None;
+            }
+            'V' => {
+                let y = tokens.number()?;
+                pos[1] = if rel { pos[1] + y } else { y };
+                cur.push(pos);
+                last_ctrl = None;
+            }
+            'C' | 'S' => {
+                let c1 = if c.eq_ignore_ascii_case(&'C') {
+                    offset(tokens.point()?, pos)
+                } else {
+                    reflect(last_ctrl, pos)
+                };
+                let c2 = offset(tokens.point()?, pos);
+                let end = offset(tokens.point()?, pos);
+                for i in 1..=CURVE_SEGMENTS {
+                    let t = i as f32 / CURVE_SEGMENTS as f32;
+                    let u = 1_f32 - t;
+                    cur.push([
+                        u * u * u * pos[0] + 3_f32 * u * u * t * c1[0] + 3_f32 * u * t * t * c2[0] + t * t * t * end[0],
+                        u * u * u * pos[1] + 3_f32 * u * u * t * c1[1] + 3_f32 * u * t * t * c2[1] + t * t * t * end[1],
+                    ]);
+                }
+                last_ctrl = Some(c2);
+                pos = end;
+            }
+            'Q' | 'T' => {
+                let c1 = if c.eq_ignore_ascii_case(&'Q') {
+                    offset(tokens.point()?, pos)
+                } else {
+                    reflect(last_ctrl, pos)
+                };
+                let end = offset(tokens.point()?, pos);
+                for i in 1..=CURVE_SEGMENTS {
+                    let t = i as f32 / CURVE_SEGMENTS as f32;
+                    let u = 1_f32 - t;
+                    cur.push([
+                        u * u * pos[0] + 2_f32 * u * t * c1[0] + t * t * end[0],
+                        u * u * pos[1] + 2_f32 * u * t * c1[1] + t * t * end[1],
+                    ]);
+                }
+                last_ctrl = Some(c1);
+                pos = end;
+            }
+            'Z' => {
+                cur.push(start);
+                pos = start;
+                cmd = None;
+                last_ctrl = None;
+                if tokens.at_end() {
+                    break;
+                }
+            }
+            _ => return Err(format!("unsupported path command: {}", c).into()),
+        }
+    }
+    if cur.len() > 1 {
+        out.push(cur);
+    }
+    Ok(out)
+}
+
+/// reflect the last control point about the current position
+fn reflect(ctrl: Option<[f32; 2]>, pos: [f32; 2]) -> [f32; 2] {
+    match ctrl {
+        Some(c) => [2_f32 * pos[0] - c[0], 2_f32 * pos[1] - c[1]],
+        None => pos,
+    }
+}
+
+struct PathTokens<'a> {
+    s: &'a [u8],
+    i: usize,
+}
+
+impl<'a> PathTokens<'a> {
+    fn new(s: &'a str) -> Self {
+        Self {
+            s: s.as_bytes(),
+            i: 0,
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while self.i < self.s.len() && (self.s[self.i].is_ascii_whitespace() || self.s[self.i] == b',') {
+            self.i += 1;
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_separators();
+        self.i >= self.s.len()
+    }
+
+    fn next_command(&mut self) -> Option<char> {
+        self.skip_separators();
+        match self.s.get(self.i) {
+            Some(c) if c.is_ascii_alphabetic() && *c != b'e' && *c != b'E' => {
+                self.i += 1;
+                Some(*c as char)
+            }
+            _ => None,
+        }
+    }
+
+    fn next_number(&mut self) -> Result<Option<f32>, Box<dyn Error>> {
+        self.skip_separators();
+        let start = self.i;
+        if matches!(self.s.get(self.i), Some(b'-') | Some(b'+')) {
+            self.i += 1;
+        }
+        let mut seen_dot = false;
+        while let Some(c) = self.s.get(self.i) {
+            match c {
+                b'0'..=b'9' => self.i += 1,
+                b'.' if !seen_dot => {
+                    seen_dot = true;
+                    self.i += 1;
+                }
+                b'e' | b'E' => {
+                    self.i += 1;
+                    if matches!(self.s.get(self.i), Some(b'-') | Some(b'+')) {
+                        self.i += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        if start == self.i {
+            return Ok(None);
+        }
+        let token = std::str::from_utf8(&self.s[start..self.i])?;
+        Ok(Some(token.parse::<f32>().map_err(|e| format!("bad number {:?}: {}", token, e))?))
+    }
+
+    fn number(&mut self) -> Result<f32, Box<dyn Error>> {
+        self.next_number()?.ok_or_else(|| "expected a number in path data".into())
+    }
+
+    fn point(&mut self) -> Result<[f32; 2], Box<dyn Error>> {
+        Ok([self.number()?, self.number()?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_path_data() {
+        let lines = parse_path_data("M10,10 L20 10 h5 v-5 M0 0 l1-1 1 1 z").unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], vec![[10.0, 10.0], [20.0, 10.0], [25.0, 10.0], [25.0, 5.0]]);
+        assert_eq!(lines[1], vec![[0.0, 0.0], [1.0, -1.0], [2.0, 0.0], [0.0, 0.0]]);
+
+        let curve = parse_path_data("M0 0 C0 10 10 10 10 0").unwrap();
+        assert_eq!(curve[0].len(), CURVE_SEGMENTS + 1);
+        assert_eq!(*curve[0].last().unwrap(), [10.0, 0.0]);
+        assert!(parse_path_data("10 10").is_err());
+    }
+
+    #[test]
+    fn test_parse_svg() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <path id="a" d="M 1 2 L 3 4"/>
+            <polyline points="0,0 5,5 10,0" />
+            <pathology d="M 0 0 L 1 1"/>
+        </svg>"#;
+        let lines = parse_svg(svg).unwrap();
+        assert_eq!(lines, vec![
+            vec![[1.0, 2.0], [3.0, 4.0]],
+            vec![[0.0, 0.0], [5.0, 5.0], [10.0, 0.0]],
+        ]);
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::Impact;
+
+/// How far back to take the simulation
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rewind {
+    /// undo the last n impacts
+    Impacts(usize),
+    Seconds(f32),
+}
+
+/// How to carry out a rewind: go back to a keyframe, then simulate forward again
+#[derive(Clone, Debug, PartialEq)]
+pub struct RewindPlan {
+    /// keyframe to restore, counting from the oldest one kept
+    pub keyframe: usize,
+    /// frame the keyframe was taken at
+    pub from: usize,
+    /// frame to simulate up to
+    pub to: usize,
+    /// impacts to add again on the way, with the frame they were added after
+    pub impacts: Vec<(usize, Impact)>,
+}
+
+/// Keeps track of what the simulation was given when, so it can be rewound
+pub struct Timeline {
+    /// frames simulated. Goes back when the simulation is rewound
+    pub frame: usize,
+    /// impacts along with the frame they were added after, oldest first
+    impacts: Vec<(usize, Impact)>,
+    /// frames that graph keyframes were taken at, oldest first. Every layer has a keyframe for each of these.
+    /// The first one is kept for resetting to
+    keyframes: VecDeque<usize>,
+    /// true once keyframes after the first one have been dropped. Rewinds can't go back past the gap
+    gap: bool,
+}
+
+impl Timeline {
+    /// frames between keyframes
+    pub const KEYFRAME_INTERVAL: usize = 60 * 5;
+    /// enough to rewind to the start of the show
+    pub const MAX_KEYFRAMES: usize = 60;
+
+    pub fn new() -> Self {
+        Self {
+            frame: 0,
+            impacts: Vec::new(),
+            keyframes: VecDeque::new(),
+            gap: false,
+        }
+    }
+
+    pub fn record_impact(&mut self, impact: Impact) {
+        self.impacts.push((self.frame, impact));
+    }
+
+    /// Move on a frame. Returns true if a keyframe should be taken
+    pub fn advance(&mut self) -> bool {
+        self.frame += 1;
+        self.frame % Self::KEYFRAME_INTERVAL == 0
+    }
+
+    /// Note that every graph took a keyframe. Returns a keyframe the graphs should drop if there are too many
+    pub fn keyframe(&mut self) -> Option<usize> {
+        self.keyframes.push_back(self.frame);
+        if self.keyframes.len() <= Self::MAX_KEYFRAMES {
+            return None;
+        }
+        // keep the first keyframe for resets
+        self.keyframes.remove(1);
+        self.gap = true;
+        // impacts before the oldest keyframe that can be rewound to can't be undone any more
+        let oldest = self.keyframes[1];
+        self.impacts.retain(|i| i.0 >= oldest);
+        Some(1)
+    }
+
+    /// Forget everything, for when the graphs forget their keyframes
+    pub fn clear(&mut self) {
+        self.impacts.clear();
+        self.keyframes.clear();
+        self.gap = false;
+    }
+
+    /// Go back to the first keyframe, forgetting everything since. Returns `None` if there are no keyframes
+    pub fn reset(&mut self) -> Option<RewindPlan> {
+        let first = *self.keyframes.front()?;
+        self.clear();
+        self.keyframes.push_back(first);
+        self.frame = first;
+        Some(RewindPlan {
+            keyframe: 0,
+            from: first,
+            to: first,
+            impacts: Vec::new(),
+        })
+    }
+
+    /// Work out how to carry out a rewind and forget everything after the frame it goes back to.
+    /// Can't go back further than the oldest keyframe. Returns `None` if there's nothing to go back to
+    pub fn rewind(&mut self, r: Rewind) -> Option<RewindPlan> {
+        let oldest = *self.keyframes.get(if self.gap { 1 } else { 0 })?;
+        let to = match r {
+            Rewind::Impacts(0) => return None,
+            Rewind::Impacts(n) => self.impacts.get(self.impacts.len().saturating_sub(n))?.0,
+            Rewind::Seconds(s) => self.frame.saturating_sub((s.max(0_f32) * 60_f32) as usize),
+        }
+        .max(oldest);
+
+        let keyframe = self.keyframes.iter().rposition(|k| *k <= to).unwrap();
+        self.keyframes.truncate(keyframe + 1);
+        let from = self.keyframes[keyframe];
+        self.impacts.truncate(self.impacts.partition_point(|i| i.0 < to));
+        let first = self.impacts.partition_point(|i| i.0 < from);
+        self.frame = to;
+        Some(RewindPlan {
+            keyframe,
+            from,
+            to,
+            impacts: self.impacts[first..].to_vec(),
+        })
+    }
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn impact(f: usize) -> Impact {
+        Impact { position: None, region: Default::default(), stress: f as f32, direction: None }
+    }
+
+    #[test]
+    fn test_timeline_rewind() {
+        let mut t = Timeline::new();
+        assert_eq!(t.rewind(Rewind::Seconds(1.0)), None);
+        t.keyframe();
+        for f in 0..1000 {
+            if f % 100 == 50 {
+                t.record_impact(impact(f));
+            }
+            if t.advance() {
+                t.keyframe();
+            }
+        }
+        // keyframes at 0, 300, 600 and 900, impacts at 50, 150 ... 950
+        let plan = t.rewind(Rewind::Impacts(2)).unwrap();
+        assert_eq!(plan, RewindPlan { keyframe: 2, from: 600, to: 850, impacts: vec![(650, impact(650)), (750, impact(750))] });
+        assert_eq!(t.frame, 850);
+
+        let plan = t.rewind(Rewind::Seconds(10.0)).unwrap();
+        assert_eq!(plan, RewindPlan { keyframe: 0, from: 0, to: 250, impacts: vec![(50, impact(50)), (150, impact(150))] });
+        assert_eq!(t.rewind(Rewind::Impacts(0)), None);
+        assert_eq!(t.rewind(Rewind::Impacts(100)).unwrap().to, 50);
+    }
+
+    #[test]
+    fn test_timeline_keyframe_limit() {
+        let mut t = Timeline::new();
+        t.keyframe();
+        let mut dropped = 0;
+        for f in 0..Timeline::KEYFRAME_INTERVAL * (Timeline::MAX_KEYFRAMES + 5) {
+            t.record_impact(impact(f));
+            if t.advance() && t.keyframe().is_some() {
+                dropped += 1;
+            }
+        }
+        assert_eq!(dropped, 6);
+        // can't rewind into the gap, but can still reset to the start
+        let oldest = 7 * Timeline::KEYFRAME_INTERVAL;
+        assert_eq!(t.rewind(Rewind::Seconds(1e6)).unwrap().from, oldest);
+        assert_eq!(t.rewind(Rewind::Impacts(1)), None);
+        assert_eq!(t.reset().unwrap(), RewindPlan { keyframe: 0, from: 0, to: 0, impacts: Vec::new() });
+        assert_eq!(t.frame, 0);
+    }
+}
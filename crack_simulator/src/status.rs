@@ -0,0 +1,55 @@
+use rosc::{OscMessage, OscType};
+use serde::Serialize;
+
+/// What the show is doing, as sent in reply to `/status` and to status subscribers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Status {
+    /// the show has started and hasn't been stopped
+    pub running: bool,
+    pub paused: bool,
+    /// seconds since the show started. 0 before it starts
+    pub elapsed: f32,
+    /// percent of the surface's edges that are cracked, as of the last stats update
+    pub cracked_percent: f32,
+    /// frames drawn a second, averaged over the last second
+    pub fps: f32,
+    /// edges waiting to be updated, across every layer
+    pub queue_depth: usize,
+    /// commands waiting for the simulation to pick them up
+    pub pending_commands: usize,
+}
+
+impl Status {
+    /// `/status running paused elapsed cracked_percent fps queue_depth pending_commands`, with the bools as 0 or 1
+    pub fn to_message(&self) -> OscMessage {
+        OscMessage {
+            addr: "/status".to_string(),
+            args: vec![
+                OscType::Int(self.running as i32),
+                OscType::Int(self.paused as i32),
+                OscType::Float(self.elapsed),
+                OscType::Float(self.cracked_percent),
+                OscType::Float(self.fps),
+                OscType::Int(self.queue_depth.min(i32::MAX as usize) as i32),
+                OscType::Int(self.pending_commands.min(i32::MAX as usize) as i32),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_message() {
+        let status = Status { running: true, paused: false, elapsed: 12.5, cracked_percent: 3.0, fps: 59.5, queue_depth: 40, pending_commands: 1 };
+        let msg = status.to_message();
+        assert_eq!(msg.addr, "/status");
+        assert_eq!(msg.args, vec![OscType::Int(1), OscType::Int(0), OscType::Float(12.5), OscType::Float(3.0),
+            OscType::Float(59.5), OscType::Int(40), OscType::Int(1)]);
+        // survives the trip through the encoder
+        let buf = rosc::encoder::encode(&rosc::OscPacket::Message(msg.clone())).unwrap();
+        assert_eq!(rosc::decoder::decode_udp(&buf).unwrap().1, rosc::OscPacket::Message(msg));
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use rosc::{OscMessage, OscType};
+use serde::{Deserialize, Serialize};
+
+use crate::net::{NetError, Remote, UdpSender};
+use crate::tcp::{TcpSender, Transport};
+
+/// Somewhere to send show messages to, like the audio rig or a lighting desk, and how to change them for the gear there
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct TargetSettings {
+    pub ip: String,
+    pub port: u16,
+    /// `tcp` for gear that can take it, so cues like `/stop` can't go missing
+    pub transport: Transport,
+    /// messages to send under a different address, like `{"/crack": "/lights/flash"}`. Addresses mapped to null aren't sent
+    pub addresses: HashMap<String, Option<String>>,
+    /// how to scale the numbers sent with each address, keyed by the address before it's remapped
+    pub scale: HashMap<String, Scale>,
+}
+
+/// Maps numbers from one range onto another. Numbers outside `from` are clamped
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Scale {
+    pub from: [f32; 2],
+    pub to: [f32; 2],
+    /// send whole numbers, for gear that only takes ints
+    #[serde(default)]
+    pub int: bool,
+}
+
+impl Scale {
+    pub fn apply(&self, v: f32) -> f32 {
+        let [a, b] = self.from;
+        if a == b {
+            return self.to[0];
+        }
+        let t = ((v - a) / (b - a)).clamp(0_f32, 1_f32);
+        self.to[0] + t * (self.to[1] - self.to[0])
+    }
+
+    fn apply_arg(&self, arg: &OscType) -> OscType {
+        let v = match arg {
+            OscType::Float(f) => *f,
+            OscType::Double(d) => *d as f32,
+            OscType::Int(i) => *i as f32,
+            OscType::Long(l) => *l as f32,
+            a => return a.clone(),
+        };
+        let v = self.apply(v);
+        if self.int {
+            OscType::Int(v.round() as i32)
+        } else {
+            OscType::Float(v)
+        }
+    }
+}
+
+/// A destination ready to send to
+#[derive(Debug)]
+pub struct Target {
+    settings: TargetSettings,
+    link: Link,
+}
+
+#[derive(Debug)]
+enum Link {
+    Udp(Remote),
+    Tcp(TcpSender),
+}
+
+impl Target {
+    /// `ip` can be an ipv4 or ipv6 address, a multicast group, or a hostname to look up when it's first sent to
+    pub fn new(settings: TargetSettings) -> Result<Self, Box<dyn Error>> {
+        let host = settings.ip.trim();
+        if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == '/') {
+            return Err(format!("bad target ip {:?}", settings.ip).into());
+        }
+        let remote = Remote::new(host, settings.port);
+        Ok(Self {
+            link: match settings.transport {
+                Transport::Udp => Link::Udp(remote),
+                Transport::Tcp => Link::Tcp(TcpSender::new(remote)),
+            },
+            settings,
+        })
+    }
+
+    pub fn remote(&self) -> &Remote {
+        match &self.link {
+            Link::Udp(r) => r,
+            Link::Tcp(t) => &t.remote,
+        }
+    }
+
+    /// Send an encoded packet the way the target wants it. `udp` is used unless it wants tcp
+    pub fn send(&self, udp: &UdpSender, packet: &[u8]) -> Result<(), NetError> {
+        match &self.link {
+            Link::Tcp(tcp) => tcp.send(packet),
+            Link::Udp(remote) => {
+                let sent = udp.send_to(packet, remote.addr()?);
+                if sent.is_err() {
+                    // a hostname might have moved to another address
+                    remote.forget();
+                }
+                sent
+            }
+        }
+    }
+
+    /// The message as this target wants it. `None` if it doesn't want it at all
+    pub fn map(&self, msg: &OscMessage) -> Option<OscMessage> {
+        let addr = match self.settings.addresses.get(&msg.addr) {
+            Some(Some(a)) => a.clone(),
+            Some(None) => return None,
+            None => msg.addr.clone(),
+        };
+        let args = match self.settings.scale.get(&msg.addr) {
+            Some(s) => msg.args.iter().map(|a| s.apply_arg(a)).collect(),
+            None => msg.args.clone(),
+        };
+        Some(OscMessage { addr, args })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_map() {
+        let settings: TargetSettings = serde_json::from_str(r#"{
+            "ip": "127.0.0.1", "port": 9000,
+            "addresses": { "/crack": "/lights/flash", "/four": null },
+            "scale": { "/crack": { "from": [500, 1000], "to": [0, 255], "int": true }, "/start": { "from": [0, 1], "to": [10, 20] } }
+        }"#).unwrap();
+        let target = Target::new(settings).unwrap();
+        assert_eq!(target.remote().addr().unwrap(), "127.0.0.1:9000".parse().unwrap());
+
+        let msg = |addr: &str, args| OscMessage { addr: addr.to_string(), args };
+        assert_eq!(target.map(&msg("/crack", vec![OscType::Float(750.0)])), Some(msg("/lights/flash", vec![OscType::Int(128)])));
+        // clamped
+        assert_eq!(target.map(&msg("/crack", vec![OscType::Float(2000.0)])), Some(msg("/lights/flash", vec![OscType::Int(255)])));
+        assert_eq!(target.map(&msg("/start", vec![OscType::Int(0), OscType::String("a".into())])),
+            Some(msg("/start", vec![OscType::Float(10.0), OscType::String("a".into())])));
+        assert_eq!(target.map(&msg("/stop", vec![OscType::Int(0)])), Some(msg("/stop", vec![OscType::Int(0)])));
+        assert_eq!(target.map(&msg("/four", vec![])), None);
+
+        assert!(Target::new(TargetSettings { ip: "".into(), ..Default::default() }).is_err());
+        assert!(Target::new(TargetSettings { ip: "no pe".into(), ..Default::default() }).is_err());
+        // looked up when it's needed
+        let target = Target::new(TargetSettings { ip: "localhost".into(), port: 9000, ..Default::default() }).unwrap();
+        assert!(target.remote().addr().unwrap().ip().is_loopback());
+        let target = Target::new(TargetSettings { ip: "::1".into(), port: 9000, ..Default::default() }).unwrap();
+        assert_eq!(target.remote().addr().unwrap(), "[::1]:9000".parse().unwrap());
+    }
+
+    #[test]
+    fn test_target_send() {
+        use crate::tcp::SlipDecoder;
+        use std::io::Read;
+        use std::net::{TcpListener, UdpSocket};
+
+        let udp = UdpSender::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = |port, transport| Target::new(TargetSettings { ip: "127.0.0.1".into(), port, transport, ..Default::default() }).unwrap();
+
+        target(receiver.local_addr().unwrap().port(), Transport::Udp).send(&udp, b"udp").unwrap();
+        let mut buf = [0u8; 16];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"udp");
+
+        target(listener.local_addr().unwrap().port(), Transport::Tcp).send(&udp, b"tcp").unwrap();
+        let n = listener.accept().unwrap().0.read(&mut buf).unwrap();
+        assert_eq!(SlipDecoder::new().push(&buf[..n]), vec![b"tcp".to_vec()]);
+    }
+}
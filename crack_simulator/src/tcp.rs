@@ -0,0 +1,306 @@
+//! OSC 1.1 over TCP, for messages that mustn't go missing on a busy network.
+//!
+//! Packets are framed with SLIP: each one goes between two `END` bytes, with any `END` or `ESC` bytes inside it
+//! escaped. Frames longer than `MAX_FRAME` are thrown away.
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::net::{Backoff, NetError, Remote};
+
+const END: u8 = 0xc0;
+const ESC: u8 = 0xdb;
+const ESC_END: u8 = 0xdc;
+const ESC_ESC: u8 = 0xdd;
+
+/// how long to wait for a target to take a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+/// longest a write can take before the other end is given up on. Sends happen with locks held, so it's short
+pub const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+/// connections that send nothing for this long are closed
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// longest packet taken, in bytes. Far more than osc is ever sent in
+const MAX_FRAME: usize = rosc::decoder::MTU * 8;
+
+/// How OSC gets to or from somewhere
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    #[default]
+    Udp,
+    /// SLIP framed, see the module docs
+    Tcp,
+}
+
+/// Frame a packet for sending
+pub fn encode(packet: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(packet.len() + 2);
+    out.push(END);
+    for &b in packet {
+        match b {
+            END => out.extend([ESC, ESC_END]),
+            ESC => out.extend([ESC, ESC_ESC]),
+            b => out.push(b),
+        }
+    }
+    out.push(END);
+    out
+}
+
+/// Pulls packets out of a stream of SLIP framed bytes
+#[derive(Default)]
+pub struct SlipDecoder {
+    frame: Vec<u8>,
+    escaped: bool,
+    /// the frame got too long, skip to the next `END`
+    discarding: bool,
+}
+
+impl SlipDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in bytes as they arrive. Returns every packet they finish
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        for &b in bytes {
+            if self.discarding {
+                self.discarding = b != END;
+                continue;
+            }
+            if self.escaped {
+                self.escaped = false;
+                self.frame.push(match b {
+                    ESC_END => END,
+                    ESC_ESC => ESC,
+                    // not allowed, but the byte is the best guess at what was meant
+                    b => b,
+                });
+            } else {
+                match b {
+                    END if !self.frame.is_empty() => packets.push(std::mem::take(&mut self.frame)),
+                    END => (),
+                    ESC => self.escaped = true,
+                    b => self.frame.push(b),
+                }
+            }
+            if self.frame.len() > MAX_FRAME {
+                self.frame = Vec::new();
+                self.discarding = true;
+            }
+        }
+        packets
+    }
+}
+
+/// Call `f` with every packet that comes in on `stream` until it's closed, or nothing comes in for `idle`
+pub fn read_packets(mut stream: TcpStream, idle: Duration, mut f: impl FnMut(Vec<u8>)) -> io::Result<()> {
+    stream.set_read_timeout(Some(idle))?;
+    let mut decoder = SlipDecoder::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match stream.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if is_timeout(&e) => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, format!("nothing sent for {} seconds", idle.as_secs())));
+            }
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            return Ok(());
+        }
+        for packet in decoder.push(&buf[..n]) {
+            f(packet);
+        }
+    }
+}
+
+/// A connection to a target, made when it's first needed and made again whenever it drops. A target that can't be
+/// reached isn't tried again until its back-off is over, and hostnames are looked up again each time
+#[derive(Debug)]
+pub struct TcpSender {
+    pub remote: Remote,
+    connection: Mutex<(Option<(TcpStream, SocketAddr)>, Backoff)>,
+}
+
+impl TcpSender {
+    pub fn new(remote: Remote) -> Self {
+        Self {
+            remote,
+            connection: Mutex::new((None, Backoff::default())),
+        }
+    }
+
+    /// Send a packet, connecting first if there's no connection or the other end has closed it.
+    /// A target too slow to take the packet within `WRITE_TIMEOUT` is disconnected and backed off from
+    pub fn send(&self, packet: &[u8]) -> Result<(), NetError> {
+        let frame = encode(packet);
+        let mut connection = self.connection.lock().unwrap();
+        let (stream, backoff) = &mut *connection;
+        let now = Instant::now();
+        if let Some((s, to)) = stream.as_mut().filter(|(s, _)| !is_closed(s)) {
+            match s.write_all(&frame) {
+                Ok(()) => return Ok(()),
+                // part of the frame may have gone, so the connection can't be used again
+                Err(source) if is_timeout(&source) => {
+                    let to = *to;
+                    *stream = None;
+                    backoff.failed(now);
+                    return Err(NetError::Send { to, source });
+                }
+                Err(_) => (),
+            }
+        }
+        *stream = None;
+
+        let retry_in = backoff.wait(now);
+        if !retry_in.is_zero() {
+            return Err(NetError::Backoff { to: self.remote.to_string(), retry_in });
+        }
+        let addr = self.remote.addr()?;
+        let mut s = match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(s) => s,
+            Err(source) => {
+                backoff.failed(now);
+                self.remote.forget();
+                return Err(NetError::Connect { to: addr, source });
+            }
+        };
+        backoff.succeeded();
+        s.set_nodelay(true)
+            .and_then(|_| s.set_write_timeout(Some(WRITE_TIMEOUT)))
+            .and_then(|_| s.write_all(&frame))
+            .map_err(|source| NetError::Send { to: addr, source })?;
+        *stream = Some((s, addr));
+        Ok(())
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Whether the other end has hung up. Writes to a closed connection can look like they worked, so this is checked
+/// before sending
+fn is_closed(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let closed = match stream.peek(&mut [0u8]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != io::ErrorKind::WouldBlock,
+    };
+    closed || stream.set_nonblocking(false).is_err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_slip() {
+        let packet = [1, END, 2, ESC, 3];
+        let framed = encode(&packet);
+        assert_eq!(framed, vec![END, 1, ESC, ESC_END, 2, ESC, ESC_ESC, 3, END]);
+
+        let mut d = SlipDecoder::new();
+        // split part way through an escape, and two packets in one read
+        assert!(d.push(&framed[..3]).is_empty());
+        let mut rest = framed[3..].to_vec();
+        rest.extend(encode(b"abc"));
+        assert_eq!(d.push(&rest), vec![packet.to_vec(), b"abc".to_vec()]);
+        // single END framing works too
+        assert_eq!(d.push(&[b'x', END, b'y', END]), vec![b"x".to_vec(), b"y".to_vec()]);
+
+        // a frame that never ends is dropped, and the next one still comes through
+        assert!(d.push(&vec![b'z'; MAX_FRAME * 3]).is_empty());
+        assert!(d.frame.len() <= MAX_FRAME);
+        assert_eq!(d.push(&encode(b"after")), vec![b"after".to_vec()]);
+    }
+
+    #[test]
+    fn test_read_idle() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = listener.accept().unwrap().0;
+        let e = read_packets(stream, Duration::from_millis(50), |_| ()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_tcp_sender() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpSender::new(listener.local_addr().unwrap().into());
+        let read = |stream: TcpStream, count: usize| {
+            let mut packets = Vec::new();
+            let mut decoder = SlipDecoder::new();
+            let mut stream = stream;
+            let mut buf = [0u8; 64];
+            while packets.len() < count {
+                let n = stream.read(&mut buf).unwrap();
+                packets.extend(decoder.push(&buf[..n]));
+            }
+            (stream, packets)
+        };
+
+        sender.send(b"one").unwrap();
+        sender.send(b"two").unwrap();
+        let (stream, packets) = read(listener.accept().unwrap().0, 2);
+        assert_eq!(packets, vec![b"one".to_vec(), b"two".to_vec()]);
+
+        // the other end goes away and comes back
+        drop(stream);
+        std::thread::sleep(Duration::from_millis(50));
+        sender.send(b"three").unwrap();
+        let (_, packets) = read(listener.accept().unwrap().0, 1);
+        assert_eq!(packets, vec![b"three".to_vec()]);
+    }
+
+    #[test]
+    fn test_tcp_sender_stalled() {
+        // a target that takes the connection but never reads
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = TcpSender::new(listener.local_addr().unwrap().into());
+        let packet = vec![0u8; 64 * 1024];
+        let mut result = Ok(());
+        for _ in 0..10_000 {
+            let t = Instant::now();
+            result = sender.send(&packet);
+            assert!(t.elapsed() < WRITE_TIMEOUT * 5);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(matches!(result, Err(NetError::Send { .. })), "{:?}", result);
+        // given up on for a while
+        assert!(matches!(sender.send(b"one"), Err(NetError::Backoff { .. })));
+        drop(listener);
+    }
+
+    #[test]
+    fn test_tcp_sender_backoff() {
+        // the audio machine is down
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let sender = TcpSender::new(addr.into());
+        assert!(matches!(sender.send(b"one"), Err(NetError::Connect { .. })));
+        // not tried again straight away
+        assert!(matches!(sender.send(b"two"), Err(NetError::Backoff { .. })));
+
+        // it comes back
+        let listener = TcpListener::bind(addr).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        sender.send(b"three").unwrap();
+        let mut stream = listener.accept().unwrap().0;
+        let mut buf = [0u8; 16];
+        let n = stream.read(&mut buf).unwrap();
+        assert_eq!(SlipDecoder::new().push(&buf[..n]), vec![b"three".to_vec()]);
+    }
+}